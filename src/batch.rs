@@ -0,0 +1,418 @@
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use glium::{
+    backend::Facade,
+    texture::{MipmapsOption, RawImage2d, UncompressedFloatFormat},
+    Texture2d,
+};
+
+use crate::{
+    calibration::DisplayCalibration,
+    color_space::WorkingSpace,
+    csf::Csf,
+    fft::AllocationError,
+    precision::{read_rgba_f32, Precision},
+    processor::{AdaptParams, Processor},
+    profiler::GpuProfiler,
+    tiled::{self, Pixel, TileConfig, TileError},
+    transfer::{TransferFunction, TransferSettings},
+    viewing::Viewing,
+};
+
+/// File format of the adapted images.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StillFormat {
+    /// 16 bits per channel in the output transfer function
+    Png,
+    /// 16 bits per channel in the output transfer function
+    Tiff,
+    /// Linear 32-bit floats relative to SDR white, neither tone mapped nor clipped
+    Exr,
+}
+
+impl FromStr for StillFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "png" => Ok(StillFormat::Png),
+            "tiff" => Ok(StillFormat::Tiff),
+            "exr" => Ok(StillFormat::Exr),
+            _ => Err(format!("Unknown image format: {}", s)),
+        }
+    }
+}
+
+impl StillFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            StillFormat::Png => "png",
+            StillFormat::Tiff => "tiff",
+            StillFormat::Exr => "exr",
+        }
+    }
+}
+
+/// Images adapted one after another into a directory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchJob {
+    /// Paths whose file names may contain `*` and `?`
+    pub inputs: Vec<String>,
+    pub output_dir: PathBuf,
+    pub format: StillFormat,
+    pub viewing: Viewing,
+    pub csf: Csf,
+    pub precision: Precision,
+    pub transfer: TransferSettings,
+    pub working_space: WorkingSpace,
+    pub calibration: DisplayCalibration,
+}
+
+#[derive(Debug)]
+pub enum BatchError {
+    Io(io::Error),
+    Image(image::ImageError),
+    Unsupported(String),
+    /// An image too large for a single transform failed while it was adapted in tiles
+    Tiled(TileError),
+    /// Outputs that would overwrite an input or each other; nothing is written
+    Conflict(String),
+    /// Number of images that could not be processed, each reported as it failed
+    Failed(usize),
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BatchError::Io(err) => write!(f, "I/O error: {}", err),
+            BatchError::Image(err) => write!(f, "Image error: {}", err),
+            BatchError::Unsupported(what) => write!(f, "Unsupported: {}", what),
+            BatchError::Tiled(err) => write!(f, "{}", err),
+            BatchError::Conflict(what) => write!(f, "Conflicting outputs: {}", what),
+            BatchError::Failed(count) => write!(f, "{} images failed", count),
+        }
+    }
+}
+
+impl std::error::Error for BatchError {}
+
+impl From<io::Error> for BatchError {
+    fn from(err: io::Error) -> Self {
+        BatchError::Io(err)
+    }
+}
+
+impl From<image::ImageError> for BatchError {
+    fn from(err: image::ImageError) -> Self {
+        BatchError::Image(err)
+    }
+}
+
+impl From<TileError> for BatchError {
+    fn from(err: TileError) -> Self {
+        BatchError::Tiled(err)
+    }
+}
+
+// `*` matches any run of characters and `?` a single one
+fn wildcard_match(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|i| wildcard_match(rest, &name[i..])),
+        Some((p, rest)) => match name.split_first() {
+            Some((c, name)) => (*p == '?' || p == c) && wildcard_match(rest, name),
+            None => false,
+        },
+    }
+}
+
+fn has_wildcards(s: &str) -> bool {
+    s.contains(['*', '?'])
+}
+
+/// The files matching a pattern, sorted. Only the file name may contain wildcards; a pattern
+/// without any is returned as is.
+fn expand(pattern: &str) -> Result<Vec<PathBuf>, BatchError> {
+    let path = Path::new(pattern);
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    if !has_wildcards(name) {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if has_wildcards(&dir.to_string_lossy()) {
+        return Err(BatchError::Unsupported(format!(
+            "wildcards in directories: {}",
+            pattern
+        )));
+    }
+    let name: Vec<char> = name.chars().collect();
+    let mut paths = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name: Vec<char> = entry.file_name().to_string_lossy().chars().collect();
+        if entry.file_type()?.is_file() && wildcard_match(&name, &file_name) {
+            paths.push(entry.path());
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+fn output_path(input: &Path, output_dir: &Path, format: StillFormat) -> PathBuf {
+    let mut name = input
+        .file_stem()
+        .unwrap_or(input.as_os_str())
+        .to_os_string();
+    name.push(".");
+    name.push(format.extension());
+    output_dir.join(name)
+}
+
+// The output of every input, refusing outputs that would replace an input or that several
+// inputs share. Paths are compared as given, so they should be canonical.
+fn output_paths(
+    inputs: &[PathBuf],
+    output_dir: &Path,
+    format: StillFormat,
+) -> Result<Vec<PathBuf>, BatchError> {
+    let mut outputs: Vec<PathBuf> = vec![];
+    for input in inputs {
+        let output = output_path(input, output_dir, format);
+        if inputs.contains(&output) {
+            return Err(BatchError::Conflict(format!(
+                "{} would be overwritten",
+                output.display()
+            )));
+        }
+        if let Some(i) = outputs.iter().position(|o| *o == output) {
+            return Err(BatchError::Conflict(format!(
+                "{} and {} would both be written to {}",
+                inputs[i].display(),
+                input.display(),
+                output.display()
+            )));
+        }
+        outputs.push(output);
+    }
+    Ok(outputs)
+}
+
+// Adapts the image in a single transform, which fails when the GPU cannot hold its spectrum.
+// Alpha is kept.
+fn adapt_whole(
+    facade: &dyn Facade,
+    processor: &mut Processor,
+    profiler: &mut GpuProfiler,
+    (width, height): (u32, u32),
+    pixels: &[Pixel],
+    params: &AdaptParams,
+) -> Result<Vec<Pixel>, AllocationError> {
+    let texture = Texture2d::with_format(
+        facade,
+        RawImage2d::from_raw_rgba(pixels.concat(), (width, height)),
+        UncompressedFloatFormat::F32F32F32F32,
+        MipmapsOption::NoMipmap,
+    )?;
+    let adapted = processor.process(facade, &texture, params, profiler)?;
+    Ok(read_rgba_f32(adapted.orig())
+        .into_iter()
+        .flatten()
+        .zip(pixels)
+        .map(|(p, input)| [p.0, p.1, p.2, input[3]])
+        .collect())
+}
+
+// Loads, adapts and saves a single image
+fn process(
+    facade: &dyn Facade,
+    processor: &mut Processor,
+    profiler: &mut GpuProfiler,
+    job: &BatchJob,
+    input: &Path,
+    output: &Path,
+) -> Result<(), BatchError> {
+    let image = image::open(input)?.into_rgba32f();
+    let (width, height) = image.dimensions();
+
+    // Same conversion as the GStreamer upload, rows top first as in the tiler
+    let pixels: Vec<Pixel> = image
+        .pixels()
+        .map(|p| {
+            let [r, g, b] = job.transfer.to_working([p[0], p[1], p[2]]);
+            [r, g, b, p[3]]
+        })
+        .collect();
+
+    let params = AdaptParams {
+        csf: job.csf.clone(),
+        pixels_per_visual_degree: job
+            .viewing
+            .pixels_per_vd(width as f32, job.viewing.screen_distance_mm),
+        target_pixels_per_visual_degree: job
+            .viewing
+            .pixels_per_vd(width as f32, job.viewing.target_distance_mm),
+        precision: job.precision,
+        working_space: job.working_space,
+        transfer: job.transfer,
+        calibration: job.calibration,
+    };
+    let size = (width, height);
+    let result = match adapt_whole(facade, processor, profiler, size, &pixels, &params) {
+        Ok(result) => result,
+        Err(err) => {
+            eprintln!("{}, adapting {}×{} in tiles", err, width, height);
+            let config = TileConfig::default();
+            tiled::adapt_pixels(facade, processor, size, &pixels, &params, config)?
+        }
+    };
+
+    match job.format {
+        StillFormat::Png | StillFormat::Tiff => {
+            let profile = job.calibration.measured_profile();
+            let encode = |v: f32| (v.clamp(0., 1.) * 65535.).round() as u16;
+            let data: Vec<u16> = result
+                .iter()
+                .flat_map(|p| {
+                    let [r, g, b] = job.transfer.to_device([p[0], p[1], p[2]], profile.as_ref());
+                    [encode(r), encode(g), encode(b), encode(p[3])]
+                })
+                .collect();
+            image::ImageBuffer::<image::Rgba<u16>, _>::from_raw(width, height, data)
+                .unwrap()
+                .save(output)?;
+        }
+        StillFormat::Exr => {
+            // A gamma of one leaves linear light relative to SDR white in the output primaries;
+            // a measured display curve does not apply to linear light
+            let linear = TransferSettings {
+                output: TransferFunction::Gamma(1.),
+                ..job.transfer
+            };
+            let data: Vec<f32> = result
+                .iter()
+                .flat_map(|p| {
+                    let [r, g, b] = linear.to_output([p[0], p[1], p[2]], true);
+                    [r, g, b, p[3]]
+                })
+                .collect();
+            image::Rgba32FImage::from_raw(width, height, data)
+                .unwrap()
+                .save(output)?;
+        }
+    }
+    Ok(())
+}
+
+/// Adapts every image matched by the inputs. An image that fails is reported and skipped.
+/// Nothing is written when an output would replace an input or another output.
+pub fn run(
+    facade: &dyn Facade,
+    processor: &mut Processor,
+    job: &BatchJob,
+) -> Result<(), BatchError> {
+    let mut inputs = vec![];
+    for pattern in &job.inputs {
+        let paths = expand(pattern)?;
+        if paths.is_empty() {
+            eprintln!("No files match {}", pattern);
+        }
+        inputs.extend(paths);
+    }
+    std::fs::create_dir_all(&job.output_dir)?;
+    // Canonical, so that an input is recognised however it and the directory are written.
+    // Files that do not exist are kept as they are and fail when they are loaded.
+    let output_dir = std::fs::canonicalize(&job.output_dir)?;
+    let mut inputs: Vec<PathBuf> = inputs
+        .into_iter()
+        .map(|path| std::fs::canonicalize(&path).unwrap_or(path))
+        .collect();
+    // An image matched by several patterns is adapted once
+    let mut seen = std::collections::HashSet::new();
+    inputs.retain(|path| seen.insert(path.clone()));
+    let outputs = output_paths(&inputs, &output_dir, job.format)?;
+
+    let mut profiler = GpuProfiler::disabled();
+    let mut failed = 0;
+    for (i, (input, output)) in inputs.iter().zip(&outputs).enumerate() {
+        eprintln!(
+            "{}/{} {} → {}",
+            i + 1,
+            inputs.len(),
+            input.display(),
+            output.display()
+        );
+        if let Err(err) = process(facade, processor, &mut profiler, job, input, output) {
+            eprintln!("{}: {}", input.display(), err);
+            failed += 1;
+        }
+    }
+    match failed {
+        0 => Ok(()),
+        n => Err(BatchError::Failed(n)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, name: &str) -> bool {
+        let chars = |s: &str| s.chars().collect::<Vec<_>>();
+        wildcard_match(&chars(pattern), &chars(name))
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(matches("*.png", "a.png"));
+        assert!(matches("*.png", ".png"));
+        assert!(!matches("*.png", "a.png.tmp"));
+        assert!(matches("img_??.*", "img_01.jpg"));
+        assert!(!matches("img_??.*", "img_1.jpg"));
+        assert!(matches("*", ""));
+        assert!(!matches("a", ""));
+    }
+
+    #[test]
+    fn test_output_path() {
+        assert_eq!(
+            output_path(
+                Path::new("photos/a.jpg"),
+                Path::new("out"),
+                StillFormat::Exr
+            ),
+            PathBuf::from("out/a.exr")
+        );
+        assert_eq!(
+            output_path(Path::new("b.v2.png"), Path::new("out"), StillFormat::Tiff),
+            PathBuf::from("out/b.v2.tiff")
+        );
+    }
+
+    #[test]
+    fn test_output_conflicts() {
+        let paths = |paths: &[&str]| paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+        let out = Path::new("/out");
+        assert_eq!(
+            output_paths(&paths(&["/a/x.jpg", "/a/y.jpg"]), out, StillFormat::Png).unwrap(),
+            paths(&["/out/x.png", "/out/y.png"])
+        );
+        // Same stem in two directories
+        assert!(matches!(
+            output_paths(&paths(&["/a/x.jpg", "/b/x.jpg"]), out, StillFormat::Png),
+            Err(BatchError::Conflict(_))
+        ));
+        // Output directory holding the inputs
+        assert!(matches!(
+            output_paths(&paths(&["/out/x.png"]), out, StillFormat::Png),
+            Err(BatchError::Conflict(_))
+        ));
+        assert!(output_paths(&paths(&["/out/x.png"]), out, StillFormat::Tiff).is_ok());
+    }
+}
@@ -0,0 +1,258 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{
+    icc::{Curve, IccProfile},
+    transfer::Primaries,
+};
+
+mod wizard;
+
+pub use wizard::CalibrationWizard;
+
+/// Measured luminance behaviour of the display and its surround.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DisplayCalibration {
+    /// Luminance of full white in cd/m²
+    pub peak_luminance: f32,
+    /// Luminance of full black in a dark room in cd/m²
+    pub black_level: f32,
+    /// Illuminance falling on the screen in lux
+    pub ambient_illuminance: f32,
+    /// Fraction of the ambient light the screen reflects diffusely
+    pub reflectance: f32,
+    /// Per-channel exponent of the display's transfer curve, once measured
+    pub gamma: Option<[f32; 3]>,
+    /// Offset added to device values before the exponent, which lifts black as in BT.1886
+    pub black_offset: f32,
+}
+
+/// The ideal display: no black level and no reflections, so contrast is left as it is.
+impl Default for DisplayCalibration {
+    fn default() -> Self {
+        Self {
+            peak_luminance: 100.,
+            black_level: 0.,
+            ambient_illuminance: 0.,
+            reflectance: 0.01,
+            gamma: None,
+            black_offset: 0.,
+        }
+    }
+}
+
+// Level the contrast loss is evaluated at, relative to white. Mid grey stands in for the mean
+// luminance of the content, which is not known before the spectrum is computed.
+const ADAPTATION_LEVEL: f32 = 0.18;
+
+// Limits the boost when the veiling luminance swamps the display
+const MAX_CONTRAST_BOOST: f32 = 8.;
+
+impl DisplayCalibration {
+    /// Luminance reflected by the screen in cd/m², assuming a Lambertian surface.
+    pub fn reflected_luminance(&self) -> f32 {
+        self.ambient_illuminance * self.reflectance / std::f32::consts::PI
+    }
+
+    /// Luminance in cd/m² reaching the eye for a linear pixel value relative to white.
+    pub fn luminance(&self, value: f32) -> f32 {
+        self.black_level
+            + (self.peak_luminance - self.black_level) * value
+            + self.reflected_luminance()
+    }
+
+    /// Michelson contrast left of a small modulation around `level` once black level and
+    /// reflections are added, relative to the ideal display.
+    pub fn contrast_retention(&self, level: f32) -> f32 {
+        let signal = (self.peak_luminance - self.black_level) * level;
+        if signal <= 0. {
+            return 0.;
+        }
+        signal / self.luminance(level)
+    }
+
+    /// Gain that restores the contrast of the ideal display at mid grey.
+    pub fn contrast_boost(&self) -> f32 {
+        (1. / self.contrast_retention(ADAPTATION_LEVEL)).min(MAX_CONTRAST_BOOST)
+    }
+
+    /// Luminance of device value `v` relative to white according to the measured curve.
+    pub fn measured_curve(&self, channel: usize, v: f32) -> Option<f32> {
+        let gamma = self.gamma?[channel];
+        let o = self.black_offset;
+        Some(((v.max(0.) + o) / (1. + o)).powf(gamma))
+    }
+
+    /// The measured curves as a profile for the output stage, assuming BT.709 primaries.
+    pub fn measured_profile(&self) -> Option<IccProfile> {
+        let gamma = self.gamma?;
+        let o = self.black_offset;
+        let trc = gamma.map(|g| Curve::Parametric {
+            kind: 1,
+            params: [g, 1. / (1. + o), o / (1. + o), 0., 0., 0., 0.],
+        });
+        Some(IccProfile::from_curves(
+            Primaries::Bt709,
+            trc,
+            Some(self.peak_luminance),
+        ))
+    }
+
+    /// `$XDG_CONFIG_HOME/csf_contrast/display_calibration`, falling back to `~/.config`.
+    pub fn default_path() -> Option<PathBuf> {
+        let config = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+        Some(config.join("csf_contrast").join("display_calibration"))
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        fs::read_to_string(path)?
+            .parse()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_string())
+    }
+
+    pub fn draw_ui(&mut self, ui: &mut egui::Ui) {
+        let mut row = |ui: &mut egui::Ui, label: &str, value: &mut f32, unit: &str| {
+            ui.horizontal(|ui| {
+                ui.label(label);
+                ui.add(
+                    egui::DragValue::new(value)
+                        .speed(0.1)
+                        .clamp_range(0.0..=100000.),
+                );
+                ui.label(unit);
+            });
+        };
+        row(ui, "Peak white:", &mut self.peak_luminance, "cd/m²");
+        row(ui, "Black level:", &mut self.black_level, "cd/m²");
+        row(ui, "Ambient light:", &mut self.ambient_illuminance, "lx");
+        ui.horizontal(|ui| {
+            ui.label("Screen reflectance:");
+            ui.add(
+                egui::DragValue::new(&mut self.reflectance)
+                    .speed(0.001)
+                    .clamp_range(0.0..=1.),
+            );
+        });
+        ui.label(format!(
+            "Contrast at mid grey: {:.0}%",
+            self.contrast_retention(ADAPTATION_LEVEL) * 100.
+        ));
+    }
+}
+
+/// One `key=value` line per field.
+impl fmt::Display for DisplayCalibration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "peak_luminance={}", self.peak_luminance)?;
+        writeln!(f, "black_level={}", self.black_level)?;
+        writeln!(f, "ambient_illuminance={}", self.ambient_illuminance)?;
+        writeln!(f, "reflectance={}", self.reflectance)?;
+        if let Some([r, g, b]) = self.gamma {
+            writeln!(f, "gamma={},{},{}", r, g, b)?;
+            writeln!(f, "black_offset={}", self.black_offset)?;
+        }
+        Ok(())
+    }
+}
+
+/// Missing keys keep their defaults.
+impl FromStr for DisplayCalibration {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut calibration = Self::default();
+        for line in s.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("Expected key=value: {}", line))?;
+            let numbers = value
+                .split(',')
+                .map(|v| v.trim().parse::<f32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| format!("Invalid number: {}", line))?;
+            let values = |n: usize| {
+                if numbers.len() == n {
+                    Ok(numbers.as_slice())
+                } else {
+                    Err(format!("Expected {} values: {}", n, line))
+                }
+            };
+            match key.trim() {
+                "peak_luminance" => calibration.peak_luminance = values(1)?[0],
+                "black_level" => calibration.black_level = values(1)?[0],
+                "ambient_illuminance" => calibration.ambient_illuminance = values(1)?[0],
+                "reflectance" => calibration.reflectance = values(1)?[0],
+                "gamma" => calibration.gamma = Some(values(3)?.try_into().unwrap()),
+                "black_offset" => calibration.black_offset = values(1)?[0],
+                key => return Err(format!("Unknown calibration key: {}", key)),
+            }
+        }
+        Ok(calibration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let calibration = DisplayCalibration {
+            peak_luminance: 250.,
+            black_level: 0.3,
+            ambient_illuminance: 150.,
+            reflectance: 0.02,
+            gamma: Some([2.1, 2.2, 2.4]),
+            black_offset: 0.05,
+        };
+        assert_eq!(calibration.to_string().parse(), Ok(calibration));
+        assert!("gamma=2.2".parse::<DisplayCalibration>().is_err());
+        assert_eq!(
+            "# comment\nblack_level = 0.5\n".parse(),
+            Ok(DisplayCalibration {
+                black_level: 0.5,
+                ..Default::default()
+            })
+        );
+        assert!("white=1".parse::<DisplayCalibration>().is_err());
+    }
+
+    #[test]
+    fn test_ideal_display_keeps_contrast() {
+        let calibration = DisplayCalibration::default();
+        assert_eq!(calibration.contrast_boost(), 1.);
+        assert_eq!(calibration.luminance(1.), 100.);
+    }
+
+    #[test]
+    fn test_veiling_luminance_lowers_contrast() {
+        let calibration = DisplayCalibration {
+            peak_luminance: 100.,
+            black_level: 1.,
+            ambient_illuminance: 500.,
+            reflectance: 0.02,
+            ..Default::default()
+        };
+        let reflected = 500. * 0.02 / std::f32::consts::PI;
+        assert!((calibration.luminance(0.) - 1. - reflected).abs() < 1e-4);
+        let retention = calibration.contrast_retention(0.5);
+        assert!((retention - 49.5 / (50.5 + reflected)).abs() < 1e-4);
+        assert!(calibration.contrast_boost() > 1.);
+        assert!(calibration.contrast_boost() <= MAX_CONTRAST_BOOST);
+    }
+}
@@ -0,0 +1,203 @@
+use glium::{backend::Facade, uniform, Surface};
+
+use super::DisplayCalibration;
+use crate::image_shader::ImageShader;
+
+// A checkerboard and the solid patch that matches it in brightness
+struct Pattern {
+    name: &'static str,
+    mask: [f32; 3],
+    checker_high: f32,
+}
+
+// The gamma patterns alternate full black and white, where the black offset hardly matters.
+// The dark grey pattern is dominated by it.
+const PATTERNS: [Pattern; 4] = [
+    Pattern {
+        name: "Red",
+        mask: [1., 0., 0.],
+        checker_high: 1.,
+    },
+    Pattern {
+        name: "Green",
+        mask: [0., 1., 0.],
+        checker_high: 1.,
+    },
+    Pattern {
+        name: "Blue",
+        mask: [0., 0., 1.],
+        checker_high: 1.,
+    },
+    Pattern {
+        name: "Dark grey",
+        mask: [1., 1., 1.],
+        checker_high: DARK_CHECKER,
+    },
+];
+
+const DARK_CHECKER: f32 = 0.25;
+
+/// Full screen matching patterns that measure the display's transfer curve by eye.
+pub struct CalibrationWizard {
+    image_shader: ImageShader,
+    step: usize,
+    /// Solid patch levels the user matched to each pattern
+    matches: [f32; 4],
+}
+
+impl CalibrationWizard {
+    pub fn new(facade: &dyn Facade) -> Self {
+        // The patterns are device values, which the framebuffer must not encode again
+        let image_shader =
+            ImageShader::with_options(facade, include_str!("wizard/frag.glsl"), true);
+        Self {
+            image_shader,
+            step: 0,
+            matches: Self::initial_matches(),
+        }
+    }
+
+    // The matches of a display with a gamma of 2.2 and no black offset
+    fn initial_matches() -> [f32; 4] {
+        PATTERNS.map(|p| p.checker_high * 0.5f32.powf(1. / 2.2))
+    }
+
+    pub fn restart(&mut self) {
+        self.step = 0;
+        self.matches = Self::initial_matches();
+    }
+
+    pub fn draw<S>(&self, surface: &mut S)
+    where
+        S: Surface,
+    {
+        let pattern = &PATTERNS[self.step];
+        self.image_shader.draw(
+            surface,
+            &uniform! {
+                mask: pattern.mask,
+                checker_high: pattern.checker_high,
+                patch_level: self.matches[self.step],
+            },
+        );
+    }
+
+    /// Shows the controls of the current step. Returns true once the last match is confirmed.
+    pub fn draw_ui(&mut self, ui: &mut egui::Ui) -> bool {
+        let pattern = &PATTERNS[self.step];
+        ui.label(format!(
+            "Step {} of {}: {}",
+            self.step + 1,
+            PATTERNS.len(),
+            pattern.name
+        ));
+        ui.label("Move the slider until the centre patch is as bright as the checkerboard around it. Squint or step back if the squares are visible.");
+        ui.add(
+            egui::Slider::new(&mut self.matches[self.step], 0.0..=pattern.checker_high)
+                .text("Patch level"),
+        );
+        let mut done = false;
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(self.step > 0, egui::Button::new("Back"))
+                .clicked()
+            {
+                self.step -= 1;
+            }
+            if self.step + 1 < PATTERNS.len() {
+                if ui.button("Next").clicked() {
+                    self.step += 1;
+                }
+            } else if ui.button("Finish").clicked() {
+                done = true;
+            }
+        });
+        done
+    }
+
+    /// Stores the fitted curve in `calibration`, with the black level it implies.
+    pub fn apply(&self, calibration: &mut DisplayCalibration) {
+        store_fit(&self.matches, calibration);
+    }
+}
+
+fn store_fit(matches: &[f32; 4], calibration: &mut DisplayCalibration) {
+    let (gamma, offset) = fit(matches);
+    calibration.gamma = Some(gamma);
+    calibration.black_offset = offset;
+    // Green dominates the luminance of black
+    calibration.black_level =
+        calibration.peak_luminance * calibration.measured_curve(1, 0.).unwrap();
+}
+
+// How much brighter the solid patch is than the checkerboard for the curve (v + offset)^gamma
+fn mismatch(patch: f32, checker_high: f32, gamma: f32, offset: f32) -> f32 {
+    let l = |v: f32| (v + offset).powf(gamma);
+    l(patch) - (l(0.) + l(checker_high)) / 2.
+}
+
+// Bisection for the root of a function that is positive below it
+fn bisect(mut low: f32, mut high: f32, f: impl Fn(f32) -> f32) -> f32 {
+    for _ in 0..32 {
+        let mid = (low + high) / 2.;
+        if f(mid) > 0. {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    (low + high) / 2.
+}
+
+// Alternates between the gammas at the current offset and the offset at the mean gamma
+fn fit(matches: &[f32; 4]) -> ([f32; 3], f32) {
+    let mut gamma = [2.2; 3];
+    let mut offset = 0.;
+    for _ in 0..16 {
+        for (c, g) in gamma.iter_mut().enumerate() {
+            // A steeper curve darkens the patch relative to the checkerboard
+            *g = bisect(1., 4., |g| mismatch(matches[c], 1., g, offset));
+        }
+        let mean = gamma.iter().sum::<f32>() / 3.;
+        // A larger offset makes the curve more linear, which brightens the patch
+        offset = bisect(0., 0.5, |o| -mismatch(matches[3], DARK_CHECKER, mean, o));
+    }
+    (gamma, offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The matches a user of a display with the given curve would make
+    fn matches(gamma: [f32; 3], offset: f32) -> [f32; 4] {
+        let mean = gamma.iter().sum::<f32>() / 3.;
+        let solve = |high: f32, g: f32| bisect(0., high, |p| -mismatch(p, high, g, offset));
+        [
+            solve(1., gamma[0]),
+            solve(1., gamma[1]),
+            solve(1., gamma[2]),
+            solve(DARK_CHECKER, mean),
+        ]
+    }
+
+    #[test]
+    fn test_fit_recovers_curve() {
+        for (gamma, offset) in [([2.2, 2.2, 2.2], 0.), ([2.0, 2.3, 2.5], 0.05)] {
+            let (fitted, fitted_offset) = fit(&matches(gamma, offset));
+            for c in 0..3 {
+                assert!((fitted[c] - gamma[c]).abs() < 1e-3, "{:?}", fitted);
+            }
+            assert!((fitted_offset - offset).abs() < 1e-3, "{}", fitted_offset);
+        }
+    }
+
+    #[test]
+    fn test_store_fit_sets_black_level() {
+        let mut calibration = DisplayCalibration::default();
+        store_fit(&matches([2.4; 3], 0.1), &mut calibration);
+        let expected = 100. * (0.1f32 / 1.1).powf(2.4);
+        assert!((calibration.black_level - expected).abs() < 1e-3);
+        assert!(calibration.measured_profile().is_some());
+    }
+}
@@ -0,0 +1,22 @@
+#version 300 es
+precision highp float;
+
+in vec2 tex_coord;
+out vec4 color;
+
+// Channels the pattern is shown in
+uniform vec3 mask;
+// Device value of the bright squares, the dark ones are 0
+uniform float checker_high;
+// Device value of the solid patch in the centre
+uniform float patch_level;
+
+void main() {
+  // Single pixel squares average out at any normal viewing distance
+  ivec2 pixel = ivec2(gl_FragCoord.xy);
+  float checker = (pixel.x + pixel.y) % 2 == 0 ? checker_high : 0.0;
+
+  vec2 centre = abs(tex_coord - 0.5);
+  bool in_patch = centre.x < 0.1 && centre.y < 0.15;
+  color = vec4(mask * (in_patch ? patch_level : checker), 1.0);
+}
@@ -0,0 +1,517 @@
+use std::{collections::HashMap, path::PathBuf, str::FromStr};
+
+use csfcontrast::{
+    batch::{BatchJob, StillFormat},
+    calibration::DisplayCalibration,
+    color_space::{WorkingSpace, Ycbcr},
+    csf::Csf,
+    output::Framebuffer,
+    precision::Precision,
+    tiled::{TileConfig, TileJob},
+    transcode::{Codec, TranscodeJob},
+    transfer::TransferSettings,
+    viewing::Viewing,
+};
+
+pub const USAGE: &str = "\
+Usage:
+    csf_contrast [URI] [--framebuffer srgb|10bit|float] [--icc PROFILE.icc]
+                 [--output-pipeline PIPELINE]
+    csf_contrast tile INPUT.png OUTPUT.png [options]
+    csf_contrast transcode INPUT OUTPUT.mp4|mkv|webm [options]
+    csf_contrast batch IMAGE... --output-dir DIR [--format png|tiff|exr] [options]
+
+URI is anything playbin opens, v4l2:///dev/videoN for a camera or testsrc://PATTERN.
+PIPELINE receives the adapted frames after appsrc ! videoconvert, for example
+\"x264enc tune=zerolatency ! rtph264pay ! udpsink host=HOST port=5000\".
+The file names of batch images may contain * and ? wildcards.
+
+Viewing options:
+    --preset NAME           Screen size and distance of a desktop (default), laptop, phone, tv,
+                            signage, print-a4 or poster-a1, adapted for the same distance
+    --screen-width MM       Width of the screen the content fills
+    --screen-height MM      Height of the screen the content fills
+    --distance MM           Distance the content is adapted from
+    --target-distance MM    Distance the content is adapted for
+    --csf A,OMEGA,SIGMA,K   Parameters of the contrast sensitivity function
+    --precision full|half   Storage precision of the FFT
+    --calibration FILE      Display calibration to compensate black level and reflections for
+
+Transfer options:
+    --input-transfer TF     Transfer function of the input: srgb, bt1886[:BLACK], gamma:GAMMA,
+                            pq or hlg[:PEAK] (default srgb; PEAK in cd/m², default 1000)
+    --output-transfer TF    Transfer function of the output (default srgb)
+    --domain linear|encoded Adapt absolute luminance or the encoded signal (default linear)
+    --input-primaries P     Primaries of the input: bt709 (default) or bt2020
+                            A transcoded video takes both from its colorimetry unless either
+                            is given
+    --output-primaries P    Primaries of the output: bt709 (default) or bt2020
+    --sdr-white CD/M2       Luminance of SDR white (default the calibrated peak, or 100)
+    --tone-mapping MODE     clip or reinhard (default), for HDR input to SDR output
+    --working-space S       ycbcr (default), ictcp, lab or dkl
+    --matrix M              YCbCr matrix to adapt in: bt601, bt709 (default) or bt2020
+
+Tiling options:
+    --tile-size PIXELS      Side of the square tiles (default 1024)
+    --overlap PIXELS        Overlap between neighbouring tiles (default 128)
+
+Transcoding options:
+    --codec h264|h265|vp9   Video encoder (default h264, vp9 for .webm)
+    --bitrate KBPS          Target video bitrate (default 8000)";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    View {
+        uri: String,
+        framebuffer: Framebuffer,
+        icc: Option<String>,
+        output_pipeline: Option<String>,
+    },
+    Tile(TileJob),
+    Transcode(TranscodeJob),
+    Batch(BatchJob),
+}
+
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    match args.get(1).map(String::as_str) {
+        Some("tile") => parse_tile(&args[2..]),
+        Some("transcode") => parse_transcode(&args[2..]),
+        Some("batch") => parse_batch(&args[2..]),
+        _ => parse_view(&args[1..]),
+    }
+}
+
+fn parse_view(args: &[String]) -> Result<Command, String> {
+    let mut options = Options::parse(args)?;
+    let uri = match options.positional.as_slice() {
+        [] => String::new(),
+        [uri] => uri.clone(),
+        _ => return Err("Expected a single URI".to_string()),
+    };
+    let framebuffer = options.take("framebuffer", Framebuffer::TenBit)?;
+    let icc = options.named.remove("icc");
+    let output_pipeline = options.named.remove("output-pipeline");
+    options.finish()?;
+    Ok(Command::View {
+        uri,
+        framebuffer,
+        icc,
+        output_pipeline,
+    })
+}
+
+fn parse_tile(args: &[String]) -> Result<Command, String> {
+    let mut options = Options::parse(args)?;
+    let (input, output) = match options.positional.as_slice() {
+        [input, output] => (PathBuf::from(input), PathBuf::from(output)),
+        _ => return Err("tile expects an input and an output path".to_string()),
+    };
+    let defaults = TileConfig::default();
+    let calibration = options.calibration()?;
+    let job = TileJob {
+        input,
+        output,
+        viewing: options.viewing()?,
+        csf: options.csf()?,
+        precision: options.precision()?,
+        transfer: options.transfer(&calibration)?,
+        working_space: options.working_space()?,
+        calibration,
+        config: TileConfig {
+            tile_size: options.take("tile-size", defaults.tile_size)?,
+            overlap: options.take("overlap", defaults.overlap)?,
+        },
+    };
+    options.finish()?;
+    Ok(Command::Tile(job))
+}
+
+fn parse_transcode(args: &[String]) -> Result<Command, String> {
+    let mut options = Options::parse(args)?;
+    let (input, output) = match options.positional.as_slice() {
+        [input, output] => (input.clone(), PathBuf::from(output)),
+        _ => return Err("transcode expects an input and an output".to_string()),
+    };
+    let webm = output.extension().map_or(false, |e| e == "webm");
+    let codec = options.take("codec", if webm { Codec::Vp9 } else { Codec::H264 })?;
+    let calibration = options.calibration()?;
+    let job = TranscodeJob {
+        input,
+        output,
+        viewing: options.viewing()?,
+        csf: options.csf()?,
+        precision: options.precision()?,
+        transfer: options.transfer(&calibration)?,
+        working_space: options.working_space()?,
+        calibration,
+        codec,
+        bitrate_kbps: options.take("bitrate", 8000)?,
+    };
+    options.finish()?;
+    Ok(Command::Transcode(job))
+}
+
+fn parse_batch(args: &[String]) -> Result<Command, String> {
+    let mut options = Options::parse(args)?;
+    if options.positional.is_empty() {
+        return Err("batch expects at least one image".to_string());
+    }
+    let output_dir = options
+        .named
+        .remove("output-dir")
+        .ok_or_else(|| "batch expects --output-dir".to_string())?;
+    let calibration = options.calibration()?;
+    let job = BatchJob {
+        inputs: std::mem::take(&mut options.positional),
+        output_dir: PathBuf::from(output_dir),
+        format: options.take("format", StillFormat::Png)?,
+        viewing: options.viewing()?,
+        csf: options.csf()?,
+        precision: options.precision()?,
+        transfer: options.transfer(&calibration)?,
+        working_space: options.working_space()?,
+        calibration,
+    };
+    options.finish()?;
+    Ok(Command::Batch(job))
+}
+
+// Positional arguments and `--name value` pairs
+struct Options {
+    positional: Vec<String>,
+    named: HashMap<String, String>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut positional = vec![];
+        let mut named = HashMap::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(name) => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| format!("--{} expects a value", name))?;
+                    named.insert(name.to_string(), value.clone());
+                }
+                None => positional.push(arg.clone()),
+            }
+        }
+        Ok(Self { positional, named })
+    }
+
+    fn take<T: FromStr>(&mut self, name: &str, default: T) -> Result<T, String> {
+        match self.named.remove(name) {
+            Some(value) => value
+                .parse()
+                .map_err(|_| format!("Invalid value for --{}: {}", name, value)),
+            None => Ok(default),
+        }
+    }
+
+    fn viewing(&mut self) -> Result<Viewing, String> {
+        let defaults = match self.named.remove("preset") {
+            Some(name) => Viewing::preset(&name)
+                .ok_or_else(|| format!("Invalid value for --preset: {}", name))?,
+            None => Viewing::default(),
+        };
+        Ok(Viewing {
+            screen_dims_mm: (
+                self.take("screen-width", defaults.screen_dims_mm.x)?,
+                self.take("screen-height", defaults.screen_dims_mm.y)?,
+            )
+                .into(),
+            screen_distance_mm: self.take("distance", defaults.screen_distance_mm)?,
+            target_distance_mm: self.take("target-distance", defaults.target_distance_mm)?,
+        })
+    }
+
+    fn csf(&mut self) -> Result<Csf, String> {
+        let value = match self.named.remove("csf") {
+            Some(value) => value,
+            None => return Ok(Csf::default()),
+        };
+        let params = value
+            .split(',')
+            .map(|p| p.trim().parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| format!("Invalid value for --csf: {}", value))?;
+        match params.as_slice() {
+            [a, ω, σ, k] => Ok(Csf {
+                a: *a,
+                ω: *ω,
+                σ: *σ,
+                k: *k,
+            }),
+            _ => Err("--csf expects four comma separated values".to_string()),
+        }
+    }
+
+    fn precision(&mut self) -> Result<Precision, String> {
+        match self.named.remove("precision").as_deref() {
+            None | Some("full") => Ok(Precision::Full),
+            Some("half") => Ok(Precision::Half),
+            Some(other) => Err(format!("Invalid value for --precision: {}", other)),
+        }
+    }
+
+    // White is shown at the calibrated peak, as in the viewer, unless --sdr-white says otherwise
+    fn transfer(&mut self, calibration: &DisplayCalibration) -> Result<TransferSettings, String> {
+        let defaults = TransferSettings::default();
+        // Streams signal their own unless the command line says otherwise; stills carry none
+        // the tiler reads, so there this has no effect
+        let follow_stream = !self.named.contains_key("input-transfer")
+            && !self.named.contains_key("input-primaries");
+        Ok(TransferSettings {
+            input: self.take("input-transfer", defaults.input)?,
+            output: self.take("output-transfer", defaults.output)?,
+            domain: self.take("domain", defaults.domain)?,
+            input_primaries: self.take("input-primaries", defaults.input_primaries)?,
+            output_primaries: self.take("output-primaries", defaults.output_primaries)?,
+            sdr_white: self.take("sdr-white", calibration.peak_luminance)?,
+            tone_mapping: self.take("tone-mapping", defaults.tone_mapping)?,
+            follow_stream,
+        })
+    }
+
+    fn ycbcr(&mut self) -> Result<Ycbcr, String> {
+        let defaults = Ycbcr::default();
+        Ok(Ycbcr {
+            matrix: self.take("matrix", defaults.matrix)?,
+        })
+    }
+
+    fn working_space(&mut self) -> Result<WorkingSpace, String> {
+        let ycbcr = self.ycbcr()?;
+        match self.named.remove("working-space").as_deref() {
+            None | Some("ycbcr") => Ok(WorkingSpace::Ycbcr(ycbcr)),
+            Some("ictcp") => Ok(WorkingSpace::Ictcp),
+            Some("lab") => Ok(WorkingSpace::Lab),
+            Some("dkl") => Ok(WorkingSpace::Dkl),
+            Some(other) => Err(format!("Invalid value for --working-space: {}", other)),
+        }
+    }
+
+    fn calibration(&mut self) -> Result<DisplayCalibration, String> {
+        match self.named.remove("calibration") {
+            Some(path) => DisplayCalibration::load(path.as_ref())
+                .map_err(|err| format!("Could not load calibration {}: {}", path, err)),
+            None => Ok(DisplayCalibration::default()),
+        }
+    }
+
+    fn finish(self) -> Result<(), String> {
+        match self.named.keys().next() {
+            Some(name) => Err(format!("Unknown option --{}", name)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use csfcontrast::{
+        color_space::YcbcrMatrix,
+        transfer::{ProcessingDomain, TransferFunction},
+    };
+
+    fn args(args: &[&str]) -> Vec<String> {
+        std::iter::once("csf_contrast")
+            .chain(args.iter().copied())
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn test_view() {
+        assert_eq!(
+            parse(&args(&["file:///a.webm"])),
+            Ok(Command::View {
+                uri: "file:///a.webm".to_string(),
+                framebuffer: Framebuffer::TenBit,
+                icc: None,
+                output_pipeline: None,
+            })
+        );
+        assert_eq!(
+            parse(&args(&[
+                "--framebuffer",
+                "float",
+                "--icc",
+                "display.icc",
+                "--output-pipeline",
+                "shmsink socket-path=/tmp/adapted",
+            ])),
+            Ok(Command::View {
+                uri: String::new(),
+                framebuffer: Framebuffer::Float,
+                icc: Some("display.icc".to_string()),
+                output_pipeline: Some("shmsink socket-path=/tmp/adapted".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn test_tile() {
+        let command = parse(&args(&[
+            "tile",
+            "in.png",
+            "--distance",
+            "500",
+            "out.png",
+            "--tile-size",
+            "512",
+            "--precision",
+            "half",
+            "--input-transfer",
+            "bt1886:0.001",
+            "--domain",
+            "encoded",
+            "--matrix",
+            "bt601",
+        ]))
+        .unwrap();
+        let job = match command {
+            Command::Tile(job) => job,
+            _ => panic!("Expected a tile command"),
+        };
+        assert_eq!(job.input, PathBuf::from("in.png"));
+        assert_eq!(job.output, PathBuf::from("out.png"));
+        assert_eq!(job.viewing.screen_distance_mm, 500.);
+        assert_eq!(job.config.tile_size, 512);
+        assert_eq!(job.config.overlap, TileConfig::default().overlap);
+        assert_eq!(job.precision, Precision::Half);
+        assert_eq!(job.csf, Csf::default());
+        assert_eq!(
+            job.transfer.input,
+            TransferFunction::Bt1886 { black: 0.001 }
+        );
+        assert_eq!(job.transfer.output, TransferFunction::Srgb);
+        assert_eq!(job.transfer.domain, ProcessingDomain::Encoded);
+        assert!(!job.transfer.follow_stream);
+        assert_eq!(
+            job.working_space,
+            WorkingSpace::Ycbcr(Ycbcr {
+                matrix: YcbcrMatrix::Bt601,
+            })
+        );
+        assert_eq!(job.calibration, DisplayCalibration::default());
+    }
+
+    #[test]
+    fn test_transcode() {
+        let command = parse(&args(&[
+            "transcode",
+            "in.mov",
+            "out.webm",
+            "--bitrate",
+            "2000",
+            "--target-distance",
+            "3000",
+        ]))
+        .unwrap();
+        let job = match command {
+            Command::Transcode(job) => job,
+            _ => panic!("Expected a transcode command"),
+        };
+        assert_eq!(job.input, "in.mov");
+        assert_eq!(job.output, PathBuf::from("out.webm"));
+        assert_eq!(job.codec, Codec::Vp9);
+        assert_eq!(job.bitrate_kbps, 2000);
+        assert_eq!(job.viewing.target_distance_mm, 3000.);
+        assert!(job.transfer.follow_stream);
+
+        let command = parse(&args(&["transcode", "a", "b.mkv", "--codec", "h265"])).unwrap();
+        assert!(matches!(command, Command::Transcode(job) if job.codec == Codec::H265));
+
+        let command = parse(&args(&[
+            "transcode",
+            "a",
+            "b.mkv",
+            "--input-transfer",
+            "hlg:600",
+        ]))
+        .unwrap();
+        assert!(matches!(
+            command,
+            Command::Transcode(job) if job.transfer.input == TransferFunction::Hlg { peak: 600. }
+        ));
+    }
+
+    #[test]
+    fn test_batch() {
+        let command = parse(&args(&[
+            "batch",
+            "a.jpg",
+            "photos/*.png",
+            "--output-dir",
+            "out",
+            "--format",
+            "exr",
+            "--preset",
+            "signage",
+            "--target-distance",
+            "8000",
+        ]))
+        .unwrap();
+        let job = match command {
+            Command::Batch(job) => job,
+            _ => panic!("Expected a batch command"),
+        };
+        assert_eq!(job.inputs, vec!["a.jpg", "photos/*.png"]);
+        assert_eq!(job.output_dir, PathBuf::from("out"));
+        assert_eq!(job.format, StillFormat::Exr);
+        let signage = Viewing::preset("signage").unwrap();
+        assert_eq!(job.viewing.screen_dims_mm, signage.screen_dims_mm);
+        assert_eq!(job.viewing.screen_distance_mm, signage.screen_distance_mm);
+        assert_eq!(job.viewing.target_distance_mm, 8000.);
+    }
+
+    #[test]
+    fn test_calibration_sdr_white() {
+        let path = std::env::temp_dir().join(format!("csf_contrast-{}.cal", std::process::id()));
+        let calibration = DisplayCalibration {
+            peak_luminance: 250.,
+            ..DisplayCalibration::default()
+        };
+        calibration.save(&path).unwrap();
+        let path = path.to_str().unwrap();
+        let sdr_white = |extra: &[&str]| {
+            let mut list = vec!["tile", "a", "b", "--calibration", path];
+            list.extend_from_slice(extra);
+            match parse(&args(&list)).unwrap() {
+                Command::Tile(job) => job.transfer.sdr_white,
+                _ => panic!("Expected a tile command"),
+            }
+        };
+        assert_eq!(sdr_white(&[]), 250.);
+        assert_eq!(sdr_white(&["--sdr-white", "80"]), 80.);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(parse(&args(&["tile", "in.png"])).is_err());
+        assert!(parse(&args(&["tile", "a", "b", "--bogus", "1"])).is_err());
+        assert!(parse(&args(&["tile", "a", "b", "--csf", "1,2"])).is_err());
+        assert!(parse(&args(&["tile", "a", "b", "--distance"])).is_err());
+        assert!(parse(&args(&["tile", "a", "b", "--domain", "log"])).is_err());
+        assert!(parse(&args(&["tile", "a", "b", "--working-space", "xyz"])).is_err());
+        assert!(parse(&args(&["tile", "a", "b", "--calibration", "/nonexistent"])).is_err());
+        assert!(parse(&args(&["transcode", "a.mp4"])).is_err());
+        assert!(parse(&args(&["transcode", "a", "b.mp4", "--codec", "av1"])).is_err());
+        assert!(parse(&args(&["batch", "--output-dir", "out"])).is_err());
+        assert!(parse(&args(&["batch", "a.png"])).is_err());
+        assert!(parse(&args(&[
+            "batch",
+            "a.png",
+            "--output-dir",
+            "o",
+            "--preset",
+            "x"
+        ]))
+        .is_err());
+    }
+}
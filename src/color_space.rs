@@ -1,39 +1,449 @@
-use glium::{backend::Facade, program::ComputeShader, uniform, Texture2d};
+use std::str::FromStr;
+
+use glium::{backend::Facade, uniform, Texture2d};
+
+use crate::{
+    precision::{Precision, PrecisionShader},
+    transfer::{glsl_mat3, with_transfer_functions, Primaries, ProcessingDomain, TransferSettings},
+};
+
+/// Luma coefficients of a YCbCr encoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum YcbcrMatrix {
+    Bt601,
+    Bt709,
+    Bt2020,
+}
+
+impl YcbcrMatrix {
+    pub fn name(&self) -> &'static str {
+        match self {
+            YcbcrMatrix::Bt601 => "BT.601",
+            YcbcrMatrix::Bt709 => "BT.709",
+            YcbcrMatrix::Bt2020 => "BT.2020",
+        }
+    }
+
+    pub fn luma_coefficients(&self) -> [f32; 3] {
+        match self {
+            YcbcrMatrix::Bt601 => [0.299, 0.587, 0.114],
+            YcbcrMatrix::Bt709 => [0.2126, 0.7152, 0.0722],
+            YcbcrMatrix::Bt2020 => [0.2627, 0.6780, 0.0593],
+        }
+    }
+}
+
+impl FromStr for YcbcrMatrix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bt601" => Ok(YcbcrMatrix::Bt601),
+            "bt709" => Ok(YcbcrMatrix::Bt709),
+            "bt2020" => Ok(YcbcrMatrix::Bt2020),
+            _ => Err(format!("Unknown matrix: {}", s)),
+        }
+    }
+}
+
+/// YCbCr encoding used while adapting.
+///
+/// The frames arrive as RGB, so the quantization range was already undone by the decoder and
+/// only the matrix matters here.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Ycbcr {
+    pub matrix: YcbcrMatrix,
+}
+
+impl Default for Ycbcr {
+    fn default() -> Self {
+        Self {
+            matrix: YcbcrMatrix::Bt709,
+        }
+    }
+}
+
+impl Ycbcr {
+    /// Row-major matrix and offset from RGB to YCbCr.
+    pub fn forward(&self) -> ([[f32; 3]; 3], [f32; 3]) {
+        let [kr, kg, kb] = self.matrix.luma_coefficients();
+        let m = [
+            [kr, kg, kb],
+            [-kr / (2. * (1. - kb)), -kg / (2. * (1. - kb)), 0.5],
+            [0.5, -kg / (2. * (1. - kr)), -kb / (2. * (1. - kr))],
+        ];
+        (m, [0.; 3])
+    }
+
+    /// Row-major matrix and offset from YCbCr to RGB.
+    pub fn inverse(&self) -> ([[f32; 3]; 3], [f32; 3]) {
+        let [kr, kg, kb] = self.matrix.luma_coefficients();
+        let m = [
+            [1., 0., 2. * (1. - kr)],
+            [1., -2. * kb * (1. - kb) / kg, -2. * kr * (1. - kr) / kg],
+            [1., 2. * (1. - kb), 0.],
+        ];
+        (m, [0.; 3])
+    }
+
+    pub fn draw_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("ycbcr_matrix")
+                .selected_text(self.matrix.name())
+                .show_ui(ui, |ui| {
+                    for matrix in [YcbcrMatrix::Bt601, YcbcrMatrix::Bt709, YcbcrMatrix::Bt2020] {
+                        ui.selectable_value(&mut self.matrix, matrix, matrix.name());
+                    }
+                });
+        });
+    }
+}
+
+type Affine = ([[f32; 3]; 3], [f32; 3]);
+
+pub(crate) fn mat_mul(a: &[[f32; 3]; 3], b: &[[f32; 3]; 3]) -> [[f32; 3]; 3] {
+    [0, 1, 2].map(|i| [0, 1, 2].map(|j| (0..3).map(|k| a[i][k] * b[k][j]).sum()))
+}
+
+fn mat_vec(m: &[[f32; 3]; 3], v: [f32; 3]) -> [f32; 3] {
+    m.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+pub(crate) fn invert(m: &[[f32; 3]; 3]) -> [[f32; 3]; 3] {
+    let cofactor = |i: usize, j: usize| {
+        let (r0, r1) = ((i + 1) % 3, (i + 2) % 3);
+        let (c0, c1) = ((j + 1) % 3, (j + 2) % 3);
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+    let det: f32 = (0..3).map(|j| m[0][j] * cofactor(0, j)).sum();
+    // The adjugate is the transposed cofactor matrix
+    [0, 1, 2].map(|i| [0, 1, 2].map(|j| cofactor(j, i) / det))
+}
+
+// Inverse of x -> m x + o
+fn invert_affine((m, offset): &Affine) -> Affine {
+    let inverse = invert(m);
+    let offset = mat_vec(&inverse, *offset).map(|c| -c);
+    (inverse, offset)
+}
+
+// BT.2100 RGB to LMS, for BT.2020 primaries
+const BT2020_TO_LMS: [[f32; 3]; 3] = [
+    [1688. / 4096., 2146. / 4096., 262. / 4096.],
+    [683. / 4096., 2951. / 4096., 462. / 4096.],
+    [99. / 4096., 309. / 4096., 3688. / 4096.],
+];
+
+// Smith-Pokorny cone fundamentals from XYZ, scaled so that L + M is luminance
+const XYZ_TO_LMS: [[f32; 3]; 3] = [
+    [0.15514, 0.54312, -0.03286],
+    [-0.15514, 0.45684, 0.03286],
+    [0., 0., 0.00801],
+];
+
+const D65: [f32; 3] = [0.95047, 1., 1.08883];
+
+/// Colour space the adaptation runs in. The CSF is applied to the first, achromatic channel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WorkingSpace {
+    Ycbcr(Ycbcr),
+    /// BT.2100 ICtCp, computed from absolute luminance through PQ
+    Ictcp,
+    /// CIE 1976 L*a*b* relative to SDR white, divided by 100
+    Lab,
+    /// Derrington-Krauskopf-Lennie cone contrasts around an SDR white background
+    Dkl,
+}
+
+impl Default for WorkingSpace {
+    fn default() -> Self {
+        WorkingSpace::Ycbcr(Ycbcr::default())
+    }
+}
+
+impl WorkingSpace {
+    pub fn name(&self) -> &'static str {
+        match self {
+            WorkingSpace::Ycbcr(_) => "YCbCr",
+            WorkingSpace::Ictcp => "ICtCp",
+            WorkingSpace::Lab => "L*a*b*",
+            WorkingSpace::Dkl => "DKL",
+        }
+    }
+
+    // Matches the SPACE_* defines of comp.glsl
+    fn shader_id(&self) -> i32 {
+        match self {
+            WorkingSpace::Ycbcr(_) | WorkingSpace::Dkl => 0,
+            WorkingSpace::Ictcp => 1,
+            WorkingSpace::Lab => 2,
+        }
+    }
+
+    /// Whether the conversion is defined on linear light rather than on any signal.
+    pub fn needs_linear(&self) -> bool {
+        !matches!(self, WorkingSpace::Ycbcr(_))
+    }
+
+    /// Forward affine part of the conversion, see comp.glsl for what it maps to.
+    fn forward(&self, transfer: &TransferSettings) -> Affine {
+        let primaries = transfer.input_primaries;
+        let white = transfer.sdr_white;
+        match self {
+            WorkingSpace::Ycbcr(ycbcr) => ycbcr.forward(),
+            WorkingSpace::Ictcp => (
+                mat_mul(&BT2020_TO_LMS, &primaries.conversion(Primaries::Bt2020)),
+                [0.; 3],
+            ),
+            WorkingSpace::Lab => (
+                [0, 1, 2].map(|i| primaries.to_xyz()[i].map(|c| c / (D65[i] * white))),
+                [0.; 3],
+            ),
+            WorkingSpace::Dkl => {
+                let to_lms = mat_mul(&XYZ_TO_LMS, &primaries.to_xyz());
+                let [l0, m0, s0] = mat_vec(&to_lms, [white; 3]);
+                // Luminance, L - M and S - (L + M) contrasts relative to the background
+                let opponent = [
+                    [1. / (l0 + m0), 1. / (l0 + m0), 0.],
+                    [1. / l0, -1. / m0, 0.],
+                    [-1. / (l0 + m0), -1. / (l0 + m0), 1. / s0],
+                ];
+                let m = mat_mul(&opponent, &to_lms);
+                let offset = mat_vec(&m, [white; 3]).map(|c| -c);
+                (m, offset)
+            }
+        }
+    }
+
+    fn inverse(&self, transfer: &TransferSettings) -> Affine {
+        match self {
+            WorkingSpace::Ycbcr(ycbcr) => ycbcr.inverse(),
+            _ => invert_affine(&self.forward(transfer)),
+        }
+    }
+
+    pub fn draw_ui(&mut self, ui: &mut egui::Ui) {
+        let ycbcr = match self {
+            WorkingSpace::Ycbcr(ycbcr) => *ycbcr,
+            _ => Ycbcr::default(),
+        };
+        egui::ComboBox::from_id_source("working_space")
+            .selected_text(self.name())
+            .show_ui(ui, |ui| {
+                for space in [
+                    WorkingSpace::Ycbcr(ycbcr),
+                    WorkingSpace::Ictcp,
+                    WorkingSpace::Lab,
+                    WorkingSpace::Dkl,
+                ] {
+                    ui.selectable_value(self, space, space.name());
+                }
+            });
+        if let WorkingSpace::Ycbcr(ycbcr) = self {
+            ycbcr.draw_ui(ui);
+        }
+    }
+}
 
 pub struct ColorSpace {
-    shader: ComputeShader,
+    shader: PrecisionShader,
 }
 
 impl ColorSpace {
     pub fn new(facade: &dyn Facade) -> Self {
         Self {
-            shader: ComputeShader::from_source(facade, include_str!("color_space/comp.glsl"))
-                .unwrap(),
+            shader: PrecisionShader::new(
+                facade,
+                &with_transfer_functions(include_str!("color_space/comp.glsl")),
+            ),
         }
     }
 
-    // Transforms the given texture from the RGB colorspace to the YCbCr BT.709 colorspace
-    // Expects a texture in RGBA32F format
-    pub fn rgb_to_ycbcr(&self, texture: &Texture2d) {
-        self.invoke(texture, 0)
+    // Transforms the given texture from the RGB working signal to the given working space
+    // Expects a texture in the format of the given precision
+    pub fn rgb_to_working(
+        &self,
+        texture: &Texture2d,
+        precision: Precision,
+        space: &WorkingSpace,
+        transfer: &TransferSettings,
+    ) {
+        self.invoke(
+            texture,
+            precision,
+            0,
+            space,
+            transfer,
+            space.forward(transfer),
+        )
     }
 
-    pub fn ycbcr_to_rgb(&self, texture: &Texture2d) {
-        self.invoke(texture, 1)
+    pub fn working_to_rgb(
+        &self,
+        texture: &Texture2d,
+        precision: Precision,
+        space: &WorkingSpace,
+        transfer: &TransferSettings,
+    ) {
+        self.invoke(
+            texture,
+            precision,
+            1,
+            space,
+            transfer,
+            space.inverse(transfer),
+        )
     }
 
-    fn invoke(&self, texture: &Texture2d, mode: u32) {
-        let image_unit = texture
-            .image_unit(glium::uniforms::ImageUnitFormat::RGBA32F)
-            .unwrap();
-        self.shader.execute(
+    fn invoke(
+        &self,
+        texture: &Texture2d,
+        precision: Precision,
+        mode: u32,
+        space: &WorkingSpace,
+        transfer: &TransferSettings,
+        (matrix, offset): Affine,
+    ) {
+        let image_unit = texture.image_unit(precision.image_unit_format()).unwrap();
+        self.shader.get(precision).execute(
             uniform! {
                 image: image_unit,
-            mode: mode,
-                    },
+                mode: mode,
+                space: space.shader_id(),
+                matrix: glsl_mat3(matrix),
+                offset: offset,
+                decode: space.needs_linear() && transfer.domain == ProcessingDomain::Encoded,
+                transfer: transfer.input.shader_id(),
+                transfer_param: transfer.input.shader_param(),
+                sdr_white: transfer.sdr_white,
+            },
             texture.width() / 64 + 1,
             texture.height(),
             1,
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use glium::texture::{MipmapsOption, RawImage2d, UncompressedFloatFormat};
+
+    use super::*;
+    use crate::{headless::HeadlessContext, precision::read_rgba_f32};
+
+    fn apply((m, offset): ([[f32; 3]; 3], [f32; 3]), v: [f32; 3]) -> [f32; 3] {
+        [0, 1, 2].map(|i| m[i][0] * v[0] + m[i][1] * v[1] + m[i][2] * v[2] + offset[i])
+    }
+
+    #[test]
+    fn test_bt709() {
+        let ycbcr = Ycbcr {
+            matrix: YcbcrMatrix::Bt709,
+        };
+        let white = apply(ycbcr.forward(), [1.; 3]);
+        assert!((white[0] - 1.).abs() < 1e-5);
+        assert!(white[1].abs() < 1e-5);
+        let (m, _) = Ycbcr::default().forward();
+        assert!((m[1][1] + 0.3854).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for matrix in [YcbcrMatrix::Bt601, YcbcrMatrix::Bt709, YcbcrMatrix::Bt2020] {
+            let ycbcr = Ycbcr { matrix };
+            let rgb = [0.1, 0.6, 0.9];
+            let back = apply(ycbcr.inverse(), apply(ycbcr.forward(), rgb));
+            for (a, b) in rgb.iter().zip(back.iter()) {
+                assert!((a - b).abs() < 1e-5, "{:?}", ycbcr);
+            }
+        }
+    }
+
+    #[test]
+    fn test_working_spaces_invert() {
+        let transfer = TransferSettings::default();
+        for space in [WorkingSpace::Ictcp, WorkingSpace::Lab, WorkingSpace::Dkl] {
+            let rgb = [20., 60., 90.];
+            let back = apply(
+                space.inverse(&transfer),
+                apply(space.forward(&transfer), rgb),
+            );
+            for (a, b) in rgb.iter().zip(back.iter()) {
+                assert!((a - b).abs() < 1e-3, "{:?}", space);
+            }
+        }
+    }
+
+    // Runs comp.glsl itself, forward and back, on a texture of saturated colours
+    #[test]
+    #[ignore = "needs a GL driver"]
+    fn test_shader_round_trip() {
+        let context = HeadlessContext::new();
+        let facade = &context.facade;
+        let color_space = ColorSpace::new(facade);
+        let transfer = TransferSettings::default();
+        let white = transfer.sdr_white;
+        let mut spaces = vec![WorkingSpace::Ictcp, WorkingSpace::Lab, WorkingSpace::Dkl];
+        for matrix in [YcbcrMatrix::Bt601, YcbcrMatrix::Bt709, YcbcrMatrix::Bt2020] {
+            spaces.push(WorkingSpace::Ycbcr(Ycbcr { matrix }));
+        }
+        // Saturated, so that a channel copied into the others would show
+        let colors = [
+            [white, 0.02 * white, 0.],
+            [0.05 * white, 0.3 * white, 0.9 * white],
+        ];
+        let pixels: Vec<f32> = colors
+            .iter()
+            .flat_map(|[r, g, b]| [*r, *g, *b, 1.])
+            .collect();
+        for space in spaces.iter() {
+            let texture = Texture2d::with_format(
+                facade,
+                RawImage2d::from_raw_rgba(pixels.clone(), (colors.len() as u32, 1)),
+                UncompressedFloatFormat::F32F32F32F32,
+                MipmapsOption::NoMipmap,
+            )
+            .unwrap();
+            color_space.rgb_to_working(&texture, Precision::Full, space, &transfer);
+            color_space.working_to_rgb(&texture, Precision::Full, space, &transfer);
+            for (rgb, back) in colors.iter().zip(&read_rgba_f32(&texture)[0]) {
+                let back = [back.0, back.1, back.2];
+                for (a, b) in rgb.iter().zip(back.iter()) {
+                    assert!(
+                        (a - b).abs() < 1e-3 * white,
+                        "{:?}: {:?} {:?}",
+                        space,
+                        rgb,
+                        back
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_lab_white() {
+        let transfer = TransferSettings::default();
+        let xyz = apply(
+            WorkingSpace::Lab.forward(&transfer),
+            [transfer.sdr_white; 3],
+        );
+        for c in xyz {
+            assert!((c - 1.).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_dkl_luminance_axis() {
+        let transfer = TransferSettings::default();
+        let forward = WorkingSpace::Dkl.forward(&transfer);
+        let background = apply(forward, [transfer.sdr_white; 3]);
+        let brighter = apply(forward, [2. * transfer.sdr_white; 3]);
+        for (c, expected) in background.iter().zip([0., 0., 0.]) {
+            assert!((c - expected).abs() < 1e-4);
+        }
+        for (c, expected) in brighter.iter().zip([1., 0., 0.]) {
+            assert!((c - expected).abs() < 1e-4);
+        }
+    }
+}
@@ -2,50 +2,103 @@
 
 #define LOCAL_SIZE 64
 
-layout (local_size_x = LOCAL_SIZE, local_size_y = 1, local_size_z = 1) in;
+#define SPACE_AFFINE 0
+#define SPACE_ICTCP 1
+#define SPACE_LAB 2
 
-layout (binding = 0, rgba32f) uniform image2D image;
+layout (local_size_x = LOCAL_SIZE, local_size_y = 1, local_size_z = 1) in;
 
+layout (binding = 0, IMAGE_FORMAT) uniform image2D image;
 
+// Mode 0 goes from RGB to the working space, mode 1 back.
+// For SPACE_AFFINE the matrix and offset are the whole conversion, for SPACE_ICTCP they convert
+// between RGB and LMS, for SPACE_LAB between RGB and XYZ relative to white.
+uniform int space;
+uniform mat3 matrix;
+uniform vec3 offset;
 
+// Decode the working signal to absolute luminance around the conversion
+uniform bool decode;
+uniform int transfer;
+uniform float transfer_param;
+uniform float sdr_white;
 
 uniform uint mode;
 
+// BT.2100
+mat3 lms_to_ictcp() {
+  return transpose(mat3(2048.0, 2048.0, 0.0,
+			6610.0, -13613.0, 7003.0,
+			17933.0, -17390.0, -543.0)) / 4096.0;
+}
+
+vec3 pq_encode(vec3 lms) {
+  lms /= PQ_PEAK;
+  return vec3(inverse_eotf(lms.x, TF_PQ, 0.0), inverse_eotf(lms.y, TF_PQ, 0.0),
+	      inverse_eotf(lms.z, TF_PQ, 0.0));
+}
+
+vec3 pq_decode(vec3 pq) {
+  return vec3(eotf(pq.x, TF_PQ, 0.0), eotf(pq.y, TF_PQ, 0.0), eotf(pq.z, TF_PQ, 0.0)) * PQ_PEAK;
+}
+
+float lab_f(float t) {
+  const float d = 6.0 / 29.0;
+  return t > d * d * d ? pow(t, 1.0 / 3.0) : t / (3.0 * d * d) + 4.0 / 29.0;
+}
+
+float lab_f_inv(float f) {
+  const float d = 6.0 / 29.0;
+  return f > d ? f * f * f : 3.0 * d * d * (f - 4.0 / 29.0);
+}
+
+// L*, a* and b* divided by 100
+vec3 xyz_to_lab(vec3 xyz) {
+  vec3 f = vec3(lab_f(xyz.x), lab_f(xyz.y), lab_f(xyz.z));
+  return vec3(1.16 * f.y - 0.16, 5.0 * (f.x - f.y), 2.0 * (f.y - f.z));
+}
+
+vec3 lab_to_xyz(vec3 lab) {
+  float fy = (lab.x + 0.16) / 1.16;
+  return vec3(lab_f_inv(fy + lab.y / 5.0), lab_f_inv(fy), lab_f_inv(fy - lab.z / 2.0));
+}
+
 void main()
 {
   ivec2 imgSize = imageSize(image);
-  
+
   ivec2 pixel_coord = ivec2(gl_WorkGroupID.x*LOCAL_SIZE + gl_LocalInvocationID.x, gl_WorkGroupID.y);
   if (pixel_coord.x >= imgSize.x) {
     return;
   }
-  
-  // BT.709
-  mat4 RGBtoYCbCr = mat4(0.2126, 0.7152, 0.0722, 0.0,
-			 -0.1146, -0.3854, 0.5, 0.0,
-			 0.5, -0.4542, -0.0458, 0.0,
-			 0.0, 0.0, 0.0, 1.0);
-
-  mat4 YCbCrtoRGB = mat4 (1.0, 0.0, 1.5748, 0.0,
-			  1.0, -0.1873, -0.4681, 0.0,
-			  1.0, 1.8556, 0.0, 0.0,
-			  0.0, 0.0, 0.0, 1.0);
-  
+
   vec4 color = imageLoad(image, pixel_coord);
-  
+
   switch (mode)
     {
     case 0:
       {
-	color = RGBtoYCbCr * color;
+	vec3 rgb = decode ? eotf_rgb(color.rgb, transfer, transfer_param, sdr_white) : color.rgb;
+	if (space == SPACE_ICTCP) {
+	  color.rgb = lms_to_ictcp() * pq_encode(matrix * rgb);
+	} else if (space == SPACE_LAB) {
+	  color.rgb = xyz_to_lab(matrix * rgb);
+	} else {
+	  color.rgb = matrix * rgb + offset;
+	}
 	break;
       }
     case 1:
       {
-	color = YCbCrtoRGB * color;
-	// FIXME
-	color.b = color.r;
-	color.g = color.r;
+	vec3 rgb;
+	if (space == SPACE_ICTCP) {
+	  rgb = matrix * pq_decode(inverse(lms_to_ictcp()) * color.rgb);
+	} else if (space == SPACE_LAB) {
+	  rgb = matrix * lab_to_xyz(color.rgb);
+	} else {
+	  rgb = matrix * color.rgb + offset;
+	}
+	color.rgb = decode ? inverse_eotf_rgb(rgb, transfer, transfer_param, sdr_white) : rgb;
 	break;
       }
     }
@@ -6,6 +6,17 @@ pub struct Csf {
     pub k: f32,
 }
 
+impl Default for Csf {
+    fn default() -> Self {
+        Self {
+            a: 1.787,
+            ω: 7.22,
+            σ: 2.2,
+            k: 0.71,
+        }
+    }
+}
+
 impl Csf {
     pub fn apply(&self, f: f32) -> f32 {
         self.a * ((-f / self.ω).exp() - self.k * (-(f / self.σ).powi(2)).exp())
@@ -0,0 +1,36 @@
+use gstreamer::prelude::*;
+
+mod imp;
+
+glib::wrapper! {
+    /// GL filter that adapts RGBA video for a target viewing distance.
+    pub struct CsfContrast(ObjectSubclass<imp::CsfContrast>)
+        @extends gstreamer_gl::GLFilter, gstreamer_gl::GLBaseFilter,
+            gstreamer_base::BaseTransform, gstreamer::Element, gstreamer::Object;
+}
+
+/// Registers `csfcontrast` with a plugin, or with the application when `plugin` is `None`.
+pub fn register(plugin: Option<&gstreamer::Plugin>) -> Result<(), glib::BoolError> {
+    gstreamer::Element::register(
+        plugin,
+        "csfcontrast",
+        gstreamer::Rank::None,
+        CsfContrast::static_type(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register() {
+        gstreamer::init().unwrap();
+        register(None).unwrap();
+        let element = gstreamer::ElementFactory::make("csfcontrast", None).unwrap();
+        assert!(element.is::<CsfContrast>());
+
+        element.set_property("distance", 1500f32);
+        assert_eq!(element.property::<f32>("distance"), 1500.);
+    }
+}
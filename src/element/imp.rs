@@ -0,0 +1,354 @@
+use std::{
+    rc::Rc,
+    sync::{Mutex, OnceLock},
+};
+
+use glium::{
+    backend::{Backend, Context, Facade},
+    debug::DebugCallbackBehavior,
+    texture::{Dimensions, MipmapsOption, UncompressedFloatFormat},
+    uniform, Surface, SwapBuffersError, Texture2d,
+};
+use gstreamer::{prelude::*, subclass::prelude::*};
+use gstreamer_base::subclass::{prelude::*, BaseTransformMode};
+use gstreamer_gl::{
+    prelude::*,
+    subclass::{prelude::*, GLFilterMode},
+    GLContext,
+};
+
+use crate::{
+    calibration::DisplayCalibration,
+    color_space::WorkingSpace,
+    csf::Csf,
+    fft::AllocationError,
+    image_shader::ImageShader,
+    output::{Framebuffer, OutputStage},
+    precision::Precision,
+    processor::{AdaptParams, Processor},
+    profiler::GpuProfiler,
+    transfer::{with_transfer_functions, ProcessingDomain, TransferSettings},
+    viewing::Viewing,
+};
+
+#[derive(Clone, Debug, Default)]
+struct Settings {
+    viewing: Viewing,
+    csf: Csf,
+}
+
+// glium on a GStreamer GL context. The context is only ever current on its own GL thread.
+struct GstBackend(GLContext);
+
+unsafe impl Backend for GstBackend {
+    fn swap_buffers(&self) -> Result<(), SwapBuffersError> {
+        Ok(())
+    }
+
+    unsafe fn get_proc_address(&self, symbol: &str) -> *const std::os::raw::c_void {
+        self.0.proc_address(symbol) as *const _
+    }
+
+    fn get_framebuffer_dimensions(&self) -> (u32, u32) {
+        (1, 1)
+    }
+
+    fn is_current(&self) -> bool {
+        GLContext::current().as_ref() == Some(&self.0)
+    }
+
+    unsafe fn make_current(&self) {
+        let _ = self.0.activate(true);
+    }
+}
+
+// Everything glium owns, created, used and dropped on the thread of the private context
+struct Gl {
+    facade: Rc<Context>,
+    decode: ImageShader,
+    processor: Processor,
+    output: OutputStage,
+    profiler: GpuProfiler,
+    linear: Option<Texture2d>,
+}
+
+// Only touched from inside `GLContext::thread_add` of the private context
+struct GlState(Gl);
+
+unsafe impl Send for GlState {}
+
+// glium caches GL state, so it gets a context of its own which shares textures with the
+// pipeline's instead of running on the pipeline's context behind the other elements' backs
+struct State {
+    context: GLContext,
+    gl: GlState,
+}
+
+/// `csfcontrast`: the adaptation of the viewer as a GL filter on sRGB encoded RGBA textures.
+#[derive(Default)]
+pub struct CsfContrast {
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for CsfContrast {
+    const NAME: &'static str = "GstCsfContrast";
+    type Type = super::CsfContrast;
+    type ParentType = gstreamer_gl::GLFilter;
+}
+
+// Lengths in millimetres and their descriptions
+const LENGTHS: [(&str, &str); 4] = [
+    ("screen-width", "Width of the screen the video fills"),
+    ("screen-height", "Height of the screen the video fills"),
+    ("distance", "Distance the video is adapted from"),
+    ("target-distance", "Distance the video is adapted for"),
+];
+
+const CSF_PARAMETERS: [(&str, &str); 4] = [
+    ("csf-a", "Amplitude of the contrast sensitivity function"),
+    (
+        "csf-omega",
+        "Decay frequency of the contrast sensitivity function",
+    ),
+    (
+        "csf-sigma",
+        "Width of the low frequency dip of the contrast sensitivity function",
+    ),
+    (
+        "csf-k",
+        "Depth of the low frequency dip of the contrast sensitivity function",
+    ),
+];
+
+impl Settings {
+    fn field(&mut self, name: &str) -> &mut f32 {
+        match name {
+            "screen-width" => &mut self.viewing.screen_dims_mm.x,
+            "screen-height" => &mut self.viewing.screen_dims_mm.y,
+            "distance" => &mut self.viewing.screen_distance_mm,
+            "target-distance" => &mut self.viewing.target_distance_mm,
+            "csf-a" => &mut self.csf.a,
+            "csf-omega" => &mut self.csf.ω,
+            "csf-sigma" => &mut self.csf.σ,
+            "csf-k" => &mut self.csf.k,
+            _ => unreachable!("Unknown property {}", name),
+        }
+    }
+}
+
+impl ObjectImpl for CsfContrast {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: OnceLock<Vec<glib::ParamSpec>> = OnceLock::new();
+        PROPERTIES.get_or_init(|| {
+            let mut defaults = Settings::default();
+            let flags = glib::ParamFlags::READWRITE | gstreamer::PARAM_FLAG_MUTABLE_PLAYING;
+            LENGTHS
+                .iter()
+                .map(|(name, blurb)| (name, blurb, 1., 1e6))
+                .chain(
+                    CSF_PARAMETERS
+                        .iter()
+                        .map(|(name, blurb)| (name, blurb, -1e3, 1e3)),
+                )
+                .map(|(name, blurb, min, max)| {
+                    let default = *defaults.field(name);
+                    glib::ParamSpecFloat::new(name, name, blurb, min, max, default, flags)
+                })
+                .collect()
+        })
+    }
+
+    fn set_property(
+        &self,
+        _obj: &Self::Type,
+        _id: usize,
+        value: &glib::Value,
+        pspec: &glib::ParamSpec,
+    ) {
+        let mut settings = self.settings.lock().unwrap();
+        *settings.field(pspec.name()) = value.get().expect("type checked upstream");
+    }
+
+    fn property(&self, _obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        let mut settings = self.settings.lock().unwrap();
+        settings.field(pspec.name()).to_value()
+    }
+}
+
+impl GstObjectImpl for CsfContrast {}
+
+impl ElementImpl for CsfContrast {
+    fn metadata() -> Option<&'static gstreamer::subclass::ElementMetadata> {
+        static METADATA: OnceLock<gstreamer::subclass::ElementMetadata> = OnceLock::new();
+        Some(METADATA.get_or_init(|| {
+            gstreamer::subclass::ElementMetadata::new(
+                "CSF contrast adaptation",
+                "Filter/Effect/Video",
+                "Preserves perceived contrast across viewing distances using the contrast \
+                 sensitivity function",
+                env!("CARGO_PKG_NAME"),
+            )
+        }))
+    }
+}
+
+impl BaseTransformImpl for CsfContrast {
+    const MODE: BaseTransformMode = BaseTransformMode::NeverInPlace;
+    const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+    const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+}
+
+impl GLBaseFilterImpl for CsfContrast {
+    fn gl_stop(&self, filter: &Self::Type) {
+        if let Some(State { context, gl }) = self.state.lock().unwrap().take() {
+            context.thread_add(move |_| drop(gl));
+        }
+        self.parent_gl_stop(filter)
+    }
+}
+
+impl GLFilterImpl for CsfContrast {
+    const MODE: GLFilterMode = GLFilterMode::Texture;
+
+    fn filter_texture(
+        &self,
+        _filter: &Self::Type,
+        input: &gstreamer_gl::GLMemory,
+        output: &gstreamer_gl::GLMemory,
+    ) -> Result<(), gstreamer::LoggableError> {
+        let pipeline_context = input.context();
+        let mut state = self.state.lock().unwrap();
+        if state.is_none() {
+            *state = Some(State::new(pipeline_context)?);
+        }
+        let State { context, gl } = state.as_mut().unwrap();
+        let settings = self.settings.lock().unwrap().clone();
+
+        // The private context may only read what the pipeline has finished drawing
+        finish(pipeline_context);
+        let (width, height) = (input.texture_width() as u32, input.texture_height() as u32);
+        let (input, output) = (input.texture_id(), output.texture_id());
+        let mut result = Ok(());
+        context.thread_add(|_| result = gl.0.process(&settings, input, output, width, height));
+        result.map_err(|err| gstreamer::loggable_error!(gstreamer::CAT_RUST, "{}", err))
+    }
+}
+
+// Blocks until the GL commands issued so far on `context` have completed. It must be current.
+fn finish(context: &GLContext) {
+    let address = context.proc_address("glFinish");
+    if address != 0 {
+        let gl_finish: extern "system" fn() = unsafe { std::mem::transmute(address) };
+        gl_finish();
+    }
+}
+
+impl State {
+    fn new(pipeline_context: &GLContext) -> Result<Self, gstreamer::LoggableError> {
+        let context = GLContext::new(&pipeline_context.display());
+        context
+            .create(Some(pipeline_context))
+            .map_err(|err| gstreamer::loggable_error!(gstreamer::CAT_RUST, "{}", err))?;
+        let mut gl = None;
+        context.thread_add(|context| {
+            gl = Gl::new(context.clone()).map(GlState);
+        });
+        match gl {
+            Some(gl) => Ok(Self { context, gl }),
+            None => Err(gstreamer::loggable_error!(
+                gstreamer::CAT_RUST,
+                "The GL context cannot run the adaptation"
+            )),
+        }
+    }
+}
+
+impl Gl {
+    fn new(context: GLContext) -> Option<Self> {
+        let facade =
+            unsafe { Context::new(GstBackend(context), false, DebugCallbackBehavior::Ignore) }
+                .ok()?;
+        Some(Self {
+            decode: ImageShader::new(
+                &facade,
+                &with_transfer_functions(include_str!("../gstreamer/copy_frag.glsl")),
+            ),
+            processor: Processor::new(&facade),
+            output: OutputStage::new(&facade, Framebuffer::Srgb),
+            profiler: GpuProfiler::disabled(),
+            linear: None,
+            facade,
+        })
+    }
+
+    // Texture rows keep their order through every stage, so nothing is flipped
+    fn process(
+        &mut self,
+        settings: &Settings,
+        input: u32,
+        output: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<(), AllocationError> {
+        let facade: &dyn Facade = &self.facade;
+        let wrap = |id| unsafe {
+            Texture2d::from_id(
+                facade,
+                UncompressedFloatFormat::U8U8U8U8,
+                id,
+                false,
+                MipmapsOption::NoMipmap,
+                Dimensions::Texture2d { width, height },
+            )
+        };
+        let (input, output) = (wrap(input), wrap(output));
+        if self.linear.as_ref().map(|t| t.dimensions()) != Some((width, height)) {
+            self.linear = Some(Texture2d::empty_with_format(
+                facade,
+                UncompressedFloatFormat::F32F32F32F32,
+                MipmapsOption::NoMipmap,
+                width,
+                height,
+            )?);
+        }
+        let linear = self.linear.as_ref().unwrap();
+
+        let transfer = TransferSettings {
+            follow_stream: false,
+            ..Default::default()
+        };
+        self.decode.draw(
+            &mut linear.as_surface(),
+            &uniform! {
+                tex: &input,
+                transfer: transfer.input.shader_id(),
+                transfer_param: transfer.input.shader_param(),
+                sdr_white: transfer.sdr_white,
+                linearize: transfer.domain == ProcessingDomain::Linear,
+            },
+        );
+
+        let viewing = &settings.viewing;
+        let params = AdaptParams {
+            csf: settings.csf.clone(),
+            pixels_per_visual_degree: viewing
+                .pixels_per_vd(width as f32, viewing.screen_distance_mm),
+            target_pixels_per_visual_degree: viewing
+                .pixels_per_vd(width as f32, viewing.target_distance_mm),
+            precision: Precision::Full,
+            working_space: WorkingSpace::default(),
+            transfer,
+            calibration: DisplayCalibration::default(),
+        };
+        let adapted = self
+            .processor
+            .process(facade, linear, &params, &mut self.profiler)?;
+        self.output
+            .draw(&mut output.as_surface(), adapted.orig(), &transfer);
+        // The pipeline's context reads the output as soon as this returns
+        self.facade.finish();
+        Ok(())
+    }
+}
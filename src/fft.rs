@@ -1,26 +1,57 @@
-use std::rc::Rc;
+use std::{fmt, rc::Rc};
 
 use glium::{
     backend::Facade,
+    buffer::BufferCreationError,
     implement_uniform_block,
-    program::ComputeShader,
-    texture::InternalFormat,
+    texture::{InternalFormat, TextureCreationError},
     uniform,
     uniforms::{self, UniformBuffer},
     Surface, Texture2d,
 };
 
+use crate::precision::{Precision, PrecisionShader};
+
+/// The GPU could not hold the textures or buffers for an image, most likely because the image is
+/// larger than a texture may be.
+#[derive(Debug)]
+pub enum AllocationError {
+    Texture(TextureCreationError),
+    Buffer(BufferCreationError),
+}
+
+impl fmt::Display for AllocationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AllocationError::Texture(err) => write!(f, "Could not allocate a texture: {:?}", err),
+            AllocationError::Buffer(err) => write!(f, "Could not allocate a buffer: {:?}", err),
+        }
+    }
+}
+
+impl std::error::Error for AllocationError {}
+
+impl From<TextureCreationError> for AllocationError {
+    fn from(err: TextureCreationError) -> Self {
+        AllocationError::Texture(err)
+    }
+}
+
+impl From<BufferCreationError> for AllocationError {
+    fn from(err: BufferCreationError) -> Self {
+        AllocationError::Buffer(err)
+    }
+}
+
 pub struct Fft {
-    shader: Rc<ComputeShader>,
+    shader: Rc<PrecisionShader>,
     texture: Option<FftTexture>,
 }
 
 impl Fft {
     pub fn new(facade: &dyn Facade) -> Self {
         Self {
-            shader: Rc::new(
-                ComputeShader::from_source(facade, include_str!("fft/comp.glsl")).unwrap(),
-            ),
+            shader: Rc::new(PrecisionShader::new(facade, include_str!("fft/comp.glsl"))),
             texture: None,
         }
     }
@@ -28,19 +59,29 @@ impl Fft {
         &'a mut self,
         facade: &dyn Facade,
         texture: &Texture2d,
-    ) -> &'a FftTexture {
+        precision: Precision,
+    ) -> Result<&'a FftTexture, AllocationError> {
         if self.texture.is_none()
             || self.texture.as_ref().unwrap().orig.dimensions() != texture.dimensions()
+            || self.texture.as_ref().unwrap().precision != precision
         {
-            self.texture = Some(FftTexture::new(facade, self.shader.clone(), &texture));
-        } else {
-            texture.as_surface().fill(
-                &self.texture.as_ref().unwrap().orig.as_surface(),
-                uniforms::MagnifySamplerFilter::Nearest,
-            );
+            self.texture = Some(FftTexture::new(
+                facade,
+                self.shader.clone(),
+                &texture,
+                precision,
+            )?);
         }
+        texture.as_surface().fill(
+            &self.texture.as_ref().unwrap().orig.as_surface(),
+            uniforms::MagnifySamplerFilter::Nearest,
+        );
+
+        Ok(self.texture.as_ref().unwrap())
+    }
 
-        self.texture.as_ref().unwrap()
+    pub fn texture(&self) -> Option<&FftTexture> {
+        self.texture.as_ref()
     }
 
     // No idea what this function does.
@@ -85,15 +126,23 @@ implement_uniform_block!(
 );
 
 pub struct FftTexture {
-    fft: Rc<ComputeShader>,
+    fft: Rc<PrecisionShader>,
+    precision: Precision,
     orig: Texture2d,
     real: Texture2d,
     imag: Texture2d,
+    // Kept on the CPU as well so dispatch sizes never require reading the buffer back
+    dims: ImgInfo,
     img_info: UniformBuffer<ImgInfo>,
 }
 
 impl FftTexture {
-    fn new(facade: &dyn Facade, fft: Rc<ComputeShader>, orig: &Texture2d) -> Self {
+    fn new(
+        facade: &dyn Facade,
+        fft: Rc<PrecisionShader>,
+        orig: &Texture2d,
+        precision: Precision,
+    ) -> Result<Self, AllocationError> {
         let (width, height) = orig.dimensions();
         let fft_dims = (width.next_power_of_two(), height.next_power_of_two());
         let clz = (Fft::clz(fft_dims.0) + 1, Fft::clz(fft_dims.1) + 1);
@@ -111,58 +160,58 @@ impl FftTexture {
 
         let orig = Texture2d::empty_with_format(
             facade,
-            glium::texture::UncompressedFloatFormat::F32F32F32F32,
+            precision.texture_format(),
             glium::texture::MipmapsOption::NoMipmap,
             width,
             height,
-        )
-        .unwrap();
+        )?;
 
         let real = Texture2d::empty_with_format(
             facade,
-            glium::texture::UncompressedFloatFormat::F32F32F32F32,
+            precision.texture_format(),
             glium::texture::MipmapsOption::NoMipmap,
             fft_dims.0,
             fft_dims.1,
-        )
-        .unwrap();
+        )?;
         let imag = Texture2d::empty_with_format(
             facade,
-            glium::texture::UncompressedFloatFormat::F32F32F32F32,
+            precision.texture_format(),
             glium::texture::MipmapsOption::NoMipmap,
             fft_dims.0,
             fft_dims.1,
-        )
-        .unwrap();
+        )?;
 
-        let img_info = UniformBuffer::new(facade, img_info).unwrap();
+        let dims = img_info;
+        let img_info = UniformBuffer::new(facade, img_info)?;
 
-        Self {
+        Ok(Self {
             fft,
+            precision,
             orig,
             real,
             imag,
+            dims,
             img_info,
-        }
+        })
     }
 
     fn invoke(&self, stage: u32, work_groups: u32) {
         let input_unit = self
             .orig()
-            .image_unit(uniforms::ImageUnitFormat::RGBA32F)
+            .image_unit(self.precision.image_unit_format())
             .unwrap();
 
         let real_unit = self
             .real()
-            .image_unit(uniforms::ImageUnitFormat::RGBA32F)
+            .image_unit(self.precision.image_unit_format())
             .unwrap();
 
         let imag_unit = self
             .imag()
-            .image_unit(uniforms::ImageUnitFormat::RGBA32F)
+            .image_unit(self.precision.image_unit_format())
             .unwrap();
 
-        self.fft.execute(
+        self.fft.get(self.precision).execute(
             uniform! {inputImage: input_unit, realPart: real_unit, imagPart: imag_unit, img_info: &self.img_info, stage: stage},
             work_groups,
             1,
@@ -171,13 +220,25 @@ impl FftTexture {
     }
 
     pub fn fft(&self, _facade: &dyn Facade) {
-        self.invoke(0, self.img_info.read().unwrap().output_width as u32);
-        self.invoke(1, self.img_info.read().unwrap().output_height as u32);
+        self.invoke(0, self.dims.output_width as u32);
+        self.invoke(1, self.dims.output_height as u32);
     }
 
     pub fn ifft(&self, _facade: &dyn Facade) {
-        self.invoke(2, self.img_info.read().unwrap().output_height as u32);
-        self.invoke(3, self.img_info.read().unwrap().output_width as u32);
+        self.invoke(2, self.dims.output_height as u32);
+        self.invoke(3, self.dims.output_width as u32);
+    }
+
+    pub fn precision(&self) -> Precision {
+        self.precision
+    }
+
+    /// Size of the spectrum: the input padded to powers of two.
+    pub fn padded_dimensions(&self) -> (u32, u32) {
+        (
+            self.dims.output_width as u32,
+            self.dims.output_height as u32,
+        )
     }
 
     pub fn orig<'b>(&'b self) -> &'b Texture2d {
@@ -220,3 +281,44 @@ fn format_channels(format: &InternalFormat) -> u32 {
         } => 4,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use glium::texture::{MipmapsOption, RawImage2d, UncompressedFloatFormat};
+
+    use super::*;
+    use crate::{headless::HeadlessContext, precision::read_rgba_f32};
+
+    #[test]
+    #[ignore = "needs a GL driver"]
+    fn test_half_round_trip() {
+        let context = HeadlessContext::new();
+        let facade = &context.facade;
+        // The sum over all pixels is far above the largest half float, 65504
+        let value = 1000f32;
+        let (width, height) = (256, 128);
+        let input = Texture2d::with_format(
+            facade,
+            RawImage2d::from_raw_rgba(vec![value; (width * height * 4) as usize], (width, height)),
+            UncompressedFloatFormat::F32F32F32F32,
+            MipmapsOption::NoMipmap,
+        )
+        .unwrap();
+
+        let mut fft = Fft::new(facade);
+        let spectrum = fft
+            .process_texture(facade, &input, Precision::Half)
+            .unwrap();
+        spectrum.fft(facade);
+        let dc = read_rgba_f32(spectrum.real())[0][0];
+        assert!((dc.0 - value).abs() < 1., "{:?}", dc);
+
+        spectrum.ifft(facade);
+        for row in read_rgba_f32(spectrum.orig()) {
+            for pixel in row {
+                assert!((pixel.0 - value).abs() < 1., "{:?}", pixel);
+                assert!((pixel.3 - value).abs() < 1., "{:?}", pixel);
+            }
+        }
+    }
+}
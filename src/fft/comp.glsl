@@ -11,9 +11,9 @@
 
 layout (local_size_x = WORKGROUP_SIZE_X, local_size_y = 1, local_size_z = 1) in;
 
-layout (binding = 0, rgba32f) uniform image2D inputImage;
-layout (binding = 1, rgba32f) uniform image2D realPart;
-layout (binding = 2, rgba32f) uniform image2D imagPart;
+layout (binding = 0, IMAGE_FORMAT) uniform image2D inputImage;
+layout (binding = 1, IMAGE_FORMAT) uniform image2D realPart;
+layout (binding = 2, IMAGE_FORMAT) uniform image2D imagPart;
 
 layout(std430, binding = 3) readonly buffer img_info {
 	int input_width;
@@ -124,15 +124,15 @@ void load_stage0(int btid, int g_offset, int scanline)
     }
 }
 
-void store_stage0(int btid, int g_offset, int scanline)
+void store_stage0(int btid, int g_offset, int scanline, float N)
 {
 	for(int i = btid * 2; i < btid * 2 + g_offset * 2; i++)
     {   
 		ivec2 idx = ivec2(i, scanline);
 
-		imageStore(realPart, idx, pixel_buffer_real[i - btid * 2]);
+		imageStore(realPart, idx, pixel_buffer_real[i - btid * 2] * N);
 		
-		imageStore(imagPart, idx, pixel_buffer_imag[i - btid * 2]);
+		imageStore(imagPart, idx, pixel_buffer_imag[i - btid * 2] * N);
     }
 }
 
@@ -215,6 +215,9 @@ void main()
 			int N = output_width;
 			int g_offset = N / 2 / WORKGROUP_SIZE_X;
 			int btid = int(g_offset * gl_LocalInvocationID.x);
+			// The forward transform divides by the size so that no coefficient exceeds the
+			// largest value of the image, which keeps half precision spectra in range
+			float divisor = 1.0 / float(N);
 			
 			load_stage0(btid, g_offset, int(gl_WorkGroupID.x));
 			sync();
@@ -230,7 +233,7 @@ void main()
 				load_from_cache(btid, g_offset, channel);
 			}
 			
-			store_stage0(btid, g_offset, int(gl_WorkGroupID.x));
+			store_stage0(btid, g_offset, int(gl_WorkGroupID.x), divisor);
 
 			sync();
 
@@ -242,7 +245,7 @@ void main()
 			int N = output_height;
 			int g_offset = N / 2 / WORKGROUP_SIZE_X;
 			int btid = int(g_offset * gl_LocalInvocationID.x);
-			float divisor = (stage == 2) ? 1.0 / float(N) : 1.0;
+			float divisor = (stage == 1) ? 1.0 / float(N) : 1.0;
 			bool is_inverse = stage == 2;
 			
 			load_stage1_2(btid, g_offset, int(gl_WorkGroupID.x));
@@ -270,7 +273,7 @@ void main()
 			int N = output_width;
 			int g_offset = N / 2 / WORKGROUP_SIZE_X;
 			int btid = int(g_offset * gl_LocalInvocationID.x);
-			float divisor = 1.0 / float(N);
+			float divisor = 1.0;
 
 			load_stage3(btid, g_offset, int(gl_WorkGroupID.x));
 			sync();
@@ -0,0 +1,314 @@
+use glium::{
+    backend::Facade,
+    uniforms::{AsUniformValue, Uniforms, UniformsStorage},
+};
+
+use crate::{fft::FftTexture, precision::PrecisionShader, processor::AdaptParams};
+
+mod filters;
+
+pub use filters::{BandPass, GaussianLowPass, HighBoost};
+
+/// Describes how the bins of a spectrum map to spatial frequencies on screen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpectrumInfo {
+    pub width: u32,
+    pub height: u32,
+    pub pixels_per_visual_degree: f32,
+}
+
+impl SpectrumInfo {
+    pub fn new(spectrum: &FftTexture, pixels_per_visual_degree: f32) -> Self {
+        let (width, height) = spectrum.real().dimensions();
+        Self {
+            width,
+            height,
+            pixels_per_visual_degree,
+        }
+    }
+
+    // Computes the coordinates around N/2 if the cartesian quadrants are diagonally swapped
+    pub fn fft_shift(&self, x: u32, y: u32) -> (f32, f32) {
+        let shift = |p: u32, n: u32| {
+            if p < n / 2 {
+                p as f32
+            } else {
+                p as f32 - n as f32
+            }
+        };
+        (shift(x, self.width), shift(y, self.height))
+    }
+
+    /// Dimensionless frequency of the given bin, or `None` at the origin.
+    pub fn frequency(&self, x: u32, y: u32) -> Option<f32> {
+        let (fx, fy) = self.fft_shift(x, y);
+        if fx == 0. && fy == 0. {
+            return None;
+        }
+        let (half_w, half_h) = (self.width as f32 / 2., self.height as f32 / 2.);
+
+        // Intersect line from origin to coord with rectangle
+        let vert = (fx * half_h / fy).hypot(half_h);
+        let horz = half_w.hypot(fy * half_w / fx);
+
+        // Get the total length of this slice over the rectangle
+        let n = 2. * vert.min(horz);
+
+        Some(fx.hypot(fy) / n)
+    }
+
+    pub fn cycles_per_degree(&self, x: u32, y: u32) -> Option<f32> {
+        self.frequency(x, y)
+            .map(|f| f * self.pixels_per_visual_degree)
+    }
+}
+
+/// Luma spectrum read back to the CPU, stored row-major with `SpectrumInfo`'s dimensions.
+#[derive(Clone, Debug, Default)]
+pub struct CpuSpectrum {
+    pub real: Vec<f32>,
+    pub imag: Vec<f32>,
+}
+
+/// A filter that runs between `FftTexture::fft` and `FftTexture::ifft`.
+///
+/// Filters are radially symmetric gains on the luma channel. `gain` is the CPU reference
+/// which the GPU dispatch has to agree with.
+pub trait FrequencyFilter {
+    fn name(&self) -> &str;
+
+    fn gain(&self, cpd: f32, info: &SpectrumInfo) -> f32;
+
+    fn dispatch(&mut self, facade: &dyn Facade, spectrum: &FftTexture, info: &SpectrumInfo);
+
+    /// Takes the viewing conditions and display of the next dispatch, for filters that follow
+    /// them.
+    fn set_params(&mut self, _params: &AdaptParams) {}
+
+    fn apply_cpu(&self, spectrum: &mut CpuSpectrum, info: &SpectrumInfo) {
+        for y in 0..info.height {
+            for x in 0..info.width {
+                if let Some(cpd) = info.cycles_per_degree(x, y) {
+                    let i = (y * info.width + x) as usize;
+                    let gain = self.gain(cpd, info);
+                    spectrum.real[i] *= gain;
+                    spectrum.imag[i] *= gain;
+                }
+            }
+        }
+    }
+
+    fn draw_ui(&mut self, _ui: &mut egui::Ui) {}
+}
+
+struct ChainEntry {
+    enabled: bool,
+    filter: Box<dyn FrequencyFilter>,
+}
+
+/// Runs several filters one after another over the same spectrum.
+#[derive(Default)]
+pub struct FilterChain {
+    entries: Vec<ChainEntry>,
+}
+
+impl FilterChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, filter: Box<dyn FrequencyFilter>, enabled: bool) {
+        self.entries.push(ChainEntry { enabled, filter });
+    }
+}
+
+impl FrequencyFilter for FilterChain {
+    fn name(&self) -> &str {
+        "Filter chain"
+    }
+
+    fn gain(&self, cpd: f32, info: &SpectrumInfo) -> f32 {
+        self.entries
+            .iter()
+            .filter(|e| e.enabled)
+            .map(|e| e.filter.gain(cpd, info))
+            .product()
+    }
+
+    fn dispatch(&mut self, facade: &dyn Facade, spectrum: &FftTexture, info: &SpectrumInfo) {
+        for entry in self.entries.iter_mut().filter(|e| e.enabled) {
+            entry.filter.dispatch(facade, spectrum, info);
+        }
+    }
+
+    fn set_params(&mut self, params: &AdaptParams) {
+        for entry in self.entries.iter_mut() {
+            entry.filter.set_params(params);
+        }
+    }
+
+    fn draw_ui(&mut self, ui: &mut egui::Ui) {
+        for entry in self.entries.iter_mut() {
+            ui.checkbox(&mut entry.enabled, entry.filter.name());
+            if entry.enabled {
+                ui.indent(entry.filter.name().to_string(), |ui| {
+                    entry.filter.draw_ui(ui);
+                });
+            }
+        }
+    }
+}
+
+/// Compute shader made of the shared spectrum header and a filter body defining `gain`.
+pub struct FilterShader {
+    shader: PrecisionShader,
+}
+
+impl FilterShader {
+    pub fn new(facade: &dyn Facade, body: &str) -> Self {
+        let source = format!("{}\n{}", include_str!("frequency_filter/header.glsl"), body);
+        Self {
+            shader: PrecisionShader::new(facade, &source),
+        }
+    }
+
+    pub fn execute<'n, T, R>(
+        &self,
+        spectrum: &FftTexture,
+        info: &SpectrumInfo,
+        uniforms: UniformsStorage<'n, T, R>,
+    ) where
+        T: AsUniformValue,
+        R: Uniforms,
+    {
+        let format = spectrum.precision().image_unit_format();
+        let real_unit = spectrum.real().image_unit(format).unwrap();
+        let imag_unit = spectrum.imag().image_unit(format).unwrap();
+        self.shader.get(spectrum.precision()).execute(
+            uniforms
+                .add("realPart", real_unit)
+                .add("imagPart", imag_unit)
+                .add("pixels_per_visual_degree", info.pixels_per_visual_degree),
+            info.width / 64 + 1,
+            info.height,
+            1,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glium::{
+        texture::{MipmapsOption, RawImage2d, UncompressedFloatFormat},
+        Texture2d,
+    };
+
+    use super::*;
+    use crate::{
+        calibration::DisplayCalibration,
+        csf::Csf,
+        fft::Fft,
+        headless::HeadlessContext,
+        perception_adapter::PerceptionAdapter,
+        precision::{read_rgba_f32, Precision},
+        transfer::TransferSettings,
+    };
+
+    fn info() -> SpectrumInfo {
+        SpectrumInfo {
+            width: 8,
+            height: 4,
+            pixels_per_visual_degree: 10.,
+        }
+    }
+
+    #[test]
+    fn test_fft_shift() {
+        assert_eq!(info().fft_shift(0, 0), (0., 0.));
+        assert_eq!(info().fft_shift(3, 1), (3., 1.));
+        assert_eq!(info().fft_shift(4, 2), (-4., -2.));
+        assert_eq!(info().fft_shift(7, 3), (-1., -1.));
+    }
+
+    #[test]
+    fn test_frequency() {
+        let info = info();
+        assert_eq!(info.frequency(0, 0), None);
+        // Nyquist along either axis is half a cycle per pixel
+        assert_eq!(info.frequency(4, 0), Some(0.5));
+        assert_eq!(info.frequency(0, 2), Some(0.5));
+        assert_eq!(info.cycles_per_degree(2, 0), Some(2.5));
+    }
+
+    // Runs every filter on the GPU and compares the result with its CPU reference
+    #[test]
+    #[ignore = "needs a GL driver"]
+    fn test_dispatch_matches_gain() {
+        let context = HeadlessContext::new();
+        let facade = &context.facade;
+        let (width, height) = (64, 32);
+        let pixels = (0..width * height * 4)
+            .map(|i| ((i * 7919) % 101) as f32)
+            .collect();
+        let input = Texture2d::with_format(
+            facade,
+            RawImage2d::from_raw_rgba(pixels, (width, height)),
+            UncompressedFloatFormat::F32F32F32F32,
+            MipmapsOption::NoMipmap,
+        )
+        .unwrap();
+        let params = AdaptParams {
+            csf: Csf::default(),
+            pixels_per_visual_degree: 40.,
+            target_pixels_per_visual_degree: 20.,
+            precision: Precision::Full,
+            working_space: Default::default(),
+            transfer: TransferSettings::default(),
+            calibration: DisplayCalibration::default(),
+        };
+        let filters: Vec<Box<dyn FrequencyFilter>> = vec![
+            Box::new(PerceptionAdapter::new(facade)),
+            Box::new(GaussianLowPass::new(facade)),
+            Box::new(HighBoost::new(facade)),
+            Box::new(BandPass::new(facade)),
+        ];
+        let luma = |texture: &Texture2d| -> Vec<f32> {
+            read_rgba_f32(texture)
+                .into_iter()
+                .flatten()
+                .map(|p| p.0)
+                .collect()
+        };
+
+        let mut fft = Fft::new(facade);
+        for mut filter in filters {
+            filter.set_params(&params);
+            let spectrum = fft
+                .process_texture(facade, &input, Precision::Full)
+                .unwrap();
+            spectrum.fft(facade);
+            let info = SpectrumInfo::new(spectrum, params.pixels_per_visual_degree);
+            let mut expected = CpuSpectrum {
+                real: luma(spectrum.real()),
+                imag: luma(spectrum.imag()),
+            };
+            filter.apply_cpu(&mut expected, &info);
+            filter.dispatch(facade, spectrum, &info);
+            let parts = [
+                (luma(spectrum.real()), &expected.real),
+                (luma(spectrum.imag()), &expected.imag),
+            ];
+            for (actual, expected) in parts {
+                for (a, e) in actual.iter().zip(expected.iter()) {
+                    assert!(
+                        (a - e).abs() <= 1e-3 * (1. + e.abs()),
+                        "{}: {} against {}",
+                        filter.name(),
+                        a,
+                        e
+                    );
+                }
+            }
+        }
+    }
+}
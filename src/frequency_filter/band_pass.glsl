@@ -0,0 +1,6 @@
+uniform float low_cpd;
+uniform float high_cpd;
+
+float gain(float cpd) {
+  return (cpd >= low_cpd && cpd <= high_cpd) ? 1.0 : 0.0;
+}
@@ -0,0 +1,156 @@
+use glium::{backend::Facade, uniform};
+
+use super::{FilterShader, FrequencyFilter, SpectrumInfo};
+use crate::fft::FftTexture;
+
+fn gaussian(cpd: f32, sigma_cpd: f32) -> f32 {
+    (-0.5 * (cpd / sigma_cpd).powi(2)).exp()
+}
+
+/// Attenuates frequencies above `sigma_cpd` with a Gaussian roll-off.
+pub struct GaussianLowPass {
+    shader: FilterShader,
+    pub sigma_cpd: f32,
+}
+
+impl GaussianLowPass {
+    pub fn new(facade: &dyn Facade) -> Self {
+        Self {
+            shader: FilterShader::new(facade, include_str!("low_pass.glsl")),
+            sigma_cpd: 8.,
+        }
+    }
+}
+
+impl FrequencyFilter for GaussianLowPass {
+    fn name(&self) -> &str {
+        "Gaussian low-pass"
+    }
+
+    fn gain(&self, cpd: f32, _info: &SpectrumInfo) -> f32 {
+        gaussian(cpd, self.sigma_cpd)
+    }
+
+    fn dispatch(&mut self, _facade: &dyn Facade, spectrum: &FftTexture, info: &SpectrumInfo) {
+        self.shader
+            .execute(spectrum, info, uniform! { sigma_cpd: self.sigma_cpd });
+    }
+
+    fn draw_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("σ");
+            ui.add(
+                egui::DragValue::new(&mut self.sigma_cpd)
+                    .speed(0.1)
+                    .clamp_range(0.01..=100.),
+            );
+            ui.label("cpd");
+        });
+    }
+}
+
+/// Unsharp masking in the frequency domain: boosts what a Gaussian low-pass would remove.
+pub struct HighBoost {
+    shader: FilterShader,
+    pub sigma_cpd: f32,
+    pub amount: f32,
+}
+
+impl HighBoost {
+    pub fn new(facade: &dyn Facade) -> Self {
+        Self {
+            shader: FilterShader::new(facade, include_str!("high_boost.glsl")),
+            sigma_cpd: 4.,
+            amount: 0.5,
+        }
+    }
+}
+
+impl FrequencyFilter for HighBoost {
+    fn name(&self) -> &str {
+        "Unsharp high-boost"
+    }
+
+    fn gain(&self, cpd: f32, _info: &SpectrumInfo) -> f32 {
+        1. + self.amount * (1. - gaussian(cpd, self.sigma_cpd))
+    }
+
+    fn dispatch(&mut self, _facade: &dyn Facade, spectrum: &FftTexture, info: &SpectrumInfo) {
+        self.shader.execute(
+            spectrum,
+            info,
+            uniform! { sigma_cpd: self.sigma_cpd, amount: self.amount },
+        );
+    }
+
+    fn draw_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("σ");
+            ui.add(
+                egui::DragValue::new(&mut self.sigma_cpd)
+                    .speed(0.1)
+                    .clamp_range(0.01..=100.),
+            );
+            ui.label("cpd");
+            ui.label("Amount");
+            ui.add(egui::DragValue::new(&mut self.amount).speed(0.01));
+        });
+    }
+}
+
+/// Keeps only the frequencies between `low_cpd` and `high_cpd`, plus the mean.
+pub struct BandPass {
+    shader: FilterShader,
+    pub low_cpd: f32,
+    pub high_cpd: f32,
+}
+
+impl BandPass {
+    pub fn new(facade: &dyn Facade) -> Self {
+        Self {
+            shader: FilterShader::new(facade, include_str!("band_pass.glsl")),
+            low_cpd: 2.,
+            high_cpd: 8.,
+        }
+    }
+}
+
+impl FrequencyFilter for BandPass {
+    fn name(&self) -> &str {
+        "Band-pass"
+    }
+
+    fn gain(&self, cpd: f32, _info: &SpectrumInfo) -> f32 {
+        if (self.low_cpd..=self.high_cpd).contains(&cpd) {
+            1.
+        } else {
+            0.
+        }
+    }
+
+    fn dispatch(&mut self, _facade: &dyn Facade, spectrum: &FftTexture, info: &SpectrumInfo) {
+        self.shader.execute(
+            spectrum,
+            info,
+            uniform! { low_cpd: self.low_cpd, high_cpd: self.high_cpd },
+        );
+    }
+
+    fn draw_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("From");
+            ui.add(
+                egui::DragValue::new(&mut self.low_cpd)
+                    .speed(0.1)
+                    .clamp_range(0.0..=self.high_cpd),
+            );
+            ui.label("to");
+            ui.add(
+                egui::DragValue::new(&mut self.high_cpd)
+                    .speed(0.1)
+                    .clamp_range(self.low_cpd..=100.),
+            );
+            ui.label("cpd");
+        });
+    }
+}
@@ -0,0 +1,63 @@
+#version 450
+
+#define LOCAL_SIZE 64
+
+layout (local_size_x = LOCAL_SIZE, local_size_y = 1, local_size_z = 1) in;
+
+layout (binding = 0, IMAGE_FORMAT) uniform image2D realPart;
+layout (binding = 1, IMAGE_FORMAT) uniform image2D imagPart;
+
+uniform float pixels_per_visual_degree;
+
+// Gain applied to the luma spectrum at the given frequency in cycles per degree.
+// Every filter appends its own definition after this header.
+float gain(float cpd);
+
+// Computes the coordinates around N/2 if the cartesian quadrants are diagonally swapped
+vec2 fftShift(ivec2 pixel_coord, ivec2 fftSize) {
+  vec2 fpixel_coord = vec2(pixel_coord);
+  vec2 adjusted = fpixel_coord - vec2(fftSize/2);
+  vec2 coord =  adjusted + -1 * sign(adjusted + vec2(0.000000000000001))*vec2(fftSize/2) ;
+  return coord;
+}
+
+// Gets dimensionless frequency
+float freq(vec2 fft_coord, ivec2 fftSize) {
+  
+  // Intersect line from origin to coord with rectangle
+  vec2 vertIntersect = vec2(fft_coord.x * (fftSize.y/2.0)/fft_coord.y,fftSize.y/2.0);
+  vec2 horzIntersect = vec2(fftSize.x/2.0, fft_coord.y * (fftSize.x/2.0)/fft_coord.x);
+
+  // Get the total length of this slice over the rectangle
+  float N = 2.0 * min(length(vertIntersect), length(horzIntersect));
+
+  
+  // Frequency is index divided by points
+  return length(fft_coord)/N;
+}
+
+void main() {
+  ivec2 fftSize = imageSize(realPart);
+  ivec2 pixel_coord = ivec2(gl_WorkGroupID.x * LOCAL_SIZE + gl_LocalInvocationID.x, gl_WorkGroupID.y);
+
+  if (pixel_coord.x >= fftSize.x) {
+    return;
+  }
+
+  vec2 fft_coord = fftShift(pixel_coord, fftSize);
+  
+  if (fft_coord == vec2(0.0)) { // Can't get a frequency at the origin
+    return;
+  }
+
+  vec4 real = imageLoad(realPart, pixel_coord);
+  vec4 imag = imageLoad(imagPart, pixel_coord);
+
+  float g = gain(freq(fft_coord, fftSize) * pixels_per_visual_degree);
+
+  real.x = g * real.x;
+  imag.x = g * imag.x;
+  
+  imageStore(realPart, pixel_coord, real);
+  imageStore(imagPart, pixel_coord, imag);
+}
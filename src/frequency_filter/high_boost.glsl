@@ -0,0 +1,7 @@
+uniform float sigma_cpd;
+uniform float amount;
+
+float gain(float cpd) {
+  float x = cpd / sigma_cpd;
+  return 1.0 + amount * (1.0 - exp(-0.5 * x * x));
+}
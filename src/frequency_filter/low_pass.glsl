@@ -0,0 +1,6 @@
+uniform float sigma_cpd;
+
+float gain(float cpd) {
+  float x = cpd / sigma_cpd;
+  return exp(-0.5 * x * x);
+}
@@ -0,0 +1,22 @@
+/// Inserts `text` on the line after the `#version` directive of a shader source.
+pub fn insert_after_version(src: &str, text: &str) -> String {
+    let version_end = src
+        .find("#version")
+        .map(|i| i + src[i..].find('\n').unwrap_or(src.len() - i))
+        .unwrap_or(0);
+    let (version, rest) = src.split_at(version_end);
+    format!("{}\n{}{}", version, text, rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_after_version() {
+        assert_eq!(
+            insert_after_version("// x\n#version 430 core\nvoid main() {}", "#define A 1"),
+            "// x\n#version 430 core\n#define A 1\nvoid main() {}"
+        );
+    }
+}
@@ -1,6 +1,9 @@
 use glium::{backend::Facade, uniform, Surface};
 
-use crate::image_shader::ImageShader;
+use crate::{
+    image_shader::ImageShader,
+    transfer::{with_transfer_functions, ProcessingDomain, TransferSettings},
+};
 
 pub struct Grating {
     image_shader: ImageShader,
@@ -9,22 +12,27 @@ pub struct Grating {
 
 impl Grating {
     pub fn new(facade: &dyn Facade) -> Self {
-        let fragment_src = include_str!("grating/frag.glsl");
-        let image_shader = ImageShader::new(facade, fragment_src);
+        let fragment_src = with_transfer_functions(include_str!("grating/frag.glsl"));
+        let image_shader = ImageShader::new(facade, &fragment_src);
         Self {
             image_shader,
             frequency: 150.0,
         }
     }
 
-    pub fn draw<S>(&mut self, surface: &mut S)
+    pub fn draw<S>(&mut self, surface: &mut S, transfer: &TransferSettings)
     where
         S: Surface,
     {
         self.image_shader.draw(
             surface,
             &uniform! {
-            frequency: self.frequency},
+                frequency: self.frequency,
+                transfer: transfer.input.shader_id(),
+                transfer_param: transfer.input.shader_param(),
+                sdr_white: transfer.sdr_white,
+                linearize: transfer.domain == ProcessingDomain::Linear,
+            },
         );
     }
 
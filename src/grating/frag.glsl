@@ -6,9 +6,17 @@ in vec2 tex_coord;
 out vec4 color;
 uniform float frequency;
 
+uniform int transfer;
+uniform float transfer_param;
+uniform float sdr_white;
+uniform bool linearize;
+
 void main() {
   float x = 2.0 * M_PI * frequency;
   float r = (sin(x*tex_coord.x) + 1.0)/2.0;
-  r = pow(r,2.2);
-  color = vec4(r,r,r,1.0);
+  vec3 rgb = vec3(r);
+  if (linearize) {
+    rgb = eotf_rgb(rgb, transfer, transfer_param, sdr_white);
+  }
+  color = vec4(rgb,1.0);
 }
@@ -1,63 +1,282 @@
-use std::time::Duration;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use glium::{
     backend::Facade,
-    texture::{Dimensions, MipmapsOption, UncompressedFloatFormat},
+    texture::{Dimensions, MipmapsOption, RawImage2d, UncompressedFloatFormat},
+    Surface,
 };
 use gstreamer::prelude::*;
 use gstreamer_gl::{prelude::*, GLContext, GLDisplay};
 
+mod audio;
+mod bus;
 mod context;
-
+mod media_info;
+mod output_stream;
+mod playlist;
+mod portal;
+mod source;
+mod transport;
+
+use audio::AudioSettings;
+use bus::{BusEvent, StreamStatus};
 pub use context::{CtxInfo, SurfaceType};
+use media_info::MediaInfo;
+pub use output_stream::OutputStream;
+pub use playlist::Playlist;
+use portal::ScreenCast;
+pub use source::{
+    capture_devices, CaptureArea, CaptureDevice, CaptureFormat, ScreenCapture, Source,
+};
+use transport::PlaybackState;
+
+use crate::{
+    color_space::{Ycbcr, YcbcrMatrix},
+    image_shader::ImageShader,
+    profiler::{GpuProfiler, Stage},
+    transfer::{
+        with_transfer_functions, Primaries, ProcessingDomain, TransferFunction, TransferSettings,
+    },
+};
 
-use crate::image_shader::ImageShader;
+// How decoded frames reach glium
+enum Upload {
+    /// GL memory of a context shared with glium's
+    Gl {
+        context: GLContext,
+        display: GLDisplay,
+    },
+    /// Frames mapped in system memory and copied into a texture, for installs without the GL
+    /// plugins or contexts that cannot be shared
+    System,
+}
 
 pub struct Gstreamer {
     pipeline: gstreamer::Pipeline,
+    // The bin holding the appsink, whose sink pad sees the decoded stream
+    sink: gstreamer::Element,
     pub appsink: gstreamer_app::AppSink,
-    gl_context: GLContext,
-    texture: Option<glium::Texture2d>,
+    source: Source,
+    // Session of a `ScreenCapture::Portal` source, closed when it is replaced
+    screen_cast: Option<ScreenCast>,
+    upload: Upload,
+    // A new frame is copied into the back texture, in the working signal, while the front one
+    // is being processed. They swap on the next draw, so the copy and the processing of the
+    // previous frame do not depend on each other.
+    textures: [glium::Texture2d; 2],
+    front: usize,
+    // Whether the back texture holds a frame that has not been shown yet
+    back_ready: bool,
+    // Whether the front texture holds a frame of the current source
+    has_frame: bool,
+    status: StreamStatus,
+    media_info: Option<Result<MediaInfo, String>>,
+    discovery: Option<std::sync::mpsc::Receiver<Result<MediaInfo, String>>>,
+    held_frame: Option<gstreamer_video::VideoFrame<gstreamer_video::video_frame::Readable>>,
     copy_shader: ImageShader,
+    stream_colorimetry: Option<gstreamer_video::VideoColorimetry>,
+    playback: Arc<Mutex<PlaybackState>>,
+    paused: bool,
+    // A paused pipeline only delivers the preroll sample
+    preroll: bool,
+    pub playlist: Playlist,
+    audio: AudioSettings,
+    // The next frame, pulled early because its time has not come yet
+    pending: Option<gstreamer::Sample>,
+    // Position to seek to once the pipeline has prerolled, after it was rebuilt for audio
+    resume_at: Option<gstreamer::ClockTime>,
 }
 
-impl Gstreamer {
-    pub fn new(facade: &dyn Facade, ctx_info: &CtxInfo) -> Self {
-        let (pipeline, appsink) =
-            Self::create_pipeline(ctx_info.gl_context.clone(), ctx_info.gl_display.clone());
+// Time between drawing a frame and it reaching the screen, one refresh at 60 Hz
+const PRESENTATION_LATENCY_NS: u64 = 16_666_667;
 
-        let bin = pipeline.upcast::<gstreamer::Bin>();
+// Running time at which a sample is due
+fn running_time(sample: &gstreamer::Sample) -> Option<u64> {
+    let pts = sample.buffer()?.pts()?;
+    let segment = sample.segment()?.downcast_ref::<gstreamer::ClockTime>()?;
+    segment.to_running_time(pts).map(|t| t.nseconds())
+}
 
-        let pipeline = bin.downcast::<gstreamer::Pipeline>().unwrap();
+impl Gstreamer {
+    /// Without a shared context frames are uploaded from system memory.
+    pub fn new(facade: &dyn Facade, ctx_info: Option<&CtxInfo>) -> Self {
+        let source = Source::Uri(String::new());
+        let playback = Arc::new(Mutex::new(PlaybackState::default()));
+        let audio = AudioSettings::default();
+        let mut upload = match ctx_info {
+            Some(ctx_info) => Upload::Gl {
+                context: ctx_info.gl_context.clone(),
+                display: ctx_info.gl_display.clone(),
+            },
+            None => Upload::System,
+        };
+        let (pipeline, sink, appsink) =
+            Self::create_pipeline(None, &mut upload, playback.clone(), &audio).unwrap();
 
-        let copy_shader = ImageShader::new(facade, include_str!("./gstreamer/copy_frag.glsl"));
+        let copy_shader = ImageShader::new(
+            facade,
+            &with_transfer_functions(include_str!("./gstreamer/copy_frag.glsl")),
+        );
 
         let input = Self {
             pipeline,
+            sink,
             appsink,
-            gl_context: ctx_info.gl_context.clone(),
-            texture: None,
+            source,
+            screen_cast: None,
+            upload,
+            textures: [
+                Self::frame_texture(facade, 1, 1),
+                Self::frame_texture(facade, 1, 1),
+            ],
+            front: 0,
+            back_ready: false,
+            has_frame: false,
+            status: StreamStatus::default(),
+            media_info: None,
+            discovery: None,
+            held_frame: None,
             copy_shader,
+            stream_colorimetry: None,
+            playback,
+            paused: false,
+            preroll: false,
+            playlist: Playlist::default(),
+            audio,
+            pending: None,
+            resume_at: None,
         };
 
         input
     }
 
+    // URIs go through playbin, live sources given by their `source_bin` are linked to the
+    // sink by hand. Falls back to system memory when the GL elements are missing.
     fn create_pipeline(
-        gl_context: GLContext,
-        gl_display: GLDisplay,
-    ) -> (gstreamer::Pipeline, gstreamer_app::AppSink) {
-        let (bin, appsink) = Self::create_sink();
+        source_bin: Option<gstreamer::Bin>,
+        upload: &mut Upload,
+        playback: Arc<Mutex<PlaybackState>>,
+        audio: &AudioSettings,
+    ) -> Result<
+        (
+            gstreamer::Pipeline,
+            gstreamer::Element,
+            gstreamer_app::AppSink,
+        ),
+        glib::Error,
+    > {
+        let (bin, appsink) = match Self::create_sink(upload) {
+            Ok(sink) => sink,
+            Err(err) if matches!(upload, Upload::Gl { .. }) => {
+                eprintln!("No GL upload ({}), using system memory", err);
+                *upload = Upload::System;
+                Self::create_sink(upload).map_err(|err| {
+                    glib::Error::new(gstreamer::CoreError::MissingPlugin, &err.to_string())
+                })?
+            }
+            Err(err) => {
+                return Err(glib::Error::new(
+                    gstreamer::CoreError::MissingPlugin,
+                    &err.to_string(),
+                ))
+            }
+        };
 
-        let playbin = gstreamer::ElementFactory::make("playbin", None).unwrap();
-        playbin.set_property_from_str("flags", "video");
+        let playbin = match source_bin {
+            None => {
+                let playbin = gstreamer::ElementFactory::make("playbin", None).unwrap();
+                playbin.set_property_from_str("flags", audio.playbin_flags());
+                playbin.set_property("video-sink", &bin);
 
-        playbin.set_property("video-sink", &bin);
-        playbin.set_property_from_str("flags", "video");
+                // Frames are queued and drawn when their timestamp is due, see `next_sample`
+                appsink.set_sync(false);
+                appsink.set_max_buffers(8);
+                appsink.set_drop(false);
 
-        let playbin = playbin.downcast::<gstreamer::Pipeline>().unwrap();
+                let playbin = playbin.downcast::<gstreamer::Pipeline>().unwrap();
+                audio.apply(&playbin);
+                playbin
+            }
+            Some(source_bin) => {
+                let pipeline = gstreamer::Pipeline::new(None);
+                pipeline.add_many(&[source_bin.upcast_ref(), &bin]).unwrap();
+                source_bin.link(&bin).unwrap();
+                // Show frames as they arrive rather than waiting on the clock
+                appsink.set_sync(false);
+                pipeline
+            }
+        };
+
+        if let Upload::Gl {
+            context: gl_context,
+            display: gl_display,
+        } = upload
+        {
+            Self::share_context(&playbin, gl_context.clone(), gl_display.clone());
+        }
+
+        // Errors of the GL elements make `draw` fall back to system memory
+        let weak_sink = bin.downgrade();
+        let uses_gl = matches!(upload, Upload::Gl { .. });
+        let weak_pipeline = playbin.downgrade();
+        playbin
+            .bus()
+            .unwrap()
+            .add_watch(move |_, msg| {
+                use gstreamer::MessageView;
+
+                let mut event = BusEvent::from_message(msg);
+                match msg.view() {
+                    MessageView::Error(err) => {
+                        eprintln!(
+                            "Error from {:?}: {} ({:?})",
+                            err.src().map(|s| s.path_string()),
+                            err.error(),
+                            err.debug()
+                        );
+                        let in_sink = match (err.src(), weak_sink.upgrade()) {
+                            (Some(src), Some(sink)) => src.has_as_ancestor(&sink),
+                            _ => false,
+                        };
+                        if uses_gl && in_sink {
+                            // Handled by falling back rather than shown
+                            event = None;
+                            playback.lock().unwrap().sink_failed = true;
+                        }
+                    }
+                    MessageView::AsyncDone(_) => playback.lock().unwrap().async_done = true,
+                    MessageView::Eos(_) => {
+                        let mut state = playback.lock().unwrap();
+                        let restarted = state.looping
+                            && weak_pipeline.upgrade().map_or(false, |pipeline| {
+                                transport::seek(&pipeline, state.rate, gstreamer::ClockTime::ZERO)
+                            });
+                        state.eos = !restarted;
+                        if restarted {
+                            event = None;
+                        }
+                    }
+                    _ => (),
+                }
+                if let Some(event) = event {
+                    playback.lock().unwrap().events.push(event);
+                }
+
+                // Tell the mainloop to continue executing this callback.
 
-        playbin.bus().unwrap().set_sync_handler(move |_, msg| {
+                Continue(true)
+            })
+            .expect("Failed to add bus watch");
+
+        Ok((playbin, bin, appsink))
+    }
+
+    // Hands glium's display and context to the GL elements that ask for them
+    fn share_context(pipeline: &gstreamer::Pipeline, gl_context: GLContext, gl_display: GLDisplay) {
+        pipeline.bus().unwrap().set_sync_handler(move |_, msg| {
             if let gstreamer::MessageView::NeedContext(ctxt) = msg.view() {
                 let context_type = ctxt.context_type();
                 if context_type == *gstreamer_gl::GL_DISPLAY_CONTEXT_TYPE {
@@ -88,50 +307,58 @@ impl Gstreamer {
 
             gstreamer::BusSyncReply::Pass
         });
-
-        playbin
-            .bus()
-            .unwrap()
-            .add_watch(move |_, msg| {
-                use gstreamer::MessageView;
-
-                if let MessageView::Error(err) = msg.view() {
-                    eprintln!(
-                        "Error from {:?}: {} ({:?})",
-                        err.src().map(|s| s.path_string()),
-                        err.error(),
-                        err.debug()
-                    );
-                };
-
-                // Tell the mainloop to continue executing this callback.
-
-                Continue(true)
-            })
-            .expect("Failed to add bus watch");
-
-        (playbin, appsink)
     }
 
-    pub fn create_sink() -> (gstreamer::Element, gstreamer_app::AppSink) {
+    fn create_sink(
+        upload: &Upload,
+    ) -> Result<(gstreamer::Element, gstreamer_app::AppSink), glib::BoolError> {
         let bin = gstreamer::Bin::new(Some("bin"));
+        let make = |name| gstreamer::ElementFactory::make(name, None);
 
-        let gl_upload = gstreamer::ElementFactory::make("glupload", None).unwrap();
-
-        let appsink = gstreamer::ElementFactory::make("appsink", Some("app-sink"))
-            .unwrap()
+        let appsink = gstreamer::ElementFactory::make("appsink", Some("app-sink"))?
             .dynamic_cast::<gstreamer_app::AppSink>()
             .unwrap();
 
-        let caps = gstreamer::Caps::builder("video/x-raw")
-            .features(&[&gstreamer_gl::CAPS_FEATURE_MEMORY_GL_MEMORY])
-            //.features(&[&gstreamer_video::CAPS_FEATURE_META_GST_VIDEO_GL_TEXTURE_UPLOAD_META])
-            .field("format", &gstreamer_video::VideoFormat::Rgba.to_str())
-            .field("texture-target", &"2D")
-            //.field("width", &1920)
-            //.field("height", &1080)
-            //.field("framerate", &"25/1")
-            .build();
+        let (caps, mut elements) = match upload {
+            Upload::Gl { .. } => {
+                let caps = gstreamer::Caps::builder("video/x-raw")
+                    .features(&[&gstreamer_gl::CAPS_FEATURE_MEMORY_GL_MEMORY])
+                    //.features(&[&gstreamer_video::CAPS_FEATURE_META_GST_VIDEO_GL_TEXTURE_UPLOAD_META])
+                    // 10 bits are preferred so that HDR streams keep their precision
+                    .field(
+                        "format",
+                        &gstreamer::List::new(&[
+                            &gstreamer_video::VideoFormat::Rgb10a2Le.to_str(),
+                            &gstreamer_video::VideoFormat::Rgba.to_str(),
+                        ]),
+                    )
+                    .field("texture-target", &"2D")
+                    //.field("width", &1920)
+                    //.field("height", &1080)
+                    //.field("framerate", &"25/1")
+                    .build();
+                let gl_flip = make("glvideoflip")?;
+                gl_flip.set_property_from_str("video-direction", "vert");
+                (
+                    caps,
+                    vec![make("glupload")?, make("glcolorconvert")?, gl_flip],
+                )
+            }
+            // `upload_frame` flips the rows instead of glvideoflip
+            Upload::System => {
+                let caps = gstreamer::Caps::builder("video/x-raw")
+                    // videoconvert only picks 16 bits for deeper sources
+                    .field(
+                        "format",
+                        &gstreamer::List::new(&[
+                            &gstreamer_video::VideoFormat::Rgba64Le.to_str(),
+                            &gstreamer_video::VideoFormat::Rgba.to_str(),
+                        ]),
+                    )
+                    .build();
+                (caps, vec![make("videoconvert")?])
+            }
+        };
         appsink.set_caps(Some(&caps));
 
         appsink.set_max_buffers(1);
@@ -139,101 +366,426 @@ impl Gstreamer {
         appsink.set_enable_last_sample(true);
         appsink.set_property("emit-signals", &false);
 
-        let gl_convert = gstreamer::ElementFactory::make("glcolorconvert", None).unwrap();
-        let gl_flip = gstreamer::ElementFactory::make("glvideoflip", None).unwrap();
-
-        gl_flip.set_property_from_str("video-direction", "vert");
+        elements.push(appsink.clone().upcast());
+        let elements: Vec<_> = elements.iter().collect();
+        bin.add_many(&elements)?;
+        gstreamer::Element::link_many(&elements)?;
 
-        {
-            let elements = [
-                &gl_upload,
-                &gl_convert,
-                &gl_flip,
-                appsink.upcast_ref::<gstreamer::Element>(),
-            ];
-
-            bin.add_many(&elements).unwrap();
-
-            gstreamer::Element::link_many(&elements).unwrap();
-        }
         let pad = bin
             .find_unlinked_pad(gstreamer::PadDirection::Sink)
             .unwrap();
-        let ghost_pad = gstreamer::GhostPad::with_target(Some("sink"), &pad).unwrap();
-        bin.add_pad(&ghost_pad).unwrap();
+        let ghost_pad = gstreamer::GhostPad::with_target(Some("sink"), &pad)?;
+        bin.add_pad(&ghost_pad)?;
 
-        (bin.upcast::<gstreamer::Element>(), appsink)
+        Ok((bin.upcast::<gstreamer::Element>(), appsink))
+    }
+
+    /// Appends a URI, or a capture device or test pattern in the syntax of `Source`, to the
+    /// playlist and plays it.
+    pub fn set_uri(&mut self, uri: &str) {
+        match uri.parse() {
+            Ok(source) => {
+                self.playlist.push(source);
+                self.play_entry(self.playlist.entries().len() - 1);
+            }
+            Err(err) => eprintln!("Could not open {}: {}", uri, err),
+        }
     }
 
-    pub fn set_uri(&self, uri: &str) {
+    /// Opens `source`, asking the portal anew for a screen to share.
+    pub fn set_source(&mut self, source: Source) -> Result<(), glib::Error> {
+        self.open(source, false)
+    }
+
+    // Opens the current source again, on the stream picked before for the portal
+    fn reopen(&mut self) -> Result<(), glib::Error> {
+        self.open(self.source.clone(), true)
+    }
+
+    // The current pipeline keeps playing until the new one has been built
+    fn open(&mut self, source: Source, keep_screen_cast: bool) -> Result<(), glib::Error> {
+        println!("Next video: {}", source);
+        let portal = matches!(source, Source::Screen(ScreenCapture::Portal));
+        // A new session, unless the stream picked before is to be used again
+        let screen_cast = if portal && !(keep_screen_cast && self.screen_cast.is_some()) {
+            Some(ScreenCast::open()?)
+        } else {
+            None
+        };
+        let source_bin = match screen_cast.as_ref().or(self.screen_cast.as_ref()) {
+            Some(screen_cast) if portal => Some(screen_cast.bin()?),
+            _ => source
+                .bin()
+                .map_err(|err| glib::Error::new(gstreamer::CoreError::Failed, &err.to_string()))?,
+        };
+        let (pipeline, sink, appsink) = Self::create_pipeline(
+            source_bin,
+            &mut self.upload,
+            self.playback.clone(),
+            &self.audio,
+        )?;
+
         self.pipeline.set_state(gstreamer::State::Null).unwrap();
+        // The watch would otherwise keep the old bus alive
+        let _ = self.pipeline.bus().unwrap().remove_watch();
+        if !portal {
+            self.screen_cast = None;
+        } else if screen_cast.is_some() {
+            self.screen_cast = screen_cast;
+        }
+        if let Source::Uri(uri) = &source {
+            pipeline.set_property("uri", uri);
+        }
+        self.pipeline = pipeline;
+        self.sink = sink;
+        self.appsink = appsink;
+        self.source = source;
+        self.stream_colorimetry = None;
+        self.pending = None;
+        self.resume_at = None;
+        self.back_ready = false;
+        self.has_frame = false;
+        self.status = StreamStatus::default();
+        self.discover();
+        self.paused = false;
+        self.preroll = false;
+        {
+            let mut playback = self.playback.lock().unwrap();
+            playback.eos = false;
+            playback.async_done = false;
+            playback.rate = 1.;
+            playback.events.clear();
+        }
+
+        if self.pipeline.set_state(gstreamer::State::Playing).is_err() {
+            // GL elements that cannot use the shared context fail on the way to playing
+            if matches!(self.upload, Upload::Gl { .. }) {
+                eprintln!("Could not play with GL upload, using system memory");
+                self.upload = Upload::System;
+                return self.reopen();
+            }
+            return Err(glib::Error::new(
+                gstreamer::CoreError::StateChange,
+                &format!("Could not play {}", self.source),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether frames arrive as GL textures rather than through system memory.
+    pub fn uses_gl_memory(&self) -> bool {
+        matches!(self.upload, Upload::Gl { .. })
+    }
 
-        println!("Next video: {}", uri);
-        self.pipeline.set_property("uri", uri);
+    // Reopens the source with system memory once the GL elements have reported an error
+    fn handle_sink_failure(&mut self) {
+        let failed = std::mem::take(&mut self.playback.lock().unwrap().sink_failed);
+        if failed && self.uses_gl_memory() {
+            eprintln!("GL upload failed, using system memory");
+            self.upload = Upload::System;
+            if let Err(err) = self.reopen() {
+                eprintln!("Could not reopen {}: {}", self.source, err);
+            }
+        }
+    }
 
-        self.pipeline.set_state(gstreamer::State::Playing).unwrap();
+    pub fn source(&self) -> &Source {
+        &self.source
     }
 
-    pub fn draw(&mut self, facade: &dyn Facade) -> &'_ glium::Texture2d {
-        let sample = self.appsink.pull_sample();
+    /// The frame copied on the previous draw, or `None` while the current source has not
+    /// delivered one. Frames are shown one draw late so that their copy overlaps processing.
+    pub fn draw(
+        &mut self,
+        facade: &dyn Facade,
+        transfer: &mut TransferSettings,
+        profiler: &mut GpuProfiler,
+    ) -> Option<&'_ glium::Texture2d> {
+        // Runs the bus watches, there is no other main loop
+        while glib::MainContext::default().iteration(false) {}
+        self.handle_bus_events();
+        self.handle_sink_failure();
+        self.handle_eos();
+        self.handle_async_done();
+
+        // The frame copied on the previous draw is processed while the next one is copied
+        if self.back_ready {
+            self.front = 1 - self.front;
+            self.back_ready = false;
+            self.has_frame = true;
+        }
 
-        match sample {
-            Err(_) => (),
-            Ok(sample) => {
-                let buffer = sample.buffer_owned().unwrap();
-                if let Some(sync) = buffer.meta::<gstreamer_gl::GLSyncMeta>() {
-                    sync.wait(&self.gl_context);
-                }
+        // Samples without a buffer or video caps are skipped
+        let frame = self.next_sample().and_then(|sample| {
+            let info = sample
+                .caps()
+                .and_then(|caps| gstreamer_video::VideoInfo::from_caps(caps).ok())?;
+            Some((sample.buffer_owned()?, info))
+        });
+        if let Some((buffer, info)) = frame {
+            let colorimetry = self
+                .source_colorimetry()
+                .unwrap_or_else(|| info.colorimetry());
+            if transfer.follow_stream {
+                Self::apply_colorimetry(&colorimetry, transfer);
+            }
+            self.stream_colorimetry = Some(colorimetry);
 
-                let info = sample
-                    .caps()
-                    .and_then(|caps| gstreamer_video::VideoInfo::from_caps(caps).ok())
-                    .unwrap();
-
-                if let Ok(frame) =
-                    gstreamer_video::VideoFrame::from_buffer_readable_gl(buffer, &info)
-                {
-                    let texture_id = frame.texture_id(0);
-                    let width = frame.width();
-                    let height = frame.height();
-                    if let Some(id) = texture_id {
-                        let new_texture = unsafe {
+            let new_texture = match &self.upload {
+                Upload::Gl { context, .. } => {
+                    if let Some(sync) = buffer.meta::<gstreamer_gl::GLSyncMeta>() {
+                        sync.wait(context);
+                    }
+                    let frame =
+                        gstreamer_video::VideoFrame::from_buffer_readable_gl(buffer, &info).ok();
+                    let texture = frame.as_ref().and_then(|frame| {
+                        let format = match info.format() {
+                            gstreamer_video::VideoFormat::Rgb10a2Le => {
+                                UncompressedFloatFormat::U10U10U10U2
+                            }
+                            _ => UncompressedFloatFormat::U8U8U8U8,
+                        };
+                        let (width, height) = (frame.width(), frame.height());
+                        let id = frame.texture_id(0)?;
+                        Some(unsafe {
                             glium::texture::Texture2d::from_id(
                                 facade,
-                                UncompressedFloatFormat::U8U8U8U8,
+                                format,
                                 id,
                                 false,
                                 MipmapsOption::NoMipmap,
                                 Dimensions::Texture2d { width, height },
                             )
-                        };
+                        })
+                    });
+                    // Keep the GStreamer texture alive until the copy below has surely executed
+                    self.held_frame = frame;
+                    texture
+                }
+                Upload::System => gstreamer_video::VideoFrame::from_buffer_readable(buffer, &info)
+                    .ok()
+                    .map(|frame| Self::upload_frame(facade, &frame)),
+            };
 
-                        if self.texture.is_none()
-                            || self.texture.as_mut().unwrap().dimensions() != (width, height)
-                        {
-                            self.texture.replace(
-                                glium::texture::Texture2d::empty_with_format(
-                                    facade,
-                                    UncompressedFloatFormat::U16U16U16U16,
-                                    MipmapsOption::NoMipmap,
-                                    width,
-                                    height,
-                                )
-                                .unwrap(),
-                            );
-                        }
-                        let mut surface = self.texture.as_ref().unwrap().as_surface();
-                        self.copy_shader.draw(
-                            &mut surface,
-                            &glium::uniform! {
-                            tex: new_texture,
-                                        },
-                        );
-                    }
+            if let Some(new_texture) = new_texture {
+                let (width, height) = new_texture.dimensions();
+                let back = 1 - self.front;
+                if self.textures[back].dimensions() != (width, height) {
+                    self.textures[back] = Self::frame_texture(facade, width, height);
+                }
+                let mut surface = self.textures[back].as_surface();
+                profiler.begin(Stage::Upload);
+                self.copy_shader.draw(
+                    &mut surface,
+                    &glium::uniform! {
+                        tex: &new_texture,
+                        transfer: transfer.input.shader_id(),
+                        transfer_param: transfer.input.shader_param(),
+                        sdr_white: transfer.sdr_white,
+                        linearize: transfer.domain == ProcessingDomain::Linear,
+                    },
+                );
+                profiler.end(Stage::Upload);
+                self.back_ready = true;
+            }
+        }
+
+        // Nothing until the current source has delivered a frame
+        self.has_frame.then(|| &self.textures[self.front])
+    }
+
+    // Never waits on the decoder: if no new frame is due the previous one is shown again.
+    //
+    // Frames of playbin sources are chosen by timestamp against the pipeline clock, which the
+    // audio sink drives, so that they reach the screen in sync with the sound. Live sources
+    // show the newest frame.
+    fn next_sample(&mut self) -> Option<gstreamer::Sample> {
+        if self.preroll {
+            let sample = self.appsink.try_pull_preroll(gstreamer::ClockTime::ZERO);
+            self.preroll = sample.is_none();
+            self.pending = None;
+            return sample;
+        }
+        // The clock keeps running while paused, which would play out the queued frames
+        if self.paused {
+            return None;
+        }
+        let now = match (self.pipeline.clock(), self.pipeline.base_time()) {
+            (Some(clock), Some(base_time)) if !self.source.is_live() => clock
+                .time()
+                .and_then(|time| time.nseconds().checked_sub(base_time.nseconds())),
+            _ => None,
+        };
+        let deadline = match now {
+            Some(now) => now + PRESENTATION_LATENCY_NS,
+            None => return self.appsink.try_pull_sample(gstreamer::ClockTime::ZERO),
+        };
+
+        // Late frames are skipped in favour of the last one that is due
+        let mut due = None;
+        while let Some(sample) = self
+            .pending
+            .take()
+            .or_else(|| self.appsink.try_pull_sample(gstreamer::ClockTime::ZERO))
+        {
+            match running_time(&sample) {
+                Some(time) if time > deadline => {
+                    self.pending = Some(sample);
+                    break;
+                }
+                _ => due = Some(sample),
+            }
+        }
+        due
+    }
+
+    // Colorimetry of the decoded stream, before it is converted to RGB
+    fn source_colorimetry(&self) -> Option<gstreamer_video::VideoColorimetry> {
+        self.stream_video_info().map(|info| info.colorimetry())
+    }
+
+    /// YCbCr encoding signalled by the current stream.
+    pub fn stream_ycbcr(&self) -> Option<Ycbcr> {
+        use gstreamer_video::VideoColorMatrix;
+
+        let colorimetry = self.stream_colorimetry.as_ref()?;
+        let matrix = match colorimetry.matrix() {
+            VideoColorMatrix::Bt601 => YcbcrMatrix::Bt601,
+            VideoColorMatrix::Bt709 => YcbcrMatrix::Bt709,
+            VideoColorMatrix::Bt2020 => YcbcrMatrix::Bt2020,
+            _ => return None,
+        };
+        Some(Ycbcr { matrix })
+    }
+
+    /// Takes the transfer function and primaries the stream signals, if it signals any.
+    pub(crate) fn apply_colorimetry(
+        colorimetry: &gstreamer_video::VideoColorimetry,
+        transfer: &mut TransferSettings,
+    ) {
+        use gstreamer_video::{VideoColorPrimaries, VideoTransferFunction};
+
+        match colorimetry.transfer() {
+            VideoTransferFunction::Smpte2084 => transfer.input = TransferFunction::Pq,
+            VideoTransferFunction::AribStdB67 => {
+                if !matches!(transfer.input, TransferFunction::Hlg { .. }) {
+                    transfer.input = TransferFunction::Hlg { peak: 1000. };
                 }
             }
+            VideoTransferFunction::Srgb => transfer.input = TransferFunction::Srgb,
+            VideoTransferFunction::Bt709
+            | VideoTransferFunction::Bt601
+            | VideoTransferFunction::Bt202010
+            | VideoTransferFunction::Bt202012 => {
+                if !matches!(transfer.input, TransferFunction::Bt1886 { .. }) {
+                    transfer.input = TransferFunction::Bt1886 { black: 0. };
+                }
+            }
+            _ => (),
         }
+        match colorimetry.primaries() {
+            VideoColorPrimaries::Bt709 => transfer.input_primaries = Primaries::Bt709,
+            VideoColorPrimaries::Bt2020 => transfer.input_primaries = Primaries::Bt2020,
+            _ => (),
+        }
+    }
+
+    // Copies a mapped frame into a new texture, bottom row first as GL expects
+    fn upload_frame(
+        facade: &dyn Facade,
+        frame: &gstreamer_video::VideoFrame<gstreamer_video::video_frame::Readable>,
+    ) -> glium::Texture2d {
+        let (width, height) = (frame.width(), frame.height());
+        let deep = frame.format() == gstreamer_video::VideoFormat::Rgba64Le;
+        let row_bytes = width as usize * if deep { 8 } else { 4 };
+        let stride = frame.plane_stride()[0] as usize;
+        let data = frame.plane_data(0).unwrap();
+        let bytes: Vec<u8> = (0..height as usize)
+            .rev()
+            .flat_map(|y| &data[y * stride..y * stride + row_bytes])
+            .copied()
+            .collect();
+        let texture = if deep {
+            let samples: Vec<u16> = bytes
+                .chunks_exact(2)
+                .map(|s| u16::from_le_bytes([s[0], s[1]]))
+                .collect();
+            glium::texture::Texture2d::with_format(
+                facade,
+                RawImage2d::from_raw_rgba(samples, (width, height)),
+                UncompressedFloatFormat::U16U16U16U16,
+                MipmapsOption::NoMipmap,
+            )
+        } else {
+            glium::texture::Texture2d::with_format(
+                facade,
+                RawImage2d::from_raw_rgba(bytes, (width, height)),
+                UncompressedFloatFormat::U8U8U8U8,
+                MipmapsOption::NoMipmap,
+            )
+        };
+        texture.unwrap()
+    }
+
+    // Float, as frames are stored as absolute luminance in the linear domain. Half floats reach
+    // 65504 cd/m² in steps finer than those of the 10-bit sources, at half the bandwidth.
+    fn frame_texture(facade: &dyn Facade, width: u32, height: u32) -> glium::Texture2d {
+        let texture = glium::texture::Texture2d::empty_with_format(
+            facade,
+            UncompressedFloatFormat::F16F16F16F16,
+            MipmapsOption::NoMipmap,
+            width,
+            height,
+        )
+        .unwrap();
+        texture.as_surface().clear_color(0., 0., 0., 1.);
+        texture
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::HeadlessContext;
+
+    #[test]
+    fn test_preroll() {
+        gstreamer::init().unwrap();
+        let mut upload = Upload::System;
+        let description = "videotestsrc num-buffers=1 ! video/x-raw,width=64,height=48";
+        let source_bin = gstreamer::parse_bin_from_description(description, true).unwrap();
+        let (pipeline, _, appsink) = Gstreamer::create_pipeline(
+            Some(source_bin),
+            &mut upload,
+            Arc::default(),
+            &AudioSettings::default(),
+        )
+        .unwrap();
+        pipeline.set_state(gstreamer::State::Paused).unwrap();
+        let sample = appsink
+            .try_pull_preroll(gstreamer::ClockTime::from_seconds(5))
+            .expect("no preroll sample");
+        let info = gstreamer_video::VideoInfo::from_caps(sample.caps().unwrap()).unwrap();
+        assert_eq!((info.width(), info.height()), (64, 48));
+        pipeline.set_state(gstreamer::State::Null).unwrap();
+    }
 
-        self.texture.as_ref().unwrap()
+    #[test]
+    #[ignore = "needs a GL driver"]
+    fn test_failed_open_keeps_pipeline() {
+        gstreamer::init().unwrap();
+        let context = HeadlessContext::new();
+        let mut input = Gstreamer::new(&context.facade, None);
+        let playing = Source::TestPattern("ball".to_string());
+        input.set_source(playing.clone()).unwrap();
+        let pipeline = input.pipeline.clone();
+
+        let broken = Source::TestPattern("no-such-pattern".to_string());
+        assert!(input.set_source(broken).is_err());
+        assert_eq!(input.pipeline, pipeline);
+        assert_eq!(input.source, playing);
+        let (_, state, _) = pipeline.state(gstreamer::ClockTime::from_seconds(5));
+        assert_eq!(state, gstreamer::State::Playing);
+        pipeline.set_state(gstreamer::State::Null).unwrap();
     }
 }
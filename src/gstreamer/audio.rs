@@ -0,0 +1,86 @@
+use gstreamer::prelude::*;
+
+use super::{Gstreamer, Source};
+
+/// Sound of `playbin` sources.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AudioSettings {
+    pub enabled: bool,
+    /// Linear, 1 is unchanged
+    pub volume: f64,
+    pub mute: bool,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            volume: 1.,
+            mute: false,
+        }
+    }
+}
+
+impl AudioSettings {
+    pub(super) fn playbin_flags(&self) -> &'static str {
+        if self.enabled {
+            "video+audio"
+        } else {
+            "video"
+        }
+    }
+
+    pub(super) fn apply(&self, playbin: &gstreamer::Pipeline) {
+        playbin.set_property("volume", self.volume);
+        playbin.set_property("mute", self.mute);
+    }
+}
+
+impl Gstreamer {
+    /// Volume and mute apply at once, enabling or disabling audio reopens the source where
+    /// it was.
+    pub fn set_audio(&mut self, audio: AudioSettings) {
+        let reopen = audio.enabled != self.audio.enabled;
+        self.audio = audio;
+        // Only playbin plays sound
+        if !matches!(&self.source, Source::Uri(uri) if !uri.is_empty()) {
+            return;
+        }
+        if !reopen {
+            audio.apply(&self.pipeline);
+            return;
+        }
+        let position = self.position();
+        if let Err(err) = self.set_source(self.source.clone()) {
+            eprintln!("Could not reopen {}: {}", self.source, err);
+            return;
+        }
+        // Seeking needs the rebuilt pipeline to have prerolled, which the bus tells
+        self.resume_at = position;
+    }
+
+    // Seeks the rebuilt pipeline back to where the old one was once it has prerolled
+    pub(super) fn handle_async_done(&mut self) {
+        let done = std::mem::take(&mut self.playback.lock().unwrap().async_done);
+        if done {
+            if let Some(position) = self.resume_at.take() {
+                self.seek(position);
+            }
+        }
+    }
+
+    pub fn draw_audio_ui(&mut self, ui: &mut egui::Ui) {
+        let mut audio = self.audio;
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut audio.enabled, "Audio");
+            ui.add_enabled(audio.enabled, egui::Checkbox::new(&mut audio.mute, "Mute"));
+        });
+        ui.add_enabled(
+            audio.enabled,
+            egui::Slider::new(&mut audio.volume, 0.0..=1.0).text("Volume"),
+        );
+        if audio != self.audio {
+            self.set_audio(audio);
+        }
+    }
+}
@@ -0,0 +1,276 @@
+use std::{collections::VecDeque, fmt};
+
+use gstreamer::prelude::*;
+
+use super::Gstreamer;
+
+/// What the bus of a pipeline reported that the user should know about.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BusEvent {
+    Error {
+        element: Option<String>,
+        message: String,
+        debug: Option<String>,
+    },
+    Warning {
+        element: Option<String>,
+        message: String,
+        debug: Option<String>,
+    },
+    /// A decoder, demuxer or source the stream needs is not installed
+    MissingPlugin {
+        description: String,
+        installer_detail: Option<String>,
+    },
+    /// How full the buffer of a network stream is, in percent
+    Buffering(i32),
+    /// New state of the pipeline itself
+    StateChanged(gstreamer::State),
+    Eos,
+}
+
+impl BusEvent {
+    /// `None` for messages of no interest, including state changes of the elements inside the
+    /// pipeline.
+    pub(super) fn from_message(msg: &gstreamer::Message) -> Option<Self> {
+        use gstreamer::MessageView;
+
+        let element = || msg.src().map(|src| src.path_string().to_string());
+        match msg.view() {
+            MessageView::Error(err) => Some(BusEvent::Error {
+                element: element(),
+                message: err.error().to_string(),
+                debug: err.debug().map(|debug| debug.to_string()),
+            }),
+            MessageView::Warning(warning) => Some(BusEvent::Warning {
+                element: element(),
+                message: warning.error().to_string(),
+                debug: warning.debug().map(|debug| debug.to_string()),
+            }),
+            MessageView::Element(_) if gstreamer_pbutils::is_missing_plugin_message(msg) => {
+                Some(BusEvent::MissingPlugin {
+                    description: gstreamer_pbutils::missing_plugin_message_get_description(msg)
+                        .to_string(),
+                    installer_detail:
+                        gstreamer_pbutils::missing_plugin_message_get_installer_detail(msg)
+                            .map(|detail| detail.to_string()),
+                })
+            }
+            MessageView::Buffering(buffering) => Some(BusEvent::Buffering(buffering.percent())),
+            // Only the pipeline has no parent
+            MessageView::StateChanged(change)
+                if msg.src().map_or(false, |src| src.parent().is_none()) =>
+            {
+                Some(BusEvent::StateChanged(change.current()))
+            }
+            MessageView::Eos(_) => Some(BusEvent::Eos),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for BusEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BusEvent::Error {
+                element, message, ..
+            } => match element {
+                Some(element) => write!(f, "Error from {}: {}", element, message),
+                None => write!(f, "Error: {}", message),
+            },
+            BusEvent::Warning {
+                element, message, ..
+            } => match element {
+                Some(element) => write!(f, "Warning from {}: {}", element, message),
+                None => write!(f, "Warning: {}", message),
+            },
+            BusEvent::MissingPlugin { description, .. } => {
+                write!(f, "Missing plugin: {}", description)
+            }
+            BusEvent::Buffering(percent) => write!(f, "Buffering {}%", percent),
+            BusEvent::StateChanged(state) => write!(f, "{:?}", state),
+            BusEvent::Eos => write!(f, "End of stream"),
+        }
+    }
+}
+
+/// The current pipeline as its bus described it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StreamStatus {
+    pub state: Option<gstreamer::State>,
+    /// Set while a network stream buffers, below 100 %
+    pub buffering: Option<i32>,
+    /// The first error or missing plugin, after which the stream plays no further
+    pub failure: Option<BusEvent>,
+    pub ended: bool,
+    /// Errors, warnings and missing plugins, the latest last
+    pub log: VecDeque<BusEvent>,
+}
+
+const LOG_LENGTH: usize = 20;
+
+impl StreamStatus {
+    fn apply(&mut self, event: BusEvent) {
+        match &event {
+            BusEvent::Error { .. } | BusEvent::MissingPlugin { .. } => {
+                if self.failure.is_none() {
+                    self.failure = Some(event.clone());
+                }
+            }
+            BusEvent::Warning { .. } => (),
+            BusEvent::Buffering(percent) => {
+                self.buffering = (*percent < 100).then(|| *percent);
+                return;
+            }
+            BusEvent::StateChanged(state) => {
+                self.state = Some(*state);
+                return;
+            }
+            BusEvent::Eos => {
+                self.ended = true;
+                return;
+            }
+        }
+        if self.log.len() == LOG_LENGTH {
+            self.log.pop_front();
+        }
+        self.log.push_back(event);
+    }
+}
+
+impl Gstreamer {
+    // Takes in what the bus watch queued. Streams that are not live pause while they buffer.
+    pub(super) fn handle_bus_events(&mut self) {
+        let events = std::mem::take(&mut self.playback.lock().unwrap().events);
+        for event in events {
+            if let BusEvent::Buffering(percent) = event {
+                if !self.source.is_live() && !self.paused {
+                    let state = if percent < 100 {
+                        gstreamer::State::Paused
+                    } else {
+                        gstreamer::State::Playing
+                    };
+                    let _ = self.pipeline.set_state(state);
+                }
+            }
+            self.status.apply(event);
+        }
+    }
+
+    pub fn draw_status_ui(&mut self, ui: &mut egui::Ui) {
+        let mut retry = false;
+        let mut skip = false;
+        let status = &self.status;
+        ui.label(match status.state {
+            Some(state) => format!("State: {:?}", state),
+            None => "State: starting".to_string(),
+        });
+        if let Some(percent) = status.buffering {
+            ui.add(
+                egui::ProgressBar::new(percent as f32 / 100.)
+                    .text(format!("Buffering {}%", percent)),
+            );
+        }
+        if let Some(failure) = status.failure.as_ref() {
+            let label = ui.colored_label(egui::Color32::RED, failure.to_string());
+            match failure {
+                BusEvent::Error {
+                    debug: Some(debug), ..
+                } => {
+                    label.on_hover_text(debug);
+                }
+                BusEvent::MissingPlugin {
+                    installer_detail: Some(detail),
+                    ..
+                } => {
+                    label.on_hover_text(format!("Package installers look for {}", detail));
+                }
+                _ => (),
+            }
+            let has_next = self
+                .playlist
+                .current()
+                .map_or(false, |i| i + 1 < self.playlist.entries().len());
+            ui.horizontal(|ui| {
+                retry = ui.button("Retry").clicked();
+                if has_next {
+                    skip = ui.button("Skip to next").clicked();
+                }
+            });
+        } else if status.ended {
+            ui.label("End of stream");
+            retry = ui.button("Play again").clicked();
+        } else if !self.has_frame {
+            ui.label("Waiting for the first frame");
+        }
+
+        if !status.log.is_empty() {
+            let mut clear = false;
+            ui.collapsing(format!("Messages ({})", status.log.len()), |ui| {
+                for event in status.log.iter() {
+                    let label = ui.label(event.to_string());
+                    if let BusEvent::Error {
+                        debug: Some(debug), ..
+                    }
+                    | BusEvent::Warning {
+                        debug: Some(debug), ..
+                    } = event
+                    {
+                        label.on_hover_text(debug);
+                    }
+                }
+                clear = ui.button("Clear").clicked();
+            });
+            if clear {
+                self.status.log.clear();
+            }
+        }
+
+        if retry {
+            if let Err(err) = self.reopen() {
+                eprintln!("Could not reopen {}: {}", self.source, err);
+            }
+        } else if skip {
+            self.next();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let mut status = StreamStatus::default();
+        status.apply(BusEvent::Buffering(40));
+        assert_eq!(status.buffering, Some(40));
+        status.apply(BusEvent::Buffering(100));
+        assert_eq!(status.buffering, None);
+
+        let missing = BusEvent::MissingPlugin {
+            description: "H.265 decoder".to_string(),
+            installer_detail: None,
+        };
+        status.apply(missing.clone());
+        status.apply(BusEvent::Error {
+            element: None,
+            message: "Internal data stream error".to_string(),
+            debug: None,
+        });
+        // The error that follows a missing plugin does not hide it
+        assert_eq!(status.failure, Some(missing));
+        assert_eq!(status.log.len(), 2);
+
+        for _ in 0..LOG_LENGTH {
+            status.apply(BusEvent::Warning {
+                element: None,
+                message: String::new(),
+                debug: None,
+            });
+        }
+        assert_eq!(status.log.len(), LOG_LENGTH);
+        status.apply(BusEvent::Eos);
+        assert!(status.ended);
+    }
+}
@@ -17,8 +17,9 @@ pub enum SurfaceType<'a> {
 }
 
 impl CtxInfo {
-    pub fn new(surface: SurfaceType) -> Self {
-        gstreamer::init().unwrap();
+    /// Wraps the context of `surface` for GStreamer, or explains why it cannot be shared.
+    pub fn new(surface: SurfaceType) -> Result<Self, String> {
+        gstreamer::init().map_err(|err| err.to_string())?;
 
         #[cfg(target_os = "linux")]
         let (gl_context, gl_display, platform, api) = {
@@ -67,7 +68,7 @@ impl CtxInfo {
                             unsafe {
                                 gstreamer_gl_egl::GLDisplayEGL::with_egl_display(display as usize)
                             }
-                            .unwrap()
+                            .map_err(|err| err.to_string())?
                             .upcast::<gstreamer_gl::GLDisplay>(),
                         )
                     };
@@ -79,24 +80,25 @@ impl CtxInfo {
                                     display as usize,
                                 )
                             }
-                            .unwrap()
+                            .map_err(|err| err.to_string())?
                             .upcast::<gstreamer_gl::GLDisplay>(),
                         )
                     };
 
                     (
                         egl_context as usize,
-                        gl_display.expect("Could not retrieve GLDisplay through EGL context and/or Wayland display"),
+                        gl_display.ok_or("Could not retrieve GLDisplay through EGL context and/or Wayland display")?,
                         gstreamer_gl::GLPlatform::EGL,
-			api
+                        api,
                     )
                 }
                 RawHandle::Glx(glx_context) => {
-                    let gl_display = if let Some(display) = xlib_display {
-                        unsafe { gstreamer_gl_x11::GLDisplayX11::with_display(display as usize) }
-                            .unwrap()
-                    } else {
-                        panic!("X11 window without X Display");
+                    let gl_display = match xlib_display {
+                        Some(display) => unsafe {
+                            gstreamer_gl_x11::GLDisplayX11::with_display(display as usize)
+                        }
+                        .map_err(|err| err.to_string())?,
+                        None => return Err("X11 window without X Display".to_string()),
                     };
 
                     (
@@ -122,22 +124,24 @@ impl CtxInfo {
                     };
                     (context, gstreamer_gl::GLDisplay::new(), platform, api)
                 }
-                SurfaceType::Headless(_) => panic!("Not supported"),
+                SurfaceType::Headless(_) => {
+                    return Err("Headless contexts cannot be shared on macOS".to_string())
+                }
             }
         };
 
         // Setup a shared_context
         let shared_context =
             unsafe { gstreamer_gl::GLContext::new_wrapped(&gl_display, gl_context, platform, api) }
-                .unwrap();
+                .ok_or("Couldn't wrap the GL context")?;
         shared_context
             .activate(true)
-            .expect("Couldn't activate wrapped GL context");
-        shared_context.fill_info().unwrap();
+            .map_err(|err| format!("Couldn't activate wrapped GL context: {}", err))?;
+        shared_context.fill_info().map_err(|err| err.to_string())?;
 
-        Self {
+        Ok(Self {
             gl_context: shared_context,
             gl_display,
-        }
+        })
     }
 }
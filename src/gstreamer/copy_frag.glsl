@@ -4,9 +4,18 @@ precision highp float;
 uniform sampler2D tex;
 in vec2 tex_coord;
 
+uniform int transfer;
+uniform float transfer_param;
+uniform float sdr_white;
+// Decode to absolute luminance, otherwise keep the encoded signal
+uniform bool linearize;
+
 out vec4 f_color;
 
 void main() {
-  
-  f_color = pow(texture(tex, tex_coord), vec4(2.2));
+  vec4 color = texture(tex, tex_coord);
+  if (linearize) {
+    color.rgb = eotf_rgb(color.rgb, transfer, transfer_param, sdr_white);
+  }
+  f_color = color;
 }
@@ -0,0 +1,108 @@
+use std::{sync::mpsc, thread};
+
+use gstreamer::{prelude::*, ClockTime};
+use gstreamer_pbutils::prelude::*;
+
+use super::{Gstreamer, Source};
+
+/// What the discoverer found in a URI beyond the decoded frames.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MediaInfo {
+    pub container: Option<String>,
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    /// Bits per second of the video stream, nominal or else the maximum
+    pub video_bitrate: Option<u32>,
+    pub duration: Option<ClockTime>,
+    /// Tags of the whole file, such as title and encoder
+    pub tags: Vec<(String, String)>,
+}
+
+// The discoverer gives up on URIs that take longer to preroll
+const DISCOVER_TIMEOUT_S: u64 = 10;
+
+// Readable name of the format in `caps`, or the media type when GStreamer has none
+fn codec(caps: Option<gstreamer::Caps>) -> Option<String> {
+    let caps = caps?;
+    match gstreamer_pbutils::pb_utils_get_codec_description(&caps) {
+        Ok(description) => Some(description.to_string()),
+        Err(_) => caps.structure(0).map(|s| s.name().to_string()),
+    }
+}
+
+impl MediaInfo {
+    /// Blocks until the discoverer has read enough of `uri`.
+    pub fn discover(uri: &str) -> Result<Self, glib::Error> {
+        let discoverer =
+            gstreamer_pbutils::Discoverer::new(ClockTime::from_seconds(DISCOVER_TIMEOUT_S))?;
+        let info = discoverer.discover_uri(uri)?;
+        let video = info.video_streams().into_iter().next();
+        let audio = info.audio_streams().into_iter().next();
+        let container = info.container_streams().into_iter().next();
+        let tags = info
+            .tags()
+            .map(|tags| {
+                tags.iter()
+                    .map(|(name, value)| {
+                        let value = value
+                            .serialize()
+                            .map_or_else(|_| format!("{:?}", value), |value| value.to_string());
+                        (name.to_string(), value)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(Self {
+            container: container.and_then(|container| codec(container.caps())),
+            video_bitrate: video.as_ref().and_then(|video| {
+                [video.bitrate(), video.max_bitrate()]
+                    .into_iter()
+                    .find(|&bitrate| bitrate > 0)
+            }),
+            video_codec: video.and_then(|video| codec(video.caps())),
+            audio_codec: audio.and_then(|audio| codec(audio.caps())),
+            duration: info.duration(),
+            tags,
+        })
+    }
+}
+
+impl Gstreamer {
+    // Runs the discoverer on a URI source in the background, since it prerolls a pipeline of
+    // its own. Other sources have nothing to discover.
+    pub(super) fn discover(&mut self) {
+        self.media_info = None;
+        self.discovery = match &self.source {
+            Source::Uri(uri) if !uri.is_empty() => {
+                let (sender, receiver) = mpsc::channel();
+                let uri = uri.clone();
+                thread::spawn(move || {
+                    let _ = sender.send(MediaInfo::discover(&uri).map_err(|err| err.to_string()));
+                });
+                Some(receiver)
+            }
+            _ => None,
+        };
+    }
+
+    /// The discoverer's result for the current source, `None` while it runs or for sources
+    /// that are not URIs.
+    pub fn media_info(&mut self) -> Option<&Result<MediaInfo, String>> {
+        if let Some(result) = self.discovery.as_ref().and_then(|r| r.try_recv().ok()) {
+            self.media_info = Some(result);
+            self.discovery = None;
+        }
+        self.media_info.as_ref()
+    }
+
+    /// Whether the discoverer is still looking at the current source.
+    pub fn discovering(&self) -> bool {
+        self.discovery.is_some()
+    }
+
+    /// Format of the decoded stream, before it is converted for the upload.
+    pub fn stream_video_info(&self) -> Option<gstreamer_video::VideoInfo> {
+        let caps = self.sink.static_pad("sink")?.current_caps()?;
+        gstreamer_video::VideoInfo::from_caps(&caps).ok()
+    }
+}
@@ -0,0 +1,240 @@
+use glium::{
+    backend::Facade,
+    texture::{pixel_buffer::PixelBuffer, MipmapsOption, UncompressedFloatFormat},
+    Surface, Texture2d,
+};
+use gstreamer::prelude::*;
+
+use crate::{
+    output::{Framebuffer, OutputStage},
+    transfer::TransferSettings,
+};
+
+/// Adapted frames pushed through `appsrc` into a user supplied pipeline, such as
+/// `x264enc tune=zerolatency ! rtph264pay ! udpsink host=HOST port=5000` or
+/// `v4l2sink device=/dev/video10`.
+pub struct OutputStream {
+    pipeline: gstreamer::Pipeline,
+    appsrc: gstreamer_app::AppSrc,
+    description: String,
+    // Encodes for 8-bit receivers, without the display profile of the local screen
+    output: OutputStage,
+    frame: Option<Texture2d>,
+    framerate: gstreamer::Fraction,
+    // The previous frame, read back while the current one is drawn
+    pending: Option<(PixelBuffer<(u8, u8, u8, u8)>, u32)>,
+    pub dropped: u64,
+}
+
+// Frames waiting in `appsrc` beyond this are dropped instead of queued
+const MAX_QUEUED_BYTES: u64 = 64 << 20;
+
+// Rate announced when the source does not state one
+const DEFAULT_FRAMERATE: (i32, i32) = (30, 1);
+
+impl OutputStream {
+    /// `description` is what follows `appsrc ! videoconvert !` in a `gst-launch` line.
+    ///
+    /// Frames are pushed as they are drawn; `videorate` repeats or drops them to keep the rate
+    /// the caps announce, which receivers such as `v4l2sink` rely on.
+    pub fn new(facade: &dyn Facade, description: &str) -> Result<Self, glib::Error> {
+        let pipeline = gstreamer::parse_launch(&format!(
+            "appsrc name=adapted is-live=true do-timestamp=true format=time ! videorate ! \
+             videoconvert ! {}",
+            description
+        ))?
+        .downcast::<gstreamer::Pipeline>()
+        .unwrap();
+        let appsrc = pipeline
+            .by_name("adapted")
+            .unwrap()
+            .dynamic_cast::<gstreamer_app::AppSrc>()
+            .unwrap();
+
+        pipeline
+            .bus()
+            .unwrap()
+            .add_watch(|_, msg| {
+                if let gstreamer::MessageView::Error(err) = msg.view() {
+                    eprintln!("Output stream error: {}", err.error());
+                }
+                glib::Continue(true)
+            })
+            .expect("Failed to add bus watch");
+        if let Err(err) = pipeline.set_state(gstreamer::State::Playing) {
+            let _ = pipeline.set_state(gstreamer::State::Null);
+            let _ = pipeline.bus().unwrap().remove_watch();
+            return Err(glib::Error::new(
+                gstreamer::CoreError::StateChange,
+                &err.to_string(),
+            ));
+        }
+
+        Ok(Self {
+            pipeline,
+            appsrc,
+            description: description.to_string(),
+            output: OutputStage::new(facade, Framebuffer::Srgb),
+            frame: None,
+            framerate: gstreamer::Fraction::new(DEFAULT_FRAMERATE.0, DEFAULT_FRAMERATE.1),
+            pending: None,
+            dropped: 0,
+        })
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// Encodes the working signal in `texture` and queues it, or drops it when the pipeline
+    /// falls behind. `framerate` is that of the source, if it has one.
+    ///
+    /// The readback is asynchronous, so each frame is pushed when the next one is drawn.
+    pub fn push(
+        &mut self,
+        facade: &dyn Facade,
+        texture: &Texture2d,
+        transfer: &TransferSettings,
+        framerate: Option<gstreamer::Fraction>,
+    ) {
+        if self.appsrc.current_level_bytes() > MAX_QUEUED_BYTES {
+            self.dropped += 1;
+            return;
+        }
+        let (width, height) = texture.dimensions();
+        let framerate = framerate
+            .filter(|rate| *rate.numer() > 0 && *rate.denom() > 0)
+            .unwrap_or(self.framerate);
+        let resized = self.frame.as_ref().map(|f| f.dimensions()) != Some((width, height));
+        if resized || framerate != self.framerate {
+            // The frame in flight still belongs to the old caps
+            self.push_pending();
+            let caps = gstreamer_video::VideoInfo::builder(
+                gstreamer_video::VideoFormat::Rgba,
+                width,
+                height,
+            )
+            .fps(framerate)
+            .build()
+            .and_then(|info| info.to_caps());
+            match caps {
+                Ok(caps) => self.appsrc.set_caps(Some(&caps)),
+                Err(err) => {
+                    eprintln!("Output stream: {}", err);
+                    return;
+                }
+            }
+            self.framerate = framerate;
+        }
+        if resized {
+            self.frame = Some(
+                Texture2d::empty_with_format(
+                    facade,
+                    UncompressedFloatFormat::U8U8U8U8,
+                    MipmapsOption::NoMipmap,
+                    width,
+                    height,
+                )
+                .unwrap(),
+            );
+        }
+        let frame = self.frame.as_ref().unwrap();
+        self.output.draw(&mut frame.as_surface(), texture, transfer);
+        let readback = frame.read_to_pixel_buffer();
+        self.push_pending();
+        self.pending = Some((readback, width));
+    }
+
+    // Pushes the frame read back before, which the GPU has finished by now
+    fn push_pending(&mut self) {
+        let (buffer, width) = match self.pending.take() {
+            Some(pending) => pending,
+            None => return,
+        };
+        let pixels = match buffer.read() {
+            Ok(pixels) => pixels,
+            Err(err) => {
+                eprintln!("Output stream: {:?}", err);
+                return;
+            }
+        };
+        // GL rows start at the bottom, video rows at the top
+        let data: Vec<u8> = pixels
+            .chunks_exact(width as usize)
+            .rev()
+            .flatten()
+            .flat_map(|(r, g, b, a)| [*r, *g, *b, *a])
+            .collect();
+        let _ = self
+            .appsrc
+            .push_buffer(gstreamer::Buffer::from_mut_slice(data));
+    }
+}
+
+impl Drop for OutputStream {
+    // Lets muxers such as the one of a file sink finish their output
+    fn drop(&mut self) {
+        self.push_pending();
+        let bus = self.pipeline.bus().unwrap();
+        let _ = bus.remove_watch();
+        let _ = self.appsrc.end_of_stream();
+        let _ = bus.timed_pop_filtered(
+            gstreamer::ClockTime::from_seconds(2),
+            &[gstreamer::MessageType::Eos, gstreamer::MessageType::Error],
+        );
+        let _ = self.pipeline.set_state(gstreamer::State::Null);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::UdpSocket, time::Duration};
+
+    use glium::texture::RawImage2d;
+
+    use super::*;
+    use crate::headless::HeadlessContext;
+
+    // Streams raw RTP to a socket of the test and waits for it to arrive
+    #[test]
+    #[ignore = "needs a GL driver"]
+    fn test_udp_loopback() {
+        gstreamer::init().unwrap();
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let port = receiver.local_addr().unwrap().port();
+
+        let context = HeadlessContext::new();
+        let facade = &context.facade;
+        let mut stream = OutputStream::new(
+            facade,
+            &format!("rtpvrawpay ! udpsink host=127.0.0.1 port={}", port),
+        )
+        .unwrap();
+        let pixels = vec![50f32; 64 * 48 * 4];
+        let texture = Texture2d::with_format(
+            facade,
+            RawImage2d::from_raw_rgba(pixels, (64, 48)),
+            UncompressedFloatFormat::F32F32F32F32,
+            MipmapsOption::NoMipmap,
+        )
+        .unwrap();
+        let framerate = Some(gstreamer::Fraction::new(25, 1));
+        for _ in 0..5 {
+            stream.push(facade, &texture, &TransferSettings::default(), framerate);
+            std::thread::sleep(Duration::from_millis(40));
+        }
+
+        let caps = stream.appsrc.caps().unwrap();
+        let info = gstreamer_video::VideoInfo::from_caps(&caps).unwrap();
+        assert_eq!(info.fps(), gstreamer::Fraction::new(25, 1));
+        let mut packet = [0; 2048];
+        let received = receiver.recv(&mut packet).expect("no RTP packet");
+        // RTP version 2
+        assert!(received > 12);
+        assert_eq!(packet[0] >> 6, 2);
+        assert_eq!(stream.dropped, 0);
+    }
+}
@@ -0,0 +1,87 @@
+use super::Source;
+
+/// Sources played one after another.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Playlist {
+    entries: Vec<Source>,
+    current: Option<usize>,
+}
+
+impl Playlist {
+    pub fn entries(&self) -> &[Source] {
+        &self.entries
+    }
+
+    pub fn current(&self) -> Option<usize> {
+        self.current
+    }
+
+    pub fn push(&mut self, source: Source) {
+        self.entries.push(source);
+    }
+
+    pub fn remove(&mut self, i: usize) {
+        if i >= self.entries.len() {
+            return;
+        }
+        self.entries.remove(i);
+        self.current = match self.current {
+            Some(current) if current == i => None,
+            Some(current) if current > i => Some(current - 1),
+            current => current,
+        };
+    }
+
+    /// Makes entry `i` current and returns it.
+    pub fn select(&mut self, i: usize) -> Option<&Source> {
+        let source = self.entries.get(i)?;
+        self.current = Some(i);
+        Some(source)
+    }
+
+    /// The entry after the current one, or the first when none is current.
+    pub fn next(&mut self) -> Option<&Source> {
+        self.select(self.current.map_or(0, |i| i + 1))
+    }
+
+    pub fn previous(&mut self) -> Option<&Source> {
+        self.select(self.current?.checked_sub(1)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uri(s: &str) -> Source {
+        Source::Uri(s.to_string())
+    }
+
+    #[test]
+    fn test_navigation() {
+        let mut playlist = Playlist::default();
+        assert_eq!(playlist.next(), None);
+        playlist.push(uri("a"));
+        playlist.push(uri("b"));
+        assert_eq!(playlist.next(), Some(&uri("a")));
+        assert_eq!(playlist.previous(), None);
+        assert_eq!(playlist.next(), Some(&uri("b")));
+        assert_eq!(playlist.next(), None);
+        assert_eq!(playlist.current(), Some(1));
+        assert_eq!(playlist.previous(), Some(&uri("a")));
+    }
+
+    #[test]
+    fn test_remove_keeps_current() {
+        let mut playlist = Playlist::default();
+        for s in ["a", "b", "c"] {
+            playlist.push(uri(s));
+        }
+        playlist.select(2);
+        playlist.remove(0);
+        assert_eq!(playlist.current(), Some(1));
+        playlist.remove(1);
+        assert_eq!(playlist.current(), None);
+        assert_eq!(playlist.entries(), &[uri("b")]);
+    }
+}
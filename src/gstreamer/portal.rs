@@ -0,0 +1,206 @@
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use gio::prelude::*;
+use glib::{variant::ObjectPath, ToVariant, Variant, VariantDict};
+use gstreamer::prelude::*;
+
+use super::source::{chain, leaky_queue};
+
+const PORTAL: &str = "org.freedesktop.portal.Desktop";
+const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+const SCREEN_CAST: &str = "org.freedesktop.portal.ScreenCast";
+
+// Source types of `SelectSources`
+const MONITOR: u32 = 1;
+const WINDOW: u32 = 2;
+
+// Makes the request paths of this process unique
+static NEXT_TOKEN: AtomicU32 = AtomicU32::new(0);
+
+/// A screen cast session of the desktop portal, which is how Wayland compositors share the
+/// screen. The stream stays available until it is dropped.
+pub struct ScreenCast {
+    connection: gio::DBusConnection,
+    // Unique bus name in the form the portal uses in request paths
+    sender: String,
+    session: String,
+    node: u32,
+}
+
+impl ScreenCast {
+    /// Lets the user pick a monitor or window in the dialog of the portal. Blocks until they
+    /// have chosen or cancelled.
+    pub fn open() -> Result<Self, glib::Error> {
+        let connection = gio::bus_get_sync(gio::BusType::Session, gio::Cancellable::NONE)?;
+        let sender = connection
+            .unique_name()
+            .map(|name| name.trim_start_matches(':').replace('.', "_"))
+            .unwrap_or_default();
+        let mut cast = Self {
+            connection,
+            sender,
+            session: String::new(),
+            node: 0,
+        };
+
+        let options = VariantDict::new(None);
+        options.insert_value("session_handle_token", &token().to_variant());
+        let results = cast.request("CreateSession", vec![], options)?;
+        cast.session = results
+            .lookup_value("session_handle", None)
+            .and_then(|handle| handle.str().map(str::to_string))
+            .ok_or_else(|| failed("CreateSession"))?;
+
+        let options = VariantDict::new(None);
+        options.insert_value("types", &(MONITOR | WINDOW).to_variant());
+        options.insert_value("multiple", &false.to_variant());
+        cast.request("SelectSources", vec![cast.session_path()?], options)?;
+
+        let results = cast.request(
+            "Start",
+            vec![cast.session_path()?, "".to_variant()],
+            VariantDict::new(None),
+        )?;
+        // Streams are `(node id, properties)`; a single one was asked for
+        cast.node = results
+            .lookup_value("streams", None)
+            .filter(|streams| streams.n_children() > 0)
+            .and_then(|streams| streams.child_value(0).child_value(0).get::<u32>())
+            .ok_or_else(|| failed("Start"))?;
+        Ok(cast)
+    }
+
+    /// A bin of the stream in the form of `Source::bin`. Each call opens a new connection to
+    /// PipeWire, which `pipewiresrc` takes over.
+    pub fn bin(&self) -> Result<gstreamer::Bin, glib::Error> {
+        let (_, fds) = self.connection.call_with_unix_fd_list_sync(
+            Some(PORTAL),
+            PORTAL_PATH,
+            SCREEN_CAST,
+            "OpenPipeWireRemote",
+            Some(&Variant::tuple_from_iter([
+                self.session_path()?,
+                VariantDict::new(None).end(),
+            ])),
+            None,
+            gio::DBusCallFlags::NONE,
+            -1,
+            gio::UnixFDList::NONE,
+            gio::Cancellable::NONE,
+        )?;
+        let fd = fds.get(0)?;
+        let build = || -> Result<_, glib::BoolError> {
+            let pipewiresrc = gstreamer::ElementFactory::make("pipewiresrc", None)?;
+            pipewiresrc.set_property("fd", fd);
+            pipewiresrc.set_property("path", self.node.to_string());
+            pipewiresrc.set_property("do-timestamp", true);
+            let convert = gstreamer::ElementFactory::make("videoconvert", None)?;
+            chain(&[pipewiresrc, leaky_queue()?, convert])
+        };
+        build()
+            .map_err(|err| glib::Error::new(gstreamer::CoreError::MissingPlugin, &err.to_string()))
+    }
+
+    fn session_path(&self) -> Result<Variant, glib::Error> {
+        ObjectPath::try_from(self.session.clone())
+            .map(|path| path.to_variant())
+            .map_err(|_| failed("CreateSession"))
+    }
+
+    // Calls a method that answers with the `Response` signal of a request object and waits for
+    // it, running the default main context meanwhile. `options` gets the handle token.
+    fn request(
+        &self,
+        method: &str,
+        mut args: Vec<Variant>,
+        options: VariantDict,
+    ) -> Result<VariantDict, glib::Error> {
+        let token = token();
+        options.insert_value("handle_token", &token.to_variant());
+        args.push(options.end());
+        // Subscribed before the call so that a quick response is not missed
+        let path = format!("{}/request/{}/{}", PORTAL_PATH, self.sender, token);
+        let response = Rc::new(RefCell::new(None));
+        let subscription = {
+            let response = response.clone();
+            self.connection.signal_subscribe(
+                Some(PORTAL),
+                Some("org.freedesktop.portal.Request"),
+                Some("Response"),
+                Some(&path),
+                None,
+                gio::DBusSignalFlags::NONE,
+                move |_, _, _, _, _, parameters| {
+                    *response.borrow_mut() = Some(parameters.clone());
+                },
+            )
+        };
+        let called = self.connection.call_sync(
+            Some(PORTAL),
+            PORTAL_PATH,
+            SCREEN_CAST,
+            method,
+            Some(&Variant::tuple_from_iter(args)),
+            None,
+            gio::DBusCallFlags::NONE,
+            -1,
+            gio::Cancellable::NONE,
+        );
+        if called.is_ok() {
+            let context = glib::MainContext::default();
+            while response.borrow().is_none() {
+                context.iteration(true);
+            }
+        }
+        self.connection.signal_unsubscribe(subscription);
+        called?;
+
+        // `(code, results)`, where 1 means the user cancelled
+        let parameters = response.borrow_mut().take().unwrap();
+        match parameters.child_value(0).get::<u32>() {
+            Some(0) => Ok(VariantDict::new(Some(&parameters.child_value(1)))),
+            Some(1) => Err(glib::Error::new(
+                gio::IOErrorEnum::Cancelled,
+                "Screen capture cancelled",
+            )),
+            _ => Err(failed(method)),
+        }
+    }
+}
+
+impl Drop for ScreenCast {
+    fn drop(&mut self) {
+        if self.session.is_empty() {
+            return;
+        }
+        let _ = self.connection.call_sync(
+            Some(PORTAL),
+            &self.session,
+            "org.freedesktop.portal.Session",
+            "Close",
+            None,
+            None,
+            gio::DBusCallFlags::NONE,
+            -1,
+            gio::Cancellable::NONE,
+        );
+    }
+}
+
+fn token() -> String {
+    format!(
+        "csf_contrast_{}",
+        NEXT_TOKEN.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+fn failed(method: &str) -> glib::Error {
+    glib::Error::new(
+        gio::IOErrorEnum::Failed,
+        &format!("Screen cast portal: {} failed", method),
+    )
+}
@@ -0,0 +1,503 @@
+use std::{fmt, str::FromStr};
+
+use gstreamer::prelude::*;
+
+/// Where frames come from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    /// Anything `playbin` can open
+    Uri(String),
+    /// A V4L2 device such as `/dev/video0`, in its default format unless one is given
+    Capture {
+        device: String,
+        format: Option<CaptureFormat>,
+    },
+    /// `videotestsrc` with the named pattern, a live stand-in for a camera
+    TestPattern(String),
+    /// What is on screen
+    Screen(ScreenCapture),
+}
+
+/// The part of the desktop a screen capture shows.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScreenCapture {
+    /// `ximagesrc` on an X11 display, `$DISPLAY` unless one is given
+    X11 {
+        display: Option<String>,
+        area: CaptureArea,
+    },
+    /// A monitor or window picked in the dialog of the desktop portal, for Wayland
+    Portal,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaptureArea {
+    Screen,
+    /// In pixels from the top left corner of the screen
+    Region {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    /// X11 window id
+    Window(u64),
+}
+
+const V4L2_SCHEME: &str = "v4l2://";
+const TEST_SCHEME: &str = "testsrc://";
+const SCREEN_SCHEME: &str = "screen://";
+const PORTAL: &str = "portal";
+
+/// `v4l2:///dev/videoN` opens a capture device and `testsrc://PATTERN` a test pattern.
+/// `screen://[DISPLAY][?region=X,Y,WIDTHxHEIGHT|?window=ID]` captures an X11 screen and
+/// `screen://portal` asks the desktop portal. Anything else is passed on to `playbin` as a URI.
+impl FromStr for Source {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(screen) = s.strip_prefix(SCREEN_SCHEME) {
+            if screen == PORTAL {
+                return Ok(Source::Screen(ScreenCapture::Portal));
+            }
+            let (display, area) = match screen.split_once('?') {
+                Some((display, query)) => (display, CaptureArea::from_query(query)?),
+                None => (screen, CaptureArea::Screen),
+            };
+            Ok(Source::Screen(ScreenCapture::X11 {
+                display: (!display.is_empty()).then(|| display.to_string()),
+                area,
+            }))
+        } else if let Some(device) = s.strip_prefix(V4L2_SCHEME) {
+            if device.is_empty() {
+                return Err(format!("Expected a device path after {}", V4L2_SCHEME));
+            }
+            Ok(Source::Capture {
+                device: device.to_string(),
+                format: None,
+            })
+        } else if let Some(pattern) = s.strip_prefix(TEST_SCHEME) {
+            Ok(Source::TestPattern(if pattern.is_empty() {
+                "smpte".to_string()
+            } else {
+                pattern.to_string()
+            }))
+        } else {
+            Ok(Source::Uri(s.to_string()))
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Uri(uri) => write!(f, "{}", uri),
+            Source::Capture { device, format } => {
+                write!(f, "{}{}", V4L2_SCHEME, device)?;
+                match format {
+                    Some(format) => write!(f, " ({})", format),
+                    None => Ok(()),
+                }
+            }
+            Source::TestPattern(pattern) => write!(f, "{}{}", TEST_SCHEME, pattern),
+            Source::Screen(ScreenCapture::Portal) => write!(f, "{}{}", SCREEN_SCHEME, PORTAL),
+            Source::Screen(ScreenCapture::X11 { display, area }) => {
+                write!(f, "{}{}", SCREEN_SCHEME, display.as_deref().unwrap_or(""))?;
+                match area {
+                    CaptureArea::Screen => Ok(()),
+                    CaptureArea::Region {
+                        x,
+                        y,
+                        width,
+                        height,
+                    } => write!(f, "?region={},{},{}x{}", x, y, width, height),
+                    CaptureArea::Window(id) => write!(f, "?window={:#x}", id),
+                }
+            }
+        }
+    }
+}
+
+impl CaptureArea {
+    // `region=X,Y,WIDTHxHEIGHT` or `window=ID`, the id in decimal or hexadecimal as `xwininfo`
+    // prints it
+    fn from_query(query: &str) -> Result<Self, String> {
+        let invalid = || {
+            format!(
+                "Expected region=X,Y,WIDTHxHEIGHT or window=ID, not {}",
+                query
+            )
+        };
+        if let Some(region) = query.strip_prefix("region=") {
+            let numbers: Vec<u32> = region
+                .split([',', 'x'])
+                .map(|n| n.parse().map_err(|_| invalid()))
+                .collect::<Result<_, _>>()?;
+            match numbers[..] {
+                [x, y, width, height] if width > 0 && height > 0 => Ok(CaptureArea::Region {
+                    x,
+                    y,
+                    width,
+                    height,
+                }),
+                _ => Err(invalid()),
+            }
+        } else if let Some(id) = query.strip_prefix("window=") {
+            let id = match id.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16),
+                None => id.parse(),
+            };
+            id.map(CaptureArea::Window).map_err(|_| invalid())
+        } else {
+            Err(invalid())
+        }
+    }
+}
+
+impl Source {
+    /// Live sources are shown as soon as a frame arrives instead of on the pipeline clock.
+    pub fn is_live(&self) -> bool {
+        !matches!(self, Source::Uri(_))
+    }
+
+    /// A bin with raw video on its source pad, or `None` for URIs, which go through `playbin`,
+    /// and for the portal, whose stream only exists once the user has picked what to share.
+    ///
+    /// The elements are built one by one, so no part of the source can add elements of its own.
+    pub fn bin(&self) -> Result<Option<gstreamer::Bin>, glib::BoolError> {
+        let make = |factory| gstreamer::ElementFactory::make(factory, None);
+        let elements = match self {
+            Source::Uri(_) | Source::Screen(ScreenCapture::Portal) => return Ok(None),
+            Source::Capture { device, format } => {
+                let v4l2src = make("v4l2src")?;
+                v4l2src.set_property("device", device.as_str());
+                v4l2src.set_property("do-timestamp", true);
+                let mut elements = vec![v4l2src];
+                if let Some(format) = format {
+                    elements.push(caps_filter(format.caps())?);
+                    if format.encoding == Encoding::Jpeg {
+                        elements.push(make("jpegdec")?);
+                    }
+                }
+                elements.extend([leaky_queue()?, make("videoconvert")?]);
+                elements
+            }
+            Source::TestPattern(pattern) => {
+                let videotestsrc = make("videotestsrc")?;
+                videotestsrc.set_property("is-live", true);
+                set_enum(&videotestsrc, "pattern", pattern)?;
+                let caps = gstreamer::Caps::builder("video/x-raw")
+                    .field("width", 1280)
+                    .field("height", 720)
+                    .field("framerate", gstreamer::Fraction::new(30, 1))
+                    .build();
+                vec![videotestsrc, caps_filter(caps)?]
+            }
+            Source::Screen(ScreenCapture::X11 { display, area }) => {
+                let ximagesrc = make("ximagesrc")?;
+                // Damage events would only send the changed parts of the screen
+                ximagesrc.set_property("use-damage", false);
+                if let Some(display) = display {
+                    ximagesrc.set_property("display-name", display.as_str());
+                }
+                match *area {
+                    CaptureArea::Screen => (),
+                    // The end coordinates are inclusive
+                    CaptureArea::Region {
+                        x,
+                        y,
+                        width,
+                        height,
+                    } => {
+                        ximagesrc.set_property("startx", x);
+                        ximagesrc.set_property("starty", y);
+                        ximagesrc.set_property("endx", x + width - 1);
+                        ximagesrc.set_property("endy", y + height - 1);
+                    }
+                    CaptureArea::Window(id) => ximagesrc.set_property("xid", id),
+                }
+                let caps = gstreamer::Caps::builder("video/x-raw")
+                    .field("framerate", gstreamer::Fraction::new(30, 1))
+                    .build();
+                vec![
+                    ximagesrc,
+                    caps_filter(caps)?,
+                    leaky_queue()?,
+                    make("videoconvert")?,
+                ]
+            }
+        };
+        chain(&elements).map(Some)
+    }
+}
+
+/// A queue of a single leaky buffer, which keeps the latency at one frame when processing lags.
+pub(super) fn leaky_queue() -> Result<gstreamer::Element, glib::BoolError> {
+    let queue = gstreamer::ElementFactory::make("queue", None)?;
+    queue.set_property_from_str("leaky", "downstream");
+    queue.set_property("max-size-buffers", 1u32);
+    Ok(queue)
+}
+
+// Restricts the stream to `caps`
+fn caps_filter(caps: gstreamer::Caps) -> Result<gstreamer::Element, glib::BoolError> {
+    let filter = gstreamer::ElementFactory::make("capsfilter", None)?;
+    filter.set_property("caps", &caps);
+    Ok(filter)
+}
+
+// Sets an enum property by its nick, failing on values the element does not know
+fn set_enum(element: &gstreamer::Element, name: &str, nick: &str) -> Result<(), glib::BoolError> {
+    let value = element
+        .find_property(name)
+        .and_then(|pspec| pspec.downcast::<glib::ParamSpecEnum>().ok())
+        .and_then(|pspec| pspec.enum_class().to_value_by_nick(nick))
+        .ok_or_else(|| glib::bool_error!("Invalid {}: {}", name, nick))?;
+    element.set_property_from_value(name, &value);
+    Ok(())
+}
+
+/// A bin of `elements` linked in order, with the source pad of the last one as its own.
+pub(super) fn chain(elements: &[gstreamer::Element]) -> Result<gstreamer::Bin, glib::BoolError> {
+    let bin = gstreamer::Bin::new(None);
+    let elements: Vec<_> = elements.iter().collect();
+    bin.add_many(&elements)?;
+    gstreamer::Element::link_many(&elements)?;
+    let src = elements.last().and_then(|last| last.static_pad("src"));
+    let src = src.ok_or_else(|| glib::bool_error!("No source pad"))?;
+    bin.add_pad(&gstreamer::GhostPad::with_target(Some("src"), &src)?)?;
+    Ok(bin)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Raw,
+    Jpeg,
+}
+
+/// One of the formats a capture device offers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CaptureFormat {
+    pub encoding: Encoding,
+    pub width: i32,
+    pub height: i32,
+    pub framerate: (i32, i32),
+}
+
+impl CaptureFormat {
+    fn caps(&self) -> gstreamer::Caps {
+        let media_type = match self.encoding {
+            Encoding::Raw => "video/x-raw",
+            Encoding::Jpeg => "image/jpeg",
+        };
+        gstreamer::Caps::builder(media_type)
+            .field("width", self.width)
+            .field("height", self.height)
+            .field(
+                "framerate",
+                gstreamer::Fraction::new(self.framerate.0, self.framerate.1),
+            )
+            .build()
+    }
+
+    // Every fixed size and rate in a caps structure; ranges are skipped
+    fn from_structure(s: &gstreamer::StructureRef) -> Vec<Self> {
+        let encoding = match s.name() {
+            "video/x-raw" => Encoding::Raw,
+            "image/jpeg" => Encoding::Jpeg,
+            _ => return vec![],
+        };
+        let (width, height) = match (s.get::<i32>("width"), s.get::<i32>("height")) {
+            (Ok(width), Ok(height)) => (width, height),
+            _ => return vec![],
+        };
+        let framerates = if let Ok(rate) = s.get::<gstreamer::Fraction>("framerate") {
+            vec![rate]
+        } else if let Ok(rates) = s.get::<gstreamer::List>("framerate") {
+            rates
+                .iter()
+                .filter_map(|rate| rate.get::<gstreamer::Fraction>().ok())
+                .collect()
+        } else {
+            vec![]
+        };
+        framerates
+            .into_iter()
+            .map(|rate| Self {
+                encoding,
+                width,
+                height,
+                framerate: (*rate.numer(), *rate.denom()),
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for CaptureFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let encoding = match self.encoding {
+            Encoding::Raw => "raw",
+            Encoding::Jpeg => "MJPEG",
+        };
+        write!(
+            f,
+            "{}×{} {:.0} fps {}",
+            self.width,
+            self.height,
+            self.framerate.0 as f32 / self.framerate.1 as f32,
+            encoding
+        )
+    }
+}
+
+/// A camera found by the device monitor.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CaptureDevice {
+    pub name: String,
+    pub path: String,
+    pub formats: Vec<CaptureFormat>,
+}
+
+impl CaptureDevice {
+    pub fn source(&self, format: Option<CaptureFormat>) -> Source {
+        Source::Capture {
+            device: self.path.clone(),
+            format,
+        }
+    }
+}
+
+/// The V4L2 video sources present right now, with the formats they offer.
+pub fn capture_devices() -> Vec<CaptureDevice> {
+    let monitor = gstreamer::DeviceMonitor::new();
+    monitor.add_filter(Some("Video/Source"), None);
+    if monitor.start().is_err() {
+        return vec![];
+    }
+    let devices = monitor
+        .devices()
+        .into_iter()
+        .filter_map(|device| {
+            // Other providers such as PipeWire list the same cameras again
+            let element = device.create_element(None).ok()?;
+            if element.factory()?.name() != "v4l2src" {
+                return None;
+            }
+            let path = element.property::<Option<String>>("device")?;
+            let mut formats: Vec<_> = device
+                .caps()
+                .iter()
+                .flat_map(|caps| {
+                    caps.iter()
+                        .flat_map(CaptureFormat::from_structure)
+                        .collect::<Vec<_>>()
+                })
+                .collect();
+            formats.dedup();
+            Some(CaptureDevice {
+                name: device.display_name().to_string(),
+                path,
+                formats,
+            })
+        })
+        .collect();
+    monitor.stop();
+    devices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "v4l2:///dev/video2".parse(),
+            Ok(Source::Capture {
+                device: "/dev/video2".to_string(),
+                format: None
+            })
+        );
+        assert_eq!(
+            "testsrc://".parse(),
+            Ok(Source::TestPattern("smpte".to_string()))
+        );
+        assert_eq!(
+            "file:///a.webm".parse(),
+            Ok(Source::Uri("file:///a.webm".to_string()))
+        );
+        assert!("v4l2://".parse::<Source>().is_err());
+        assert_eq!(
+            "screen://".parse(),
+            Ok(Source::Screen(ScreenCapture::X11 {
+                display: None,
+                area: CaptureArea::Screen
+            }))
+        );
+        assert_eq!(
+            "screen://:99?region=10,20,640x480".parse(),
+            Ok(Source::Screen(ScreenCapture::X11 {
+                display: Some(":99".to_string()),
+                area: CaptureArea::Region {
+                    x: 10,
+                    y: 20,
+                    width: 640,
+                    height: 480
+                }
+            }))
+        );
+        assert_eq!(
+            "screen://portal".parse(),
+            Ok(Source::Screen(ScreenCapture::Portal))
+        );
+        assert!("screen://?region=0,0,0x480".parse::<Source>().is_err());
+        assert!("screen://?window=none".parse::<Source>().is_err());
+        let source: Source = "screen://?window=0x3a00007".parse().unwrap();
+        assert_eq!(source.to_string().parse(), Ok(source));
+        let source: Source = "testsrc://ball".parse().unwrap();
+        assert_eq!(source.to_string().parse(), Ok(source));
+    }
+
+    #[test]
+    fn test_bin() {
+        gstreamer::init().unwrap();
+        assert!(Source::Uri(String::new()).bin().unwrap().is_none());
+        assert!(Source::Screen(ScreenCapture::Portal)
+            .bin()
+            .unwrap()
+            .is_none());
+        let bin = Source::TestPattern("snow".to_string())
+            .bin()
+            .unwrap()
+            .unwrap();
+        assert!(bin.static_pad("src").is_some());
+        let source = bin.iterate_sources().into_iter().next().unwrap().unwrap();
+        let pattern = source.property_value("pattern").serialize().unwrap();
+        assert_eq!(pattern.as_str(), "snow");
+        // Nothing in the pattern is parsed as a pipeline
+        assert!(Source::TestPattern("snow ! fakesink".to_string())
+            .bin()
+            .is_err());
+    }
+
+    #[test]
+    fn test_capture_caps() {
+        gstreamer::init().unwrap();
+        let format = CaptureFormat {
+            encoding: Encoding::Jpeg,
+            width: 1280,
+            height: 720,
+            framerate: (30, 1),
+        };
+        let caps = format.caps();
+        assert_eq!(
+            CaptureFormat::from_structure(caps.structure(0).unwrap()),
+            vec![format]
+        );
+        assert!(Source::Capture {
+            device: "/dev/video0".to_string(),
+            format: Some(format),
+        }
+        .is_live());
+    }
+}
@@ -0,0 +1,223 @@
+use gstreamer::{prelude::*, ClockTime, SeekFlags, SeekType};
+
+use super::{BusEvent, Gstreamer, Source};
+
+/// Playback state shared with the bus watch.
+#[derive(Debug)]
+pub(super) struct PlaybackState {
+    pub looping: bool,
+    /// Set at the end of a stream that does not loop
+    pub eos: bool,
+    pub rate: f64,
+    /// Set when an element of a GL sink reports an error
+    pub sink_failed: bool,
+    /// Set when the pipeline has finished a state change or seek
+    pub async_done: bool,
+    /// Queued by the bus watch until the next frame is drawn
+    pub events: Vec<BusEvent>,
+}
+
+impl Default for PlaybackState {
+    fn default() -> Self {
+        Self {
+            looping: false,
+            eos: false,
+            rate: 1.,
+            sink_failed: false,
+            async_done: false,
+            events: vec![],
+        }
+    }
+}
+
+// Accurate so that stepping through a paused video lands on the frame asked for
+pub(super) fn seek(pipeline: &gstreamer::Pipeline, rate: f64, position: ClockTime) -> bool {
+    pipeline
+        .seek(
+            rate,
+            SeekFlags::FLUSH | SeekFlags::ACCURATE,
+            SeekType::Set,
+            position,
+            SeekType::None,
+            ClockTime::NONE,
+        )
+        .is_ok()
+}
+
+fn seconds(time: ClockTime) -> f64 {
+    time.nseconds() as f64 / 1e9
+}
+
+const RATES: [f64; 6] = [0.25, 0.5, 1., 1.5, 2., 4.];
+
+impl Gstreamer {
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        let state = if paused {
+            gstreamer::State::Paused
+        } else {
+            gstreamer::State::Playing
+        };
+        if self.pipeline.set_state(state).is_ok() {
+            self.paused = paused;
+            self.preroll = paused;
+        }
+    }
+
+    pub fn position(&self) -> Option<ClockTime> {
+        self.pipeline.query_position::<ClockTime>()
+    }
+
+    /// `None` for live sources and streams of unknown length.
+    pub fn duration(&self) -> Option<ClockTime> {
+        self.pipeline.query_duration::<ClockTime>()
+    }
+
+    pub fn seek(&mut self, position: ClockTime) {
+        let rate = self.playback.lock().unwrap().rate;
+        if seek(&self.pipeline, rate, position) {
+            self.preroll = self.paused;
+        }
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.playback.lock().unwrap().rate
+    }
+
+    /// Changes the speed from the current position on.
+    pub fn set_rate(&mut self, rate: f64) {
+        let position = self.position().unwrap_or(ClockTime::ZERO);
+        if seek(&self.pipeline, rate, position) {
+            self.playback.lock().unwrap().rate = rate;
+            self.preroll = self.paused;
+        }
+    }
+
+    pub fn looping(&self) -> bool {
+        self.playback.lock().unwrap().looping
+    }
+
+    pub fn set_looping(&self, looping: bool) {
+        self.playback.lock().unwrap().looping = looping;
+    }
+
+    /// Pauses and advances by a single frame.
+    pub fn step_frame(&mut self) {
+        if !self.paused {
+            self.set_paused(true);
+        }
+        let step =
+            gstreamer::event::Step::new(gstreamer::format::Buffers(Some(1)), 1., true, false);
+        if self.pipeline.send_event(step) {
+            self.preroll = true;
+        }
+    }
+
+    /// Plays entry `i` of the playlist.
+    pub fn play_entry(&mut self, i: usize) {
+        let source = self.playlist.select(i).cloned();
+        self.play(source);
+    }
+
+    pub fn next(&mut self) {
+        let source = self.playlist.next().cloned();
+        self.play(source);
+    }
+
+    pub fn previous(&mut self) {
+        let source = self.playlist.previous().cloned();
+        self.play(source);
+    }
+
+    fn play(&mut self, source: Option<Source>) {
+        if let Some(source) = source {
+            let name = source.to_string();
+            if let Err(err) = self.set_source(source) {
+                eprintln!("Could not open {}: {}", name, err);
+            }
+        }
+    }
+
+    // Moves on to the next entry once a stream has ended without looping
+    pub(super) fn handle_eos(&mut self) {
+        let eos = std::mem::take(&mut self.playback.lock().unwrap().eos);
+        if eos {
+            self.next();
+        }
+    }
+
+    pub fn draw_transport_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if ui.button("⏮").clicked() {
+                self.previous();
+            }
+            let label = if self.paused { "▶" } else { "⏸" };
+            if ui.button(label).clicked() {
+                self.set_paused(!self.paused);
+            }
+            if ui.button("Step").clicked() {
+                self.step_frame();
+            }
+            if ui.button("⏭").clicked() {
+                self.next();
+            }
+            let mut looping = self.looping();
+            if ui.checkbox(&mut looping, "Loop").changed() {
+                self.set_looping(looping);
+            }
+        });
+
+        let position = self.position().unwrap_or(ClockTime::ZERO);
+        match self.duration() {
+            Some(duration) => {
+                let mut at = seconds(position);
+                let slider = ui.add(
+                    egui::Slider::new(&mut at, 0.0..=seconds(duration))
+                        .suffix(" s")
+                        .text(format!("of {:.1} s", seconds(duration))),
+                );
+                if slider.changed() {
+                    self.seek(ClockTime::from_nseconds((at * 1e9) as u64));
+                }
+            }
+            None => {
+                ui.label(format!("{:.1} s", seconds(position)));
+            }
+        }
+
+        let rate = self.rate();
+        egui::ComboBox::from_label("Rate")
+            .selected_text(format!("{}×", rate))
+            .show_ui(ui, |ui| {
+                for r in RATES {
+                    if ui.selectable_label(r == rate, format!("{}×", r)).clicked() {
+                        self.set_rate(r);
+                    }
+                }
+            });
+
+        ui.label("Playlist:");
+        let mut play = None;
+        let mut remove = None;
+        for (i, source) in self.playlist.entries().iter().enumerate() {
+            ui.horizontal(|ui| {
+                let current = self.playlist.current() == Some(i);
+                if ui.selectable_label(current, source.to_string()).clicked() {
+                    play = Some(i);
+                }
+                if ui.small_button("✖").clicked() {
+                    remove = Some(i);
+                }
+            });
+        }
+        if let Some(i) = play {
+            self.play_entry(i);
+        }
+        if let Some(i) = remove {
+            self.playlist.remove(i);
+        }
+    }
+}
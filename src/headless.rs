@@ -0,0 +1,28 @@
+use glium::{
+    backend::glutin::headless::Headless,
+    glutin::{self, platform::unix::EventLoopExtUnix},
+};
+
+/// An offscreen GL context for processing without a window.
+pub struct HeadlessContext {
+    // The context is tied to the event loop it was created from
+    _event_loop: glutin::event_loop::EventLoop<()>,
+    pub facade: Headless,
+}
+
+impl HeadlessContext {
+    /// Works from any thread, since nothing is ever shown, which lets tests run on the GPU.
+    pub fn new() -> Self {
+        let event_loop = glutin::event_loop::EventLoop::new_any_thread();
+        let context = glutin::ContextBuilder::new()
+            .with_gl_profile(glutin::GlProfile::Core)
+            .with_gl(glutin::GlRequest::Latest)
+            .build_headless(&event_loop, glutin::dpi::PhysicalSize::new(1, 1))
+            .unwrap();
+        let context = unsafe { context.make_current() }.unwrap();
+        Self {
+            _event_loop: event_loop,
+            facade: Headless::new(context).unwrap(),
+        }
+    }
+}
@@ -0,0 +1,382 @@
+use std::{fmt, fs, io, path::Path};
+
+use crate::{
+    color_space::{invert, mat_mul},
+    transfer::Primaries,
+};
+
+/// Tone response curve of one channel, from device value to linear light.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Curve {
+    Gamma(f32),
+    /// Samples spread evenly over the device range
+    Table(Vec<f32>),
+    /// ICC parametric curve of the given function type, with its parameters g, a, b, c, d, e, f
+    Parametric {
+        kind: u16,
+        params: [f32; 7],
+    },
+}
+
+impl Curve {
+    pub fn eval(&self, x: f32) -> f32 {
+        let x = x.clamp(0., 1.);
+        match self {
+            Curve::Gamma(g) => x.powf(*g),
+            Curve::Table(table) => {
+                let pos = x * (table.len() - 1) as f32;
+                let i = (pos.floor() as usize).min(table.len() - 2);
+                let t = pos - i as f32;
+                table[i] * (1. - t) + table[i + 1] * t
+            }
+            Curve::Parametric { kind, params } => {
+                let [g, a, b, c, d, e, f] = *params;
+                match kind {
+                    0 => x.powf(g),
+                    1 if x >= -b / a => (a * x + b).powf(g),
+                    1 => 0.,
+                    2 if x >= -b / a => (a * x + b).powf(g) + c,
+                    2 => c,
+                    3 if x >= d => (a * x + b).powf(g),
+                    3 => c * x,
+                    _ if x >= d => (a * x + b).powf(g) + e,
+                    _ => c * x + f,
+                }
+            }
+        }
+    }
+
+    /// Device value giving linear light `y`, for monotonic curves.
+    pub fn inverse(&self, y: f32) -> f32 {
+        let (mut low, mut high) = (0f32, 1f32);
+        for _ in 0..32 {
+            let mid = (low + high) / 2.;
+            if self.eval(mid) < y {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        (low + high) / 2.
+    }
+}
+
+#[derive(Debug)]
+pub enum IccError {
+    Io(io::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for IccError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IccError::Io(err) => write!(f, "Could not read ICC profile: {}", err),
+            IccError::Invalid(msg) => write!(f, "Unsupported ICC profile: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for IccError {}
+
+impl From<io::Error> for IccError {
+    fn from(err: io::Error) -> Self {
+        IccError::Io(err)
+    }
+}
+
+/// An RGB display profile of the matrix/TRC kind.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IccProfile {
+    /// Row-major matrix from linear device RGB to PCS XYZ (D50)
+    pub colorants: [[f32; 3]; 3],
+    pub trc: [Curve; 3],
+    /// Media white point in PCS XYZ
+    pub white: [f32; 3],
+    /// Luminance of the display white in cd/m², if the profile records it
+    pub luminance: Option<f32>,
+}
+
+// Bradford adaptation from D65 to the D50 of the profile connection space
+const BRADFORD_D65_TO_D50: [[f32; 3]; 3] = [
+    [1.0479, 0.0229, -0.0502],
+    [0.0296, 0.9904, -0.0171],
+    [-0.0092, 0.0151, 0.7519],
+];
+
+// Big-endian reader over the profile bytes
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn bytes(&self, offset: usize, len: usize) -> Result<&[u8], IccError> {
+        self.0
+            .get(offset..offset + len)
+            .ok_or_else(|| IccError::Invalid("truncated".to_string()))
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16, IccError> {
+        let b = self.bytes(offset, 2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32, IccError> {
+        let b = self.bytes(offset, 4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn s15f16(&self, offset: usize) -> Result<f32, IccError> {
+        Ok(self.u32(offset)? as i32 as f32 / 65536.)
+    }
+
+    fn signature(&self, offset: usize) -> Result<&[u8], IccError> {
+        self.bytes(offset, 4)
+    }
+}
+
+// D50 white of the profile connection space
+const PCS_WHITE: [f32; 3] = [0.9642, 1., 0.8249];
+
+impl IccProfile {
+    /// A profile of a display with the given primaries and D65 white.
+    pub fn from_curves(primaries: Primaries, trc: [Curve; 3], luminance: Option<f32>) -> Self {
+        Self {
+            colorants: mat_mul(&BRADFORD_D65_TO_D50, &primaries.to_xyz()),
+            trc,
+            white: PCS_WHITE,
+            luminance,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, IccError> {
+        Self::parse(&fs::read(path)?)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, IccError> {
+        let r = Reader(data);
+        if r.signature(36)? != b"acsp" {
+            return Err(IccError::Invalid("not an ICC profile".to_string()));
+        }
+        if r.signature(16)? != b"RGB " || r.signature(20)? != b"XYZ " {
+            return Err(IccError::Invalid(
+                "only RGB profiles with an XYZ connection space are supported".to_string(),
+            ));
+        }
+
+        let tag_count = r.u32(128)? as usize;
+        let find = |sig: &[u8; 4]| -> Result<Option<usize>, IccError> {
+            for i in 0..tag_count {
+                let entry = 132 + 12 * i;
+                if r.signature(entry)? == sig {
+                    return Ok(Some(r.u32(entry + 4)? as usize));
+                }
+            }
+            Ok(None)
+        };
+        let require = |sig: &[u8; 4]| -> Result<usize, IccError> {
+            find(sig)?.ok_or_else(|| {
+                IccError::Invalid(format!(
+                    "missing {} tag, only matrix/TRC profiles are supported",
+                    String::from_utf8_lossy(sig)
+                ))
+            })
+        };
+        let xyz = |offset: usize| -> Result<[f32; 3], IccError> {
+            if r.signature(offset)? != b"XYZ " {
+                return Err(IccError::Invalid("malformed XYZ tag".to_string()));
+            }
+            Ok([
+                r.s15f16(offset + 8)?,
+                r.s15f16(offset + 12)?,
+                r.s15f16(offset + 16)?,
+            ])
+        };
+        let curve = |offset: usize| -> Result<Curve, IccError> {
+            match r.signature(offset)? {
+                b"curv" => {
+                    let count = r.u32(offset + 8)? as usize;
+                    match count {
+                        0 => Ok(Curve::Gamma(1.)),
+                        1 => Ok(Curve::Gamma(r.u16(offset + 12)? as f32 / 256.)),
+                        _ => Ok(Curve::Table(
+                            (0..count)
+                                .map(|i| Ok(r.u16(offset + 12 + 2 * i)? as f32 / 65535.))
+                                .collect::<Result<_, IccError>>()?,
+                        )),
+                    }
+                }
+                b"para" => {
+                    let kind = r.u16(offset + 8)?;
+                    let count = match kind {
+                        0 => 1,
+                        1 => 3,
+                        2 => 4,
+                        3 => 5,
+                        4 => 7,
+                        _ => return Err(IccError::Invalid("unknown parametric curve".to_string())),
+                    };
+                    let mut params = [0.; 7];
+                    for (i, param) in params.iter_mut().enumerate().take(count) {
+                        *param = r.s15f16(offset + 12 + 4 * i)?;
+                    }
+                    Ok(Curve::Parametric { kind, params })
+                }
+                _ => Err(IccError::Invalid("unsupported curve type".to_string())),
+            }
+        };
+
+        let columns = [
+            xyz(require(b"rXYZ")?)?,
+            xyz(require(b"gXYZ")?)?,
+            xyz(require(b"bXYZ")?)?,
+        ];
+        Ok(Self {
+            colorants: [0, 1, 2].map(|i| columns.map(|c| c[i])),
+            trc: [
+                curve(require(b"rTRC")?)?,
+                curve(require(b"gTRC")?)?,
+                curve(require(b"bTRC")?)?,
+            ],
+            white: match find(b"wtpt")? {
+                Some(offset) => xyz(offset)?,
+                None => PCS_WHITE,
+            },
+            luminance: find(b"lumi")?.map(xyz).transpose()?.map(|l| l[1]),
+        })
+    }
+
+    /// Luminance of the display white in cd/m², if the profile records it. `lumi` is measured
+    /// against the PCS white, so it is scaled by the luminance of the media white point.
+    pub fn white_luminance(&self) -> Option<f32> {
+        self.luminance.map(|luminance| luminance * self.white[1])
+    }
+
+    /// Black relative to white: the luminance the tone curves give at device zero.
+    pub fn black_level(&self) -> f32 {
+        // The Y row of the colorants weighs the channels by their share of white
+        let y = self.colorants[1];
+        let black: f32 = (0..3).map(|i| y[i] * self.trc[i].eval(0.)).sum();
+        black / y.iter().sum::<f32>()
+    }
+
+    /// Row-major matrix from linear RGB in `primaries`, relative to white, to linear device RGB.
+    pub fn output_matrix(&self, primaries: Primaries) -> [[f32; 3]; 3] {
+        mat_mul(
+            &invert(&self.colorants),
+            &mat_mul(&BRADFORD_D65_TO_D50, &primaries.to_xyz()),
+        )
+    }
+
+    /// Device values for linear light `(i / (size - 1))²`, per channel.
+    ///
+    /// Squaring spends more of the table on dark values, where inverse curves are steep.
+    pub fn inverse_trc_lut(&self, size: usize) -> Vec<(f32, f32, f32)> {
+        (0..size)
+            .map(|i| {
+                let x = i as f32 / (size - 1) as f32;
+                let y = x * x;
+                (
+                    self.trc[0].inverse(y),
+                    self.trc[1].inverse(y),
+                    self.trc[2].inverse(y),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn s15f16(v: f32) -> [u8; 4] {
+        ((v * 65536.).round() as i32).to_be_bytes()
+    }
+
+    fn xyz_tag(v: [f32; 3]) -> Vec<u8> {
+        let mut tag = b"XYZ \0\0\0\0".to_vec();
+        for c in v {
+            tag.extend_from_slice(&s15f16(c));
+        }
+        tag
+    }
+
+    // sRGB-like profile with a gamma 2.2 curve
+    fn profile() -> Vec<u8> {
+        let mut tags: Vec<(&[u8; 4], Vec<u8>)> = vec![
+            (b"rXYZ", xyz_tag([0.4361, 0.2225, 0.0139])),
+            (b"gXYZ", xyz_tag([0.3851, 0.7169, 0.0971])),
+            (b"bXYZ", xyz_tag([0.1431, 0.0606, 0.7141])),
+            (b"wtpt", xyz_tag([0.9642, 1., 0.8249])),
+            (b"lumi", xyz_tag([0., 160., 0.])),
+        ];
+        let mut curve = b"curv\0\0\0\0".to_vec();
+        curve.extend_from_slice(&1u32.to_be_bytes());
+        curve.extend_from_slice(&((2.2 * 256.) as u16).to_be_bytes());
+        curve.extend_from_slice(&[0, 0]);
+        for sig in [b"rTRC", b"gTRC", b"bTRC"] {
+            tags.push((sig, curve.clone()));
+        }
+
+        let mut data = vec![0u8; 128];
+        data[16..20].copy_from_slice(b"RGB ");
+        data[20..24].copy_from_slice(b"XYZ ");
+        data[36..40].copy_from_slice(b"acsp");
+        data.extend_from_slice(&(tags.len() as u32).to_be_bytes());
+        let mut offset = 132 + 12 * tags.len();
+        let mut bodies = vec![];
+        for (sig, body) in &tags {
+            data.extend_from_slice(*sig);
+            data.extend_from_slice(&(offset as u32).to_be_bytes());
+            data.extend_from_slice(&(body.len() as u32).to_be_bytes());
+            offset += body.len();
+            bodies.extend_from_slice(body);
+        }
+        data.extend_from_slice(&bodies);
+        data
+    }
+
+    #[test]
+    fn test_parse() {
+        let profile = IccProfile::parse(&profile()).unwrap();
+        assert_eq!(profile.luminance, Some(160.));
+        assert!(matches!(profile.trc[1], Curve::Gamma(g) if (g - 2.2).abs() < 1e-2));
+        // D65 white lands on device white
+        let m = profile.output_matrix(Primaries::Bt709);
+        for row in m {
+            assert!((row.iter().sum::<f32>() - 1.).abs() < 1e-2, "{:?}", m);
+        }
+        assert!(IccProfile::parse(&[0; 200]).is_err());
+    }
+
+    #[test]
+    fn test_white_and_black() {
+        let profile = IccProfile::parse(&profile()).unwrap();
+        assert!((profile.white_luminance().unwrap() - 160.).abs() < 1e-2);
+        assert_eq!(profile.black_level(), 0.);
+        // A dimmer media white than the PCS white, and curves that do not reach zero
+        let lifted = Curve::Parametric {
+            kind: 2,
+            params: [2.2, 1., 0., 0.001, 0., 0., 0.],
+        };
+        let profile = IccProfile {
+            white: [0.9642 * 0.8, 0.8, 0.8249 * 0.8],
+            trc: [lifted.clone(), lifted.clone(), lifted],
+            ..profile
+        };
+        assert!((profile.white_luminance().unwrap() - 128.).abs() < 1e-2);
+        assert!((profile.black_level() - 0.001).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_curves() {
+        let table = Curve::Table(vec![0., 0.25, 1.]);
+        assert!((table.eval(0.25) - 0.125).abs() < 1e-6);
+        assert!((table.inverse(0.125) - 0.25).abs() < 1e-5);
+        // sRGB as a type 3 parametric curve
+        let srgb = Curve::Parametric {
+            kind: 3,
+            params: [2.4, 1. / 1.055, 0.055 / 1.055, 1. / 12.92, 0.04045, 0., 0.],
+        };
+        assert!((srgb.eval(0.5) - 0.214).abs() < 1e-3);
+        assert!((srgb.inverse(srgb.eval(0.02)) - 0.02).abs() < 1e-5);
+    }
+}
@@ -1,6 +1,6 @@
 use glium::{
     backend::Facade, implement_vertex, index::PrimitiveType, program::ProgramCreationInput,
-    uniforms::Uniforms, IndexBuffer, Program, Surface, VertexBuffer,
+    uniforms::Uniforms, DrawParameters, IndexBuffer, Program, Rect, Surface, VertexBuffer,
 };
 
 #[derive(Copy, Clone)]
@@ -18,6 +18,12 @@ pub struct ImageShader {
 
 impl ImageShader {
     pub fn new(display: &dyn Facade, fragment_src: &str) -> Self {
+        Self::with_options(display, fragment_src, false)
+    }
+
+    /// With `outputs_srgb` the fragment shader writes display encoded values and the
+    /// framebuffer's own sRGB conversion is disabled.
+    pub fn with_options(display: &dyn Facade, fragment_src: &str, outputs_srgb: bool) -> Self {
         let vertices = {
             glium::VertexBuffer::new(
                 display,
@@ -64,7 +70,7 @@ impl ImageShader {
             geometry_shader: None,
             fragment_shader: fragment_src,
             transform_feedback_varyings: None,
-            outputs_srgb: false,
+            outputs_srgb,
             uses_point_size: false,
         };
 
@@ -78,6 +84,30 @@ impl ImageShader {
     }
 
     pub fn draw<S, U>(&self, surface: &mut S, uniforms: &U)
+    where
+        S: Surface,
+        U: Uniforms,
+    {
+        self.draw_with(surface, uniforms, &Default::default());
+    }
+
+    /// Draws into `viewport` only, leaving the rest of the surface as it was.
+    pub fn draw_in<S, U>(&self, surface: &mut S, uniforms: &U, viewport: Rect)
+    where
+        S: Surface,
+        U: Uniforms,
+    {
+        self.draw_with(
+            surface,
+            uniforms,
+            &DrawParameters {
+                viewport: Some(viewport),
+                ..Default::default()
+            },
+        );
+    }
+
+    fn draw_with<S, U>(&self, surface: &mut S, uniforms: &U, params: &DrawParameters)
     where
         S: Surface,
         U: Uniforms,
@@ -88,7 +118,7 @@ impl ImageShader {
                 &self.indices,
                 &self.shader,
                 uniforms,
-                &Default::default(),
+                params,
             )
             .unwrap();
     }
@@ -0,0 +1,48 @@
+//! The `csfcontrast` GStreamer plugin, which runs the adaptation inside any GL pipeline:
+//!
+//! ```text
+//! gst-launch-1.0 videotestsrc ! glupload ! csfcontrast distance=750 target-distance=2000 ! glimagesink
+//! ```
+//!
+//! The viewer in `main.rs` and its command line tools are built on the same modules.
+
+pub mod batch;
+pub mod calibration;
+pub mod color_space;
+pub mod csf;
+mod element;
+pub mod fft;
+pub mod frequency_filter;
+pub mod glsl;
+pub mod grating;
+pub mod gstreamer;
+pub mod gui;
+pub mod headless;
+pub mod icc;
+pub mod image_shader;
+pub mod output;
+pub mod perception_adapter;
+pub mod precision;
+pub mod processor;
+pub mod profiler;
+pub mod system;
+pub mod tiled;
+pub mod transcode;
+pub mod transfer;
+pub mod viewing;
+
+// `gstreamer` alone names the module of the viewer here
+fn plugin_init(plugin: &::gstreamer::Plugin) -> Result<(), glib::BoolError> {
+    element::register(Some(plugin))
+}
+
+::gstreamer::plugin_define!(
+    csfcontrast,
+    "Contrast adaptation for viewing distance based on the contrast sensitivity function",
+    plugin_init,
+    env!("CARGO_PKG_VERSION"),
+    "GPL",
+    env!("CARGO_PKG_NAME"),
+    env!("CARGO_PKG_NAME"),
+    env!("CARGO_PKG_REPOSITORY")
+);
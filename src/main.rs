@@ -1,27 +1,74 @@
 use std::{cell::RefCell, rc::Rc};
 
+use cli::Command;
+use csfcontrast::{
+    batch, gui, headless, output::Framebuffer, processor, system::System, tiled, transcode,
+};
 use glium::glutin;
-use system::System;
-
-mod color_space;
-mod csf;
-mod fft;
-mod grating;
-mod gstreamer;
-mod gui;
-mod image_shader;
-mod perception_adapter;
-mod system;
 
-fn main() {
-    let event_loop = glutin::event_loop::EventLoop::with_user_event();
-    let display = create_display(&event_loop);
+mod cli;
 
+fn main() {
     let args: Vec<_> = std::env::args().collect();
-    let default = "".to_string();
-    let uri = args.get(1).unwrap_or(&default);
+    let command = match cli::parse(&args) {
+        Ok(command) => command,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
 
-    let system = Rc::new(RefCell::new(System::new(&display, uri)));
+    match command {
+        Command::View {
+            uri,
+            framebuffer,
+            icc,
+            output_pipeline,
+        } => view(
+            &uri,
+            framebuffer,
+            icc.as_deref(),
+            output_pipeline.as_deref(),
+        ),
+        Command::Tile(job) => {
+            let context = headless::HeadlessContext::new();
+            let mut processor = processor::Processor::new(&context.facade);
+            if let Err(err) = tiled::run(&context.facade, &mut processor, &job) {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        Command::Transcode(job) => {
+            let context = headless::HeadlessContext::new();
+            let mut processor = processor::Processor::new(&context.facade);
+            if let Err(err) = transcode::run(&context.facade, &mut processor, &job) {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        Command::Batch(job) => {
+            let context = headless::HeadlessContext::new();
+            let mut processor = processor::Processor::new(&context.facade);
+            if let Err(err) = batch::run(&context.facade, &mut processor, &job) {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn view(uri: &str, framebuffer: Framebuffer, icc: Option<&str>, output_pipeline: Option<&str>) {
+    let event_loop = glutin::event_loop::EventLoop::with_user_event();
+    let (display, framebuffer) = create_display(&event_loop, framebuffer);
+
+    let mut system = System::new(&display, uri, framebuffer);
+    if let Some(icc) = icc {
+        system.request_icc(icc);
+    }
+    if let Some(output_pipeline) = output_pipeline {
+        system.request_output_stream(output_pipeline);
+    }
+    let system = Rc::new(RefCell::new(system));
 
     gui::run(
         Box::new({
@@ -40,7 +87,11 @@ fn main() {
     );
 }
 
-fn create_display(event_loop: &glutin::event_loop::EventLoop<()>) -> glium::Display {
+// Falls back to an sRGB framebuffer when a float or 10-bit one is not available
+fn create_display(
+    event_loop: &glutin::event_loop::EventLoop<()>,
+    framebuffer: Framebuffer,
+) -> (glium::Display, Framebuffer) {
     let window_builder = glutin::window::WindowBuilder::new()
         .with_resizable(true)
         .with_fullscreen(Some(glutin::window::Fullscreen::Borderless(None)));
@@ -51,5 +102,32 @@ fn create_display(event_loop: &glutin::event_loop::EventLoop<()>) -> glium::Disp
         .with_gl_profile(glutin::GlProfile::Core)
         .with_gl(glutin::GlRequest::Latest);
 
-    glium::Display::new(window_builder, context_builder, event_loop).unwrap()
+    if framebuffer == Framebuffer::Float {
+        let mut float_builder = context_builder.clone().with_srgb(false);
+        float_builder.pf_reqs.float_color_buffer = true;
+        match glium::Display::new(window_builder.clone(), float_builder, event_loop) {
+            Ok(display) => return (display, Framebuffer::Float),
+            Err(err) => eprintln!("No float framebuffer, using sRGB instead: {}", err),
+        }
+    }
+
+    // The driver may hand out 8 bits anyway, so check what was granted
+    if framebuffer == Framebuffer::TenBit {
+        let deep_builder = context_builder
+            .clone()
+            .with_srgb(false)
+            .with_pixel_format(30, 2);
+        match glium::Display::new(window_builder.clone(), deep_builder, event_loop) {
+            Ok(display) if display.gl_window().get_pixel_format().color_bits >= 30 => {
+                return (display, Framebuffer::TenBit)
+            }
+            Ok(_) => eprintln!("No 10-bit framebuffer, using sRGB instead"),
+            Err(err) => eprintln!("No 10-bit framebuffer, using sRGB instead: {}", err),
+        }
+    }
+
+    (
+        glium::Display::new(window_builder, context_builder, event_loop).unwrap(),
+        Framebuffer::Srgb,
+    )
 }
@@ -0,0 +1,244 @@
+use std::str::FromStr;
+
+use glium::{
+    backend::Facade,
+    texture::{MipmapsOption, UncompressedFloatFormat},
+    uniform,
+    uniforms::{MagnifySamplerFilter, MinifySamplerFilter, SamplerWrapFunction},
+    Rect, Surface, Texture2d,
+};
+
+use crate::{
+    icc::IccProfile,
+    image_shader::ImageShader,
+    transfer::{glsl_mat3, with_transfer_functions, ProcessingDomain, TransferSettings},
+};
+
+/// Colour buffer of the window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Framebuffer {
+    /// 8 bits per channel, limited to the output peak
+    Srgb,
+    /// 10 bits per channel, limited to the output peak
+    TenBit,
+    /// Floating point, keeps luminance above the output peak for HDR displays
+    Float,
+}
+
+impl FromStr for Framebuffer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "srgb" => Ok(Framebuffer::Srgb),
+            "10bit" => Ok(Framebuffer::TenBit),
+            "float" => Ok(Framebuffer::Float),
+            _ => Err(format!("Unknown framebuffer: {}", s)),
+        }
+    }
+}
+
+impl Framebuffer {
+    /// Highest code value of the integer framebuffers.
+    pub fn max_code(&self) -> Option<f32> {
+        match self {
+            Framebuffer::Srgb => Some(255.),
+            Framebuffer::TenBit => Some(1023.),
+            Framebuffer::Float => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Framebuffer::Srgb => "8-bit",
+            Framebuffer::TenBit => "10-bit",
+            Framebuffer::Float => "float",
+        }
+    }
+}
+
+/// Encodes the working signal for the display.
+pub struct OutputStage {
+    shader: ImageShader,
+    framebuffer: Framebuffer,
+    /// Adds triangular noise of one code value before quantisation, which trades banding for
+    /// fine grain
+    pub dither: bool,
+    // Varies the noise from frame to frame
+    frame: u32,
+    profile: Option<IccProfile>,
+    // Inverse tone curves of the profile, or a placeholder without one
+    icc_trc: Texture2d,
+}
+
+const ICC_LUT_SIZE: usize = 4096;
+
+const IDENTITY: [[f32; 3]; 3] = [[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]];
+
+impl OutputStage {
+    pub fn new(facade: &dyn Facade, framebuffer: Framebuffer) -> Self {
+        // The shader does the encoding, so the framebuffer must not convert to sRGB again
+        let shader = ImageShader::with_options(
+            facade,
+            &with_transfer_functions(include_str!("output/frag.glsl")),
+            true,
+        );
+        Self {
+            shader,
+            framebuffer,
+            dither: true,
+            frame: 0,
+            profile: None,
+            icc_trc: Self::lut_texture(facade, vec![(0., 0., 0.); 2]),
+        }
+    }
+
+    fn lut_texture(facade: &dyn Facade, lut: Vec<(f32, f32, f32)>) -> Texture2d {
+        Texture2d::with_format(
+            facade,
+            vec![lut],
+            UncompressedFloatFormat::F32F32F32,
+            MipmapsOption::NoMipmap,
+        )
+        .unwrap()
+    }
+
+    /// Replaces the output transfer function and primaries with a display profile.
+    pub fn set_profile(&mut self, facade: &dyn Facade, profile: IccProfile) {
+        self.icc_trc = Self::lut_texture(facade, profile.inverse_trc_lut(ICC_LUT_SIZE));
+        self.profile = Some(profile);
+    }
+
+    pub fn clear_profile(&mut self) {
+        self.profile = None;
+    }
+
+    pub fn profile(&self) -> Option<&IccProfile> {
+        self.profile.as_ref()
+    }
+
+    pub fn framebuffer(&self) -> Framebuffer {
+        self.framebuffer
+    }
+
+    pub fn draw<S>(&mut self, surface: &mut S, texture: &Texture2d, transfer: &TransferSettings)
+    where
+        S: Surface,
+    {
+        self.draw_viewport(surface, texture, transfer, None);
+    }
+
+    /// Draws `texture` into `viewport` of the surface only.
+    pub fn draw_in<S>(
+        &mut self,
+        surface: &mut S,
+        texture: &Texture2d,
+        transfer: &TransferSettings,
+        viewport: Rect,
+    ) where
+        S: Surface,
+    {
+        self.draw_viewport(surface, texture, transfer, Some(viewport));
+    }
+
+    fn draw_viewport<S>(
+        &mut self,
+        surface: &mut S,
+        texture: &Texture2d,
+        transfer: &TransferSettings,
+        viewport: Option<Rect>,
+    ) where
+        S: Surface,
+    {
+        let (gamut, output_luma, output_peak, icc_matrix) = match self.profile.as_ref() {
+            // The profile takes over from the input primaries, so tone mapping stays in them
+            Some(profile) => (
+                IDENTITY,
+                transfer.input_primaries.luma_coefficients(),
+                profile.white_luminance().unwrap_or(transfer.sdr_white),
+                profile.output_matrix(transfer.input_primaries),
+            ),
+            None => (
+                transfer
+                    .input_primaries
+                    .conversion(transfer.output_primaries),
+                transfer.output_primaries.luma_coefficients(),
+                transfer.output.peak_luminance(transfer.sdr_white),
+                IDENTITY,
+            ),
+        };
+        self.frame = self.frame.wrapping_add(1);
+        let max_code = match self.framebuffer.max_code() {
+            Some(max_code) if self.dither => max_code,
+            _ => 0.,
+        };
+        let uniforms = uniform! {
+            tex: texture.sampled().magnify_filter(MagnifySamplerFilter::Nearest),
+            input_transfer: transfer.input.shader_id(),
+            input_param: transfer.input.shader_param(),
+            output_transfer: transfer.output.shader_id(),
+            output_param: transfer.output.shader_param(),
+            sdr_white: transfer.sdr_white,
+            output_peak: output_peak,
+            encoded: transfer.domain == ProcessingDomain::Encoded,
+            gamut: glsl_mat3(gamut),
+            output_luma: output_luma,
+            tone_mapping: transfer.tone_mapping.shader_id(),
+            content_peak: transfer.content_peak(),
+            extended: self.framebuffer == Framebuffer::Float,
+            dither_max_code: max_code,
+            frame: self.frame,
+            icc: self.profile.is_some(),
+            icc_matrix: glsl_mat3(icc_matrix),
+            icc_trc: self
+                .icc_trc
+                .sampled()
+                .magnify_filter(MagnifySamplerFilter::Linear)
+                .minify_filter(MinifySamplerFilter::Linear)
+                .wrap_function(SamplerWrapFunction::Clamp),
+        };
+        match viewport {
+            Some(viewport) => self.shader.draw_in(surface, &uniforms, viewport),
+            None => self.shader.draw(surface, &uniforms),
+        }
+    }
+}
+
+/// The largest rectangle of the aspect ratio of `content` centred in `area`, with bars along
+/// the other sides.
+pub fn fit(content: (u32, u32), area: (u32, u32)) -> Rect {
+    let (cw, ch) = (content.0.max(1) as u64, content.1.max(1) as u64);
+    let (aw, ah) = (area.0 as u64, area.1 as u64);
+    let (width, height) = if aw * ch > ah * cw {
+        // Pillarbox
+        ((ah * cw / ch) as u32, area.1)
+    } else {
+        // Letterbox
+        (area.0, (aw * ch / cw) as u32)
+    };
+    Rect {
+        left: (area.0 - width) / 2,
+        bottom: (area.1 - height) / 2,
+        width,
+        height,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit() {
+        let rect = |left, bottom, width, height| Rect {
+            left,
+            bottom,
+            width,
+            height,
+        };
+        assert_eq!(fit((1920, 1080), (1920, 1080)), rect(0, 0, 1920, 1080));
+        assert_eq!(fit((3840, 2160), (1920, 1200)), rect(0, 60, 1920, 1080));
+        assert_eq!(fit((1080, 1920), (1920, 1080)), rect(656, 0, 607, 1080));
+        assert_eq!(fit((640, 480), (1920, 1080)), rect(240, 0, 1440, 1080));
+    }
+}
@@ -0,0 +1,81 @@
+#version 300 es
+precision highp float;
+
+uniform sampler2D tex;
+in vec2 tex_coord;
+
+uniform int input_transfer;
+uniform float input_param;
+uniform int output_transfer;
+uniform float output_param;
+uniform float sdr_white;
+// Luminance in cd/m² of a full output signal
+uniform float output_peak;
+// The working signal is still encoded with the input transfer function
+uniform bool encoded;
+// From the input primaries to the output primaries
+uniform mat3 gamut;
+uniform vec3 output_luma;
+uniform int tone_mapping;
+uniform float content_peak;
+// Float framebuffer: keep values above the output peak
+uniform bool extended;
+
+// ICC display profile: matrix to linear device RGB and inverse tone curves
+uniform bool icc;
+uniform mat3 icc_matrix;
+uniform sampler2D icc_trc;
+
+// Highest code value of the framebuffer to dither for, 0 without dithering
+uniform float dither_max_code;
+uniform uint frame;
+
+out vec4 f_color;
+
+// PCG hash
+uint hash(uint v) {
+  uint state = v * 747796405u + 2891336453u;
+  uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+  return (word >> 22u) ^ word;
+}
+
+float uniform_noise(uint seed) {
+  return float(hash(seed)) / 4294967295.0;
+}
+
+// Triangular PDF noise between -1 and 1 code value, independent per channel and frame
+vec3 tpdf_noise() {
+  uvec2 pixel = uvec2(gl_FragCoord.xy);
+  uint seed = hash(pixel.x + hash(pixel.y + hash(frame)));
+  vec3 noise;
+  for (uint c = 0u; c < 3u; c++) {
+    uint channel_seed = hash(seed + c);
+    noise[c] = uniform_noise(channel_seed) + uniform_noise(hash(channel_seed)) - 1.0;
+  }
+  return noise;
+}
+
+// The table is indexed by the square root of linear light
+vec3 icc_encode(vec3 device) {
+  float size = float(textureSize(icc_trc, 0).x);
+  vec3 coord = (sqrt(device) * (size - 1.0) + 0.5) / size;
+  return vec3(texture(icc_trc, vec2(coord.r, 0.5)).r, texture(icc_trc, vec2(coord.g, 0.5)).g,
+              texture(icc_trc, vec2(coord.b, 0.5)).b);
+}
+
+void main() {
+  vec4 color = texture(tex, tex_coord);
+  vec3 linear = encoded ? eotf_rgb(color.rgb, input_transfer, input_param, sdr_white) : color.rgb;
+  vec3 relative = gamut * linear / output_peak;
+  if (!extended || icc) {
+    relative = clamp(tone_map(relative, tone_mapping, content_peak / output_peak, output_luma), 0.0, 1.0);
+  }
+  if (icc) {
+    f_color = vec4(icc_encode(clamp(icc_matrix * relative, 0.0, 1.0)), color.a);
+  } else {
+    f_color = vec4(inverse_eotf_rgb(relative * output_peak, output_transfer, output_param, sdr_white), color.a);
+  }
+  if (dither_max_code > 0.0) {
+    f_color.rgb = clamp(f_color.rgb + tpdf_noise() / dither_max_code, 0.0, 1.0);
+  }
+}
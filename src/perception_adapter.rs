@@ -1,55 +1,83 @@
-use glium::{
-    backend::Facade, implement_uniform_block, program::ComputeShader, uniform,
-    uniforms::UniformBuffer, Texture2d,
-};
+use glium::{backend::Facade, implement_uniform_block, uniform, uniforms::UniformBuffer};
 
-use crate::csf::Csf;
+use crate::{
+    calibration::DisplayCalibration,
+    csf::Csf,
+    fft::FftTexture,
+    frequency_filter::{FilterShader, FrequencyFilter, SpectrumInfo},
+    processor::AdaptParams,
+};
 
+/// Compensates the contrast loss of viewing from the target distance according to the CSF.
 pub struct PerceptionAdapter {
-    shader: ComputeShader,
+    shader: FilterShader,
     csf_upload: CsfUpload,
+    csf: Csf,
+    // The table the shader samples, so that `gain` agrees with it
+    lut: Box<CsfLut>,
+    target_pixels_per_visual_degree: f32,
+    // Makes up for the contrast lost to black level and reflections
+    contrast_boost: f32,
 }
 
 impl PerceptionAdapter {
     pub fn new(facade: &dyn Facade) -> Self {
-        let shader =
-            ComputeShader::from_source(facade, include_str!("perception_adapter/comp.glsl"))
-                .unwrap();
+        let shader = FilterShader::new(facade, include_str!("perception_adapter/comp.glsl"));
         Self {
             shader,
             csf_upload: CsfUpload::new(facade),
+            csf: CsfUpload::empty_csf(),
+            lut: Box::new(CsfLut::from_csf(&CsfUpload::empty_csf())),
+            target_pixels_per_visual_degree: 0.,
+            contrast_boost: 1.,
+        }
+    }
+
+    pub fn set_viewing(&mut self, csf: &Csf, target_pixels_per_visual_degree: f32) {
+        if self.csf != *csf {
+            self.csf = csf.clone();
+            self.lut = Box::new(CsfLut::from_csf(csf));
         }
+        self.target_pixels_per_visual_degree = target_pixels_per_visual_degree;
+    }
+
+    pub fn set_calibration(&mut self, calibration: &DisplayCalibration) {
+        self.contrast_boost = calibration.contrast_boost();
+    }
+}
+
+impl FrequencyFilter for PerceptionAdapter {
+    fn name(&self) -> &str {
+        "CSF compensation"
+    }
+
+    fn gain(&self, cpd: f32, info: &SpectrumInfo) -> f32 {
+        let target_cpd = cpd * self.target_pixels_per_visual_degree / info.pixels_per_visual_degree;
+        self.contrast_boost * self.lut.sample(target_cpd) / self.lut.sample(cpd)
     }
 
-    pub fn draw(
-        &mut self,
-        facade: &dyn Facade,
-        real_texture: &Texture2d,
-        imag_texture: &Texture2d,
-        pixels_per_visual_degree: f32,
-        csf: &Csf,
-        target_pixels_per_visual_degree: f32,
-    ) {
-        use glium::uniforms::ImageUnitFormat::RGBA32F;
-        let real_unit = real_texture.image_unit(RGBA32F).unwrap();
-        let imag_unit = imag_texture.image_unit(RGBA32F).unwrap();
-        let csf_lut = self.csf_upload.ubuffer(facade, csf);
+    fn dispatch(&mut self, facade: &dyn Facade, spectrum: &FftTexture, info: &SpectrumInfo) {
+        let csf_lut = self.csf_upload.ubuffer(facade, &self.csf);
         self.shader.execute(
+            spectrum,
+            info,
             uniform! {
-                                    realPart: real_unit,
-                        imagPart: imag_unit,
-                    pixels_per_visual_degree: pixels_per_visual_degree,
-            target_pixels_per_visual_degree: target_pixels_per_visual_degree,
+                target_pixels_per_visual_degree: self.target_pixels_per_visual_degree,
+                contrast_boost: self.contrast_boost,
                 CsfLut: csf_lut,
-
-                                },
-            real_texture.width() / 64 + 1,
-            real_texture.height(),
-            1,
+            },
         )
     }
+
+    fn set_params(&mut self, params: &AdaptParams) {
+        self.set_viewing(&params.csf, params.target_pixels_per_visual_degree);
+        self.set_calibration(&params.calibration);
+    }
 }
 
+const CSF_LUT_LOWER: f32 = 0.;
+const CSF_LUT_UPPER: f32 = 50.;
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 struct CsfLut {
@@ -60,8 +88,8 @@ struct CsfLut {
 
 impl CsfLut {
     pub fn from_csf(csf: &Csf) -> Self {
-        let lut_lower_limit = 0.;
-        let lut_upper_limit = 50.;
+        let lut_lower_limit = CSF_LUT_LOWER;
+        let lut_upper_limit = CSF_LUT_UPPER;
         let points = csf
             .plot_points(lut_lower_limit, lut_upper_limit, 4096)
             .map(|(_, y)| y);
@@ -71,6 +99,13 @@ impl CsfLut {
             lut_array: points.collect::<Vec<_>>().try_into().unwrap(),
         }
     }
+
+    // Same lookup as `sampleLut` of comp.glsl
+    fn sample(&self, x: f32) -> f32 {
+        let adjusted = (x - self.lut_lower_limit) / (self.lut_upper_limit - self.lut_lower_limit);
+        let index = adjusted.clamp(0., 1.) * (self.lut_array.len() - 1) as f32;
+        self.lut_array[index as usize]
+    }
 }
 
 implement_uniform_block!(CsfLut, lut_lower_limit, lut_upper_limit, lut_array,);
@@ -85,12 +120,16 @@ impl CsfUpload {
         let ubuffer = UniformBuffer::empty(facade).unwrap();
         Self {
             ubuffer,
-            cached_csf: Csf {
-                a: 0.,
-                ω: 0.,
-                σ: 0.,
-                k: 0.,
-            },
+            cached_csf: Self::empty_csf(),
+        }
+    }
+
+    fn empty_csf() -> Csf {
+        Csf {
+            a: 0.,
+            ω: 0.,
+            σ: 0.,
+            k: 0.,
         }
     }
 
@@ -0,0 +1,156 @@
+use glium::{
+    backend::Facade, program::ComputeShader, texture::UncompressedFloatFormat,
+    uniforms::ImageUnitFormat, Rect, Texture2d,
+};
+
+use crate::glsl::insert_after_version;
+
+/// Storage precision of the spectrum and the working textures of the adaptation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Precision {
+    Full,
+    Half,
+}
+
+impl Precision {
+    pub fn texture_format(&self) -> UncompressedFloatFormat {
+        match self {
+            Precision::Full => UncompressedFloatFormat::F32F32F32F32,
+            Precision::Half => UncompressedFloatFormat::F16F16F16F16,
+        }
+    }
+
+    pub fn image_unit_format(&self) -> ImageUnitFormat {
+        match self {
+            Precision::Full => ImageUnitFormat::RGBA32F,
+            Precision::Half => ImageUnitFormat::RGBA16F,
+        }
+    }
+
+    fn glsl_format(&self) -> &'static str {
+        match self {
+            Precision::Full => "rgba32f",
+            Precision::Half => "rgba16f",
+        }
+    }
+
+    fn shader_source(&self, src: &str) -> String {
+        insert_after_version(src, &format!("#define IMAGE_FORMAT {}", self.glsl_format()))
+    }
+}
+
+/// A compute shader whose image layout qualifiers are compiled for every `Precision`.
+pub struct PrecisionShader {
+    full: ComputeShader,
+    half: ComputeShader,
+}
+
+impl PrecisionShader {
+    pub fn new(facade: &dyn Facade, src: &str) -> Self {
+        let compile = |precision: Precision| {
+            ComputeShader::from_source(facade, &precision.shader_source(src)).unwrap()
+        };
+        Self {
+            full: compile(Precision::Full),
+            half: compile(Precision::Half),
+        }
+    }
+
+    pub fn get(&self, precision: Precision) -> &ComputeShader {
+        match precision {
+            Precision::Full => &self.full,
+            Precision::Half => &self.half,
+        }
+    }
+}
+
+// Reads a float texture back to the CPU. This stalls the pipeline.
+pub fn read_rgba_f32(texture: &Texture2d) -> Vec<Vec<(f32, f32, f32, f32)>> {
+    let rect = Rect {
+        left: 0,
+        bottom: 0,
+        width: texture.width(),
+        height: texture.height(),
+    };
+    texture
+        .main_level()
+        .first_layer()
+        .into_image(None)
+        .unwrap()
+        .raw_read(&rect)
+}
+
+/// Difference between the adapted output at half precision and at full precision.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ErrorReport {
+    pub rms: [f64; 3],
+    pub max_abs: [f64; 3],
+    pub psnr_db: f64,
+}
+
+impl ErrorReport {
+    pub fn compare(
+        reference: &[Vec<(f32, f32, f32, f32)>],
+        test: &[Vec<(f32, f32, f32, f32)>],
+    ) -> Self {
+        let mut sum_sq = [0f64; 3];
+        let mut max_abs = [0f64; 3];
+        let mut count = 0usize;
+        for (ref_row, test_row) in reference.iter().zip(test.iter()) {
+            for (r, t) in ref_row.iter().zip(test_row.iter()) {
+                let diffs = [(r.0 - t.0) as f64, (r.1 - t.1) as f64, (r.2 - t.2) as f64];
+                for (c, d) in diffs.iter().enumerate() {
+                    sum_sq[c] += d * d;
+                    max_abs[c] = max_abs[c].max(d.abs());
+                }
+                count += 1;
+            }
+        }
+        let count = count.max(1) as f64;
+        let rms = sum_sq.map(|s| (s / count).sqrt());
+        let mse = sum_sq.iter().sum::<f64>() / (3. * count);
+        Self {
+            rms,
+            max_abs,
+            // Signal is in [0, 1]
+            psnr_db: -10. * mse.log10(),
+        }
+    }
+
+    pub fn draw_ui(&self, ui: &mut egui::Ui) {
+        ui.label(format!(
+            "RMS error: {:.2e} / {:.2e} / {:.2e}",
+            self.rms[0], self.rms[1], self.rms[2]
+        ));
+        ui.label(format!(
+            "Max error: {:.2e} / {:.2e} / {:.2e}",
+            self.max_abs[0], self.max_abs[1], self.max_abs[2]
+        ));
+        ui.label(format!("PSNR: {:.1} dB", self.psnr_db));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shader_source() {
+        assert_eq!(
+            Precision::Half.shader_source("// x\n#version 430 core\nlayout (IMAGE_FORMAT) x;"),
+            "// x\n#version 430 core\n#define IMAGE_FORMAT rgba16f\nlayout (IMAGE_FORMAT) x;"
+        );
+    }
+
+    #[test]
+    fn test_compare() {
+        let reference = vec![vec![(0.5, 0.5, 0.5, 1.); 4]; 4];
+        let mut test = reference.clone();
+        test[0][0].0 = 0.6;
+        let report = ErrorReport::compare(&reference, &test);
+        assert!((report.max_abs[0] - 0.1).abs() < 1e-6);
+        assert_eq!(report.max_abs[1], 0.);
+        assert!((report.rms[0] - 0.025).abs() < 1e-6);
+        assert!(report.psnr_db > 30.);
+    }
+}
@@ -0,0 +1,104 @@
+use glium::{backend::Facade, Texture2d};
+
+use crate::{
+    calibration::DisplayCalibration,
+    color_space::{ColorSpace, WorkingSpace},
+    csf::Csf,
+    fft::{AllocationError, Fft, FftTexture},
+    frequency_filter::{FilterChain, FrequencyFilter, SpectrumInfo},
+    perception_adapter::PerceptionAdapter,
+    precision::Precision,
+    profiler::{GpuProfiler, Stage},
+    transfer::TransferSettings,
+};
+
+/// Parameters of a single run of the adaptation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AdaptParams {
+    pub csf: Csf,
+    pub pixels_per_visual_degree: f32,
+    pub target_pixels_per_visual_degree: f32,
+    pub precision: Precision,
+    pub working_space: WorkingSpace,
+    /// Describes the working signal of the input
+    pub transfer: TransferSettings,
+    pub calibration: DisplayCalibration,
+}
+
+/// The adaptation chain: conversion to the working space, FFT, CSF compensation and filters, inverse FFT.
+pub struct Processor {
+    fft: Fft,
+    color_space: ColorSpace,
+    /// Starts with the CSF compensation, enabled
+    pub filters: FilterChain,
+}
+
+impl Processor {
+    pub fn new(facade: &dyn Facade) -> Self {
+        let mut filters = FilterChain::new();
+        filters.push(Box::new(PerceptionAdapter::new(facade)), true);
+        Self {
+            fft: Fft::new(facade),
+            color_space: ColorSpace::new(facade),
+            filters,
+        }
+    }
+
+    // Runs the adaptation on the input, leaving the result in the FFT texture's original image.
+    // Fails when the spectrum of an input of this size cannot be allocated.
+    pub fn process(
+        &mut self,
+        facade: &dyn Facade,
+        input: &Texture2d,
+        params: &AdaptParams,
+        profiler: &mut GpuProfiler,
+    ) -> Result<&FftTexture, AllocationError> {
+        let precision = params.precision;
+        let fft_tex = self.fft.process_texture(facade, input, precision)?;
+        profiler.end(Stage::Resample);
+
+        profiler.begin(Stage::ToWorkingSpace);
+        self.color_space.rgb_to_working(
+            fft_tex.orig(),
+            precision,
+            &params.working_space,
+            &params.transfer,
+        );
+        profiler.end(Stage::ToWorkingSpace);
+
+        profiler.begin(Stage::ForwardFft);
+        fft_tex.fft(facade);
+        profiler.end(Stage::ForwardFft);
+
+        let info = SpectrumInfo::new(fft_tex, params.pixels_per_visual_degree);
+        profiler.begin(Stage::Filters);
+        self.filters.set_params(params);
+        self.filters.dispatch(facade, fft_tex, &info);
+        profiler.end(Stage::Filters);
+
+        profiler.begin(Stage::InverseFft);
+        fft_tex.ifft(facade);
+        profiler.end(Stage::InverseFft);
+
+        profiler.begin(Stage::FromWorkingSpace);
+        self.color_space.working_to_rgb(
+            fft_tex.orig(),
+            precision,
+            &params.working_space,
+            &params.transfer,
+        );
+        profiler.end(Stage::FromWorkingSpace);
+
+        Ok(fft_tex)
+    }
+
+    /// Result of the last `process` call.
+    pub fn output(&self) -> Option<&Texture2d> {
+        self.fft.texture().map(|t| t.orig())
+    }
+
+    /// Size the spectrum of the last `process` call was computed at.
+    pub fn padded_dimensions(&self) -> Option<(u32, u32)> {
+        self.fft.texture().map(|t| t.padded_dimensions())
+    }
+}
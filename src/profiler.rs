@@ -0,0 +1,351 @@
+use std::{collections::VecDeque, ffi::c_void, io::Write, path::Path};
+
+use glium::Display;
+
+/// Pipeline stages timed by the `GpuProfiler`, in the order they run in `System::draw`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    Upload,
+    Resample,
+    ToWorkingSpace,
+    ForwardFft,
+    Filters,
+    InverseFft,
+    FromWorkingSpace,
+    Blit,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 8] = [
+        Stage::Upload,
+        Stage::Resample,
+        Stage::ToWorkingSpace,
+        Stage::ForwardFft,
+        Stage::Filters,
+        Stage::InverseFft,
+        Stage::FromWorkingSpace,
+        Stage::Blit,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Upload => "GStreamer upload",
+            Stage::Resample => "Resample",
+            Stage::ToWorkingSpace => "RGB→working space",
+            Stage::ForwardFft => "Forward FFT",
+            Stage::Filters => "Frequency filters",
+            Stage::InverseFft => "Inverse FFT",
+            Stage::FromWorkingSpace => "Working space→RGB",
+            Stage::Blit => "Final blit",
+        }
+    }
+
+    fn index(&self) -> usize {
+        Self::ALL.iter().position(|s| s == self).unwrap()
+    }
+}
+
+// Number of frames a query set may stay in flight before it is reused.
+const RING_SIZE: usize = 4;
+// Number of samples kept per stage for the statistics.
+const HISTORY: usize = 600;
+
+const GL_TIMESTAMP: u32 = 0x8E28;
+const GL_QUERY_RESULT: u32 = 0x8866;
+const GL_QUERY_RESULT_AVAILABLE: u32 = 0x8867;
+
+type GenQueries = unsafe extern "system" fn(n: i32, ids: *mut u32);
+type DeleteQueries = unsafe extern "system" fn(n: i32, ids: *const u32);
+type QueryCounter = unsafe extern "system" fn(id: u32, target: u32);
+type GetQueryObjectiv = unsafe extern "system" fn(id: u32, pname: u32, params: *mut i32);
+type GetQueryObjectui64v = unsafe extern "system" fn(id: u32, pname: u32, params: *mut u64);
+
+// glium does not expose timer queries for compute dispatches, so the few entry points
+// needed are loaded straight from the context.
+struct QueryFns {
+    gen_queries: GenQueries,
+    delete_queries: DeleteQueries,
+    query_counter: QueryCounter,
+    get_query_objectiv: GetQueryObjectiv,
+    get_query_objectui64v: GetQueryObjectui64v,
+}
+
+impl QueryFns {
+    fn load(display: &Display) -> Option<Self> {
+        let gl_window = display.gl_window();
+        let load = |name: &str| {
+            let ptr = gl_window.get_proc_address(name);
+            if ptr.is_null() {
+                None
+            } else {
+                Some(ptr)
+            }
+        };
+        unsafe {
+            Some(Self {
+                gen_queries: std::mem::transmute::<*const c_void, GenQueries>(load(
+                    "glGenQueries",
+                )?),
+                delete_queries: std::mem::transmute::<*const c_void, DeleteQueries>(load(
+                    "glDeleteQueries",
+                )?),
+                query_counter: std::mem::transmute::<*const c_void, QueryCounter>(load(
+                    "glQueryCounter",
+                )?),
+                get_query_objectiv: std::mem::transmute::<*const c_void, GetQueryObjectiv>(load(
+                    "glGetQueryObjectiv",
+                )?),
+                get_query_objectui64v: std::mem::transmute::<*const c_void, GetQueryObjectui64v>(
+                    load("glGetQueryObjectui64v")?,
+                ),
+            })
+        }
+    }
+}
+
+struct QuerySet {
+    // A begin and an end timestamp per stage
+    ids: Vec<u32>,
+    issued: Vec<bool>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StageSummary {
+    pub samples: usize,
+    pub mean_ms: f64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+}
+
+impl StageSummary {
+    pub fn from_samples(samples: &VecDeque<f64>) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+        let mut sorted: Vec<f64> = samples.iter().copied().collect();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let percentile = |p: f64| sorted[((p / 100.) * (sorted.len() - 1) as f64).round() as usize];
+        Self {
+            samples: sorted.len(),
+            mean_ms: sorted.iter().sum::<f64>() / sorted.len() as f64,
+            p50_ms: percentile(50.),
+            p95_ms: percentile(95.),
+            p99_ms: percentile(99.),
+        }
+    }
+}
+
+/// Times pipeline stages with GL timestamp queries.
+///
+/// Query sets are kept in a ring buffer and only read back once their results are available,
+/// so profiling never waits on the GPU. Results that are still pending when a set comes up for
+/// reuse are dropped.
+pub struct GpuProfiler {
+    fns: Option<QueryFns>,
+    sets: Vec<QuerySet>,
+    current: usize,
+    history: Vec<VecDeque<f64>>,
+    enabled: bool,
+    export_path: String,
+    export_status: Option<String>,
+}
+
+impl GpuProfiler {
+    pub fn new(display: &Display) -> Self {
+        let fns = QueryFns::load(display);
+        let sets = match fns.as_ref() {
+            Some(fns) => (0..RING_SIZE)
+                .map(|_| {
+                    let mut ids = vec![0; Stage::ALL.len() * 2];
+                    unsafe { (fns.gen_queries)(ids.len() as i32, ids.as_mut_ptr()) };
+                    QuerySet {
+                        ids,
+                        issued: vec![false; Stage::ALL.len()],
+                    }
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        Self {
+            fns,
+            sets,
+            current: 0,
+            history: vec![VecDeque::with_capacity(HISTORY); Stage::ALL.len()],
+            enabled: false,
+            export_path: "gpu_profile.csv".to_string(),
+            export_status: None,
+        }
+    }
+
+    /// A profiler that records nothing, for processing without a window.
+    pub fn disabled() -> Self {
+        Self {
+            fns: None,
+            sets: Vec::new(),
+            current: 0,
+            history: vec![VecDeque::new(); Stage::ALL.len()],
+            enabled: false,
+            export_path: String::new(),
+            export_status: None,
+        }
+    }
+
+    /// Moves to the next query set, collecting whatever results it holds from earlier frames.
+    pub fn begin_frame(&mut self) {
+        if self.fns.is_none() {
+            return;
+        }
+        self.current = (self.current + 1) % self.sets.len();
+        self.collect(self.current);
+    }
+
+    pub fn begin(&mut self, stage: Stage) {
+        self.timestamp(stage.index() * 2);
+    }
+
+    pub fn end(&mut self, stage: Stage) {
+        if self.timestamp(stage.index() * 2 + 1) {
+            self.sets[self.current].issued[stage.index()] = true;
+        }
+    }
+
+    fn timestamp(&mut self, query: usize) -> bool {
+        match self.fns.as_ref() {
+            Some(fns) if self.enabled => {
+                unsafe { (fns.query_counter)(self.sets[self.current].ids[query], GL_TIMESTAMP) };
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn collect(&mut self, set: usize) {
+        let fns = self.fns.as_ref().unwrap();
+        let set = &mut self.sets[set];
+        for (stage, issued) in set.issued.iter_mut().enumerate() {
+            if !*issued {
+                continue;
+            }
+            *issued = false;
+
+            let (begin, end) = (set.ids[stage * 2], set.ids[stage * 2 + 1]);
+            let mut available = 0;
+            unsafe { (fns.get_query_objectiv)(end, GL_QUERY_RESULT_AVAILABLE, &mut available) };
+            if available == 0 {
+                continue;
+            }
+
+            let (mut begin_ns, mut end_ns) = (0u64, 0u64);
+            unsafe {
+                (fns.get_query_objectui64v)(begin, GL_QUERY_RESULT, &mut begin_ns);
+                (fns.get_query_objectui64v)(end, GL_QUERY_RESULT, &mut end_ns);
+            }
+
+            let history = &mut self.history[stage];
+            if history.len() == HISTORY {
+                history.pop_front();
+            }
+            history.push_back(end_ns.saturating_sub(begin_ns) as f64 / 1e6);
+        }
+    }
+
+    pub fn summaries(&self) -> impl Iterator<Item = (Stage, StageSummary)> + '_ {
+        Stage::ALL
+            .iter()
+            .zip(self.history.iter())
+            .map(|(stage, samples)| (*stage, StageSummary::from_samples(samples)))
+    }
+
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        writeln!(writer, "stage,samples,mean_ms,p50_ms,p95_ms,p99_ms")?;
+        for (stage, s) in self.summaries() {
+            writeln!(
+                writer,
+                "{},{},{:.4},{:.4},{:.4},{:.4}",
+                stage.name(),
+                s.samples,
+                s.mean_ms,
+                s.p50_ms,
+                s.p95_ms,
+                s.p99_ms
+            )?;
+        }
+        Ok(())
+    }
+
+    fn export(&self, path: &Path) -> std::io::Result<()> {
+        self.write_csv(std::io::BufWriter::new(std::fs::File::create(path)?))
+    }
+
+    pub fn draw_ui(&mut self, ui: &mut egui::Ui) {
+        if self.fns.is_none() {
+            ui.label("Timer queries are not supported by this context");
+            return;
+        }
+        ui.checkbox(&mut self.enabled, "Enabled");
+        egui::Grid::new("gpu_profiler_grid")
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("Stage");
+                ui.label("mean");
+                ui.label("p50");
+                ui.label("p95");
+                ui.label("p99");
+                ui.end_row();
+                for (stage, s) in self.summaries() {
+                    ui.label(stage.name());
+                    ui.label(format!("{:.3} ms", s.mean_ms));
+                    ui.label(format!("{:.3} ms", s.p50_ms));
+                    ui.label(format!("{:.3} ms", s.p95_ms));
+                    ui.label(format!("{:.3} ms", s.p99_ms));
+                    ui.end_row();
+                }
+            });
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.export_path);
+            if ui.button("Export CSV").clicked() {
+                self.export_status = Some(match self.export(Path::new(&self.export_path)) {
+                    Ok(()) => format!("Saved to {}", self.export_path),
+                    Err(err) => format!("Export failed: {}", err),
+                });
+            }
+        });
+        if let Some(status) = self.export_status.as_ref() {
+            ui.label(status);
+        }
+        if ui.button("Reset").clicked() {
+            self.history.iter_mut().for_each(|h| h.clear());
+        }
+    }
+}
+
+impl Drop for GpuProfiler {
+    fn drop(&mut self) {
+        if let Some(fns) = self.fns.as_ref() {
+            for set in self.sets.iter() {
+                unsafe { (fns.delete_queries)(set.ids.len() as i32, set.ids.as_ptr()) };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let samples: VecDeque<f64> = (1..=100).map(|i| i as f64).collect();
+        let summary = StageSummary::from_samples(&samples);
+        assert_eq!(summary.samples, 100);
+        assert_eq!(summary.mean_ms, 50.5);
+        assert_eq!(summary.p50_ms, 51.);
+        assert_eq!(summary.p95_ms, 95.);
+        assert_eq!(summary.p99_ms, 99.);
+        assert_eq!(
+            StageSummary::from_samples(&VecDeque::new()),
+            StageSummary::default()
+        );
+    }
+}
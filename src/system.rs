@@ -1,4 +1,3 @@
-use cgmath::Vector2;
 use egui::plot::{Line, Plot, Value, Values};
 use glium::{
     backend::Facade, framebuffer::SimpleFrameBuffer, texture::SrgbTexture2d, Display, Frame,
@@ -6,31 +5,71 @@ use glium::{
 };
 
 use crate::{
-    color_space::ColorSpace,
+    calibration::{CalibrationWizard, DisplayCalibration},
+    color_space::WorkingSpace,
     csf::Csf,
-    fft::Fft,
+    fft::AllocationError,
+    frequency_filter::{BandPass, FrequencyFilter, GaussianLowPass, HighBoost},
     grating::Grating,
-    gstreamer::{CtxInfo, Gstreamer},
-    perception_adapter::PerceptionAdapter,
+    gstreamer::{
+        capture_devices, CaptureArea, CaptureDevice, CaptureFormat, CtxInfo, Gstreamer,
+        OutputStream, ScreenCapture, Source,
+    },
+    icc::IccProfile,
+    output::{fit, Framebuffer, OutputStage},
+    precision::{read_rgba_f32, ErrorReport, Precision},
+    processor::{AdaptParams, Processor},
+    profiler::{GpuProfiler, Stage},
+    transfer::TransferSettings,
+    viewing::Viewing,
 };
 
 pub struct System {
     grating: Grating,
     intermediate: Option<Texture2d>,
+    // Shares of the window width and height the content is shown across
+    content_fraction: (f32, f32),
+    // Width over height of a pixel of the source, which is not square in anamorphic video
+    pixel_aspect: f32,
     flowers: SrgbTexture2d,
-    screen_dims_mm: Vector2<f32>,
-    screen_distance_mm: f32,
-    target_distance_mm: f32,
+    viewing: Viewing,
     csf: Csf,
-    adapter: PerceptionAdapter,
     adapt: bool,
-    fft: Fft,
-    color_space: ColorSpace,
+    processor: Processor,
     gstreamer: Gstreamer,
+    profiler: GpuProfiler,
+    precision: Precision,
+    measure_precision: bool,
+    // Full and half precision, apart from `processor` so that its spectrum is kept
+    precision_processors: Option<[Processor; 2]>,
+    precision_report: Option<ErrorReport>,
+    transfer: TransferSettings,
+    output: OutputStage,
+    working_space: WorkingSpace,
+    ycbcr_follow_stream: bool,
+    icc_path: String,
+    load_icc: bool,
+    icc_status: Option<String>,
+    // SDR white, peak and black level from before the profile set them, restored on "Clear"
+    before_icc: Option<[f32; 3]>,
+    calibration: DisplayCalibration,
+    calibration_status: Option<String>,
+    wizard: CalibrationWizard,
+    wizard_active: bool,
+    apply_measured: bool,
+    source_input: String,
+    devices: Vec<CaptureDevice>,
+    device: usize,
+    capture_format: Option<CaptureFormat>,
+    source_status: Option<String>,
+    output_stream: Option<OutputStream>,
+    output_pipeline: String,
+    start_output_stream: bool,
+    output_stream_status: Option<String>,
 }
 
 impl System {
-    pub fn new(facade: &Display, initial_uri: &str) -> Self {
+    pub fn new(facade: &Display, initial_uri: &str, framebuffer: Framebuffer) -> Self {
         let image = image::load(
             std::io::Cursor::new(&include_bytes!("../flowers.png")[..]),
             image::ImageFormat::Png,
@@ -44,90 +83,549 @@ impl System {
 
         let grating = Grating::new(facade);
 
-        let ctx_info = CtxInfo::new(crate::gstreamer::SurfaceType::Display(facade));
-        let gstreamer = Gstreamer::new(facade, &ctx_info);
-        gstreamer.set_uri(initial_uri);
+        let ctx_info = CtxInfo::new(crate::gstreamer::SurfaceType::Display(facade))
+            .map_err(|err| eprintln!("Cannot share the GL context ({}), using system memory", err))
+            .ok();
+        let mut gstreamer = Gstreamer::new(facade, ctx_info.as_ref());
+        if !initial_uri.is_empty() {
+            gstreamer.set_uri(initial_uri);
+        }
+
+        let mut processor = Processor::new(facade);
+        processor
+            .filters
+            .push(Box::new(GaussianLowPass::new(facade)), false);
+        processor
+            .filters
+            .push(Box::new(HighBoost::new(facade)), false);
+        processor
+            .filters
+            .push(Box::new(BandPass::new(facade)), false);
+
+        let (calibration, calibration_status) = Self::load_calibration();
+        let transfer = TransferSettings {
+            sdr_white: calibration.peak_luminance,
+            ..Default::default()
+        };
         Self {
             grating,
             intermediate: None,
+            content_fraction: (1., 1.),
+            pixel_aspect: 1.,
             flowers,
-            screen_dims_mm: (600.0f32, 336.0f32).into(),
-            screen_distance_mm: 750.,
-            target_distance_mm: 900.,
-            csf: Csf {
-                a: 1.787,
-                ω: 7.22,
-                σ: 2.2,
-                k: 0.71,
-            },
-            adapter: PerceptionAdapter::new(facade),
+            viewing: Viewing::default(),
+            csf: Csf::default(),
             adapt: true,
-            fft: Fft::new(facade),
-            color_space: ColorSpace::new(facade),
+            processor,
             gstreamer,
+            profiler: GpuProfiler::new(facade),
+            precision: Precision::Full,
+            measure_precision: false,
+            precision_processors: None,
+            precision_report: None,
+            transfer,
+            output: OutputStage::new(facade, framebuffer),
+            working_space: WorkingSpace::default(),
+            ycbcr_follow_stream: true,
+            icc_path: String::new(),
+            load_icc: false,
+            icc_status: None,
+            before_icc: None,
+            apply_measured: calibration.gamma.is_some(),
+            calibration,
+            calibration_status,
+            wizard: CalibrationWizard::new(facade),
+            wizard_active: false,
+            source_input: initial_uri.to_string(),
+            devices: vec![],
+            device: 0,
+            capture_format: None,
+            source_status: None,
+            output_stream: None,
+            output_pipeline: String::new(),
+            start_output_stream: false,
+            output_stream_status: None,
         }
     }
 
-    pub fn draw(&mut self, facade: &dyn Facade, surface: &mut Frame) {
-        if self.intermediate.is_none() {
-            self.intermediate = Some(
-                Texture2d::empty_with_format(
-                    facade,
-                    glium::texture::UncompressedFloatFormat::U16U16U16U16,
-                    glium::texture::MipmapsOption::NoMipmap,
-                    1920,
-                    1080,
+    fn open_source(&mut self, source: Source) {
+        self.source_status = self
+            .gstreamer
+            .set_source(source)
+            .err()
+            .map(|err| err.to_string());
+    }
+
+    fn draw_source_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.source_input);
+            if ui.button("Open").clicked() {
+                match self.source_input.parse() {
+                    Ok(source) => self.open_source(source),
+                    Err(err) => self.source_status = Some(err),
+                }
+            }
+            if ui.button("Add to playlist").clicked() {
+                match self.source_input.parse() {
+                    Ok(source) => self.gstreamer.playlist.push(source),
+                    Err(err) => self.source_status = Some(err),
+                }
+            }
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Find cameras").clicked() {
+                self.devices = capture_devices();
+                self.device = 0;
+                self.capture_format = None;
+            }
+            if ui.button("Test pattern").clicked() {
+                self.open_source(Source::TestPattern("smpte".to_string()));
+            }
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Capture X11 screen").clicked() {
+                self.open_source(Source::Screen(ScreenCapture::X11 {
+                    display: None,
+                    area: CaptureArea::Screen,
+                }));
+            }
+            if ui.button("Share screen or window").clicked() {
+                self.open_source(Source::Screen(ScreenCapture::Portal));
+            }
+        });
+        if let Some(device) = self.devices.get(self.device).cloned() {
+            egui::ComboBox::from_label("Camera")
+                .selected_text(&device.name)
+                .show_ui(ui, |ui| {
+                    for (i, device) in self.devices.iter().enumerate() {
+                        if ui
+                            .selectable_label(i == self.device, &device.name)
+                            .clicked()
+                        {
+                            self.device = i;
+                            self.capture_format = None;
+                        }
+                    }
+                });
+            egui::ComboBox::from_label("Format")
+                .selected_text(
+                    self.capture_format
+                        .map_or("Default".to_string(), |f| f.to_string()),
                 )
-                .unwrap(),
-            );
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.capture_format, None, "Default");
+                    for format in device.formats.iter() {
+                        ui.selectable_value(
+                            &mut self.capture_format,
+                            Some(*format),
+                            format.to_string(),
+                        );
+                    }
+                });
+            if ui.button("Open camera").clicked() {
+                self.open_source(device.source(self.capture_format));
+            }
+        }
+        ui.label(format!("Playing: {}", self.gstreamer.source()));
+        if !self.gstreamer.uses_gl_memory() {
+            ui.label("Frames are uploaded from system memory");
         }
-        let intermediate = self.intermediate.as_ref().unwrap();
-        let mut int_surface = intermediate.as_surface();
-        //self.grating.draw(&mut int_surface);
-        // let flowers_fb = SimpleFrameBuffer::new(facade, &self.flowers).unwrap();
-        // flowers_fb.fill(&int_surface, glium::uniforms::MagnifySamplerFilter::Nearest);
-        let gstreamer_fb = self.gstreamer.draw(facade).as_surface();
-        gstreamer_fb.fill(&int_surface, glium::uniforms::MagnifySamplerFilter::Nearest);
+        if let Some(status) = self.source_status.as_ref() {
+            ui.label(status);
+        }
+    }
 
-        if self.adapt {
-            let pixels_per_vd =
-                self.pixels_per_vd(intermediate.width() as f32, self.screen_distance_mm);
-            let target_pixels_per_vd =
-                self.pixels_per_vd(intermediate.width() as f32, self.target_distance_mm);
-
-            let fft_tex = self.fft.process_texture(facade, intermediate);
-            self.color_space.rgb_to_ycbcr(fft_tex.orig());
-            fft_tex.fft(facade);
-            self.adapter.draw(
+    /// Starts pushing adapted frames into a pipeline before the next frame.
+    pub fn request_output_stream(&mut self, description: &str) {
+        self.output_pipeline = description.to_string();
+        self.start_output_stream = true;
+    }
+
+    fn open_output_stream(&mut self, facade: &dyn Facade) {
+        // The old pipeline may hold a device the new one opens
+        self.output_stream = None;
+        match OutputStream::new(facade, &self.output_pipeline) {
+            Ok(stream) => {
+                self.output_stream = Some(stream);
+                self.output_stream_status = None;
+            }
+            Err(err) => self.output_stream_status = Some(err.to_string()),
+        }
+    }
+
+    // The source as GStreamer describes it and the geometry it is processed with
+    fn draw_media_info_ui(&mut self, ui: &mut egui::Ui) {
+        match self.gstreamer.stream_video_info() {
+            Some(info) => {
+                let (fps, par) = (info.fps(), info.par());
+                ui.label(format!("Resolution: {}×{}", info.width(), info.height()));
+                ui.label(match *fps.numer() {
+                    0 => "Frame rate: variable".to_string(),
+                    numer => format!("Frame rate: {:.3} fps", numer as f64 / *fps.denom() as f64),
+                });
+                ui.label(format!(
+                    "Pixel aspect ratio: {}:{}",
+                    par.numer(),
+                    par.denom()
+                ));
+                ui.label(format!("Pixel format: {}", info.format().to_str()));
+                ui.label(format!("Colorimetry: {}", info.colorimetry()));
+            }
+            None => {
+                ui.label("No stream");
+            }
+        }
+        let discovering = self.gstreamer.discovering();
+        match self.gstreamer.media_info() {
+            Some(Ok(media)) => {
+                ui.label(format!(
+                    "Container: {}",
+                    media.container.as_deref().unwrap_or("unknown")
+                ));
+                ui.label(format!(
+                    "Video codec: {}",
+                    media.video_codec.as_deref().unwrap_or("unknown")
+                ));
+                ui.label(format!(
+                    "Audio codec: {}",
+                    media.audio_codec.as_deref().unwrap_or("unknown")
+                ));
+                if let Some(duration) = media.duration {
+                    ui.label(format!("Duration: {}", duration));
+                }
+                if let Some(bitrate) = media.video_bitrate {
+                    ui.label(format!("Video bitrate: {} kbit/s", bitrate / 1000));
+                }
+                if !media.tags.is_empty() {
+                    ui.collapsing("Tags", |ui| {
+                        for (name, value) in media.tags.iter() {
+                            ui.label(format!("{}: {}", name, value));
+                        }
+                    });
+                }
+            }
+            Some(Err(err)) => {
+                ui.label(format!("Discoverer: {}", err));
+            }
+            None if discovering => {
+                ui.label("Discovering…");
+            }
+            None => (),
+        }
+
+        ui.separator();
+        if let Some(intermediate) = self.intermediate.as_ref() {
+            let (width, height) = intermediate.dimensions();
+            ui.label(format!("Processing size: {}×{}", width, height));
+            if let Some((fft_width, fft_height)) = self.processor.padded_dimensions() {
+                ui.label(format!("FFT size: {}×{}", fft_width, fft_height));
+            }
+            let distance = self.viewing.screen_distance_mm;
+            ui.label(format!(
+                "Pixels per visual degree: {:.1} horizontally, {:.1} vertically",
+                self.pixels_per_vd(width as f32, distance),
+                self.viewing.content_pixels_per_vd_vertical(
+                    height as f32,
+                    self.content_fraction.1,
+                    distance
+                ),
+            ));
+        }
+    }
+
+    fn draw_output_stream_ui(&mut self, ui: &mut egui::Ui) {
+        ui.label("Pipeline after appsrc ! videoconvert:");
+        ui.text_edit_singleline(&mut self.output_pipeline);
+        ui.horizontal(|ui| {
+            if ui.button("Start").clicked() {
+                self.start_output_stream = true;
+            }
+            if self.output_stream.is_some() && ui.button("Stop").clicked() {
+                self.output_stream = None;
+            }
+        });
+        if let Some(stream) = self.output_stream.as_ref() {
+            ui.label(format!("Streaming to {}", stream.description()));
+            if stream.dropped > 0 {
+                ui.label(format!("{} frames dropped", stream.dropped));
+            }
+        }
+        if let Some(status) = self.output_stream_status.as_ref() {
+            ui.label(status);
+        }
+    }
+
+    // A missing file leaves the ideal display
+    fn load_calibration() -> (DisplayCalibration, Option<String>) {
+        let path = match DisplayCalibration::default_path() {
+            Some(path) => path,
+            None => return (DisplayCalibration::default(), None),
+        };
+        match DisplayCalibration::load(&path) {
+            Ok(calibration) => (calibration, Some(format!("Loaded {}", path.display()))),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                (DisplayCalibration::default(), None)
+            }
+            Err(err) => (
+                DisplayCalibration::default(),
+                Some(format!("Could not load {}: {}", path.display(), err)),
+            ),
+        }
+    }
+
+    fn save_calibration(&mut self) {
+        self.calibration_status = Some(match DisplayCalibration::default_path() {
+            Some(path) => match self.calibration.save(&path) {
+                Ok(()) => format!("Saved {}", path.display()),
+                Err(err) => format!("Could not save {}: {}", path.display(), err),
+            },
+            None => "No configuration directory".to_string(),
+        });
+    }
+
+    /// Loads a display profile before the next frame.
+    pub fn request_icc(&mut self, path: &str) {
+        self.icc_path = path.to_string();
+        self.load_icc = true;
+        // A profile chosen explicitly wins over the saved measured curve.
+        self.apply_measured = false;
+    }
+
+    // The profile's white luminance becomes the luminance of SDR white
+    fn load_icc(&mut self, facade: &dyn Facade) {
+        self.icc_status = Some(match IccProfile::load(self.icc_path.as_ref()) {
+            Ok(profile) => {
+                if let Some(luminance) = profile.white_luminance() {
+                    self.before_icc.get_or_insert([
+                        self.transfer.sdr_white,
+                        self.calibration.peak_luminance,
+                        self.calibration.black_level,
+                    ]);
+                    self.transfer.sdr_white = luminance;
+                    self.calibration.peak_luminance = luminance;
+                    self.calibration.black_level = luminance * profile.black_level();
+                }
+                self.output.set_profile(facade, profile);
+                format!("Loaded {}", self.icc_path)
+            }
+            Err(err) => err.to_string(),
+        });
+    }
+
+    pub fn draw(&mut self, facade: &dyn Facade, surface: &mut Frame) {
+        if self.load_icc {
+            self.load_icc = false;
+            self.load_icc(facade);
+        }
+        if self.apply_measured {
+            self.apply_measured = false;
+            if let Some(profile) = self.calibration.measured_profile() {
+                self.output.set_profile(facade, profile);
+            }
+        }
+        if self.start_output_stream {
+            self.start_output_stream = false;
+            self.open_output_stream(facade);
+        }
+        if self.wizard_active {
+            self.wizard.draw(surface);
+            return;
+        }
+        self.profiler.begin_frame();
+        let frame = match self
+            .gstreamer
+            .draw(facade, &mut self.transfer, &mut self.profiler)
+        {
+            Some(frame) => frame,
+            // A plain background until the source delivers, the side panel tells why
+            None => {
+                surface.clear_color(0.1, 0.1, 0.1, 1.);
+                return;
+            }
+        };
+        // Processed at the native resolution of the source, in half floats like the frame
+        let dimensions = frame.dimensions();
+        let intermediate = match self.intermediate.take() {
+            Some(intermediate) if intermediate.dimensions() == dimensions => intermediate,
+            _ => Texture2d::empty_with_format(
                 facade,
-                fft_tex.real(),
-                fft_tex.imag(),
-                pixels_per_vd,
-                &self.csf,
-                target_pixels_per_vd,
-            );
-            fft_tex.ifft(facade);
-            self.color_space.ycbcr_to_rgb(fft_tex.orig());
+                glium::texture::UncompressedFloatFormat::F16F16F16F16,
+                glium::texture::MipmapsOption::NoMipmap,
+                dimensions.0,
+                dimensions.1,
+            )
+            .unwrap(),
+        };
+        {
+            let int_surface = intermediate.as_surface();
+            //self.grating.draw(&mut int_surface, &self.transfer);
+            // let flowers_fb = SimpleFrameBuffer::new(facade, &self.flowers).unwrap();
+            // flowers_fb.fill(&int_surface, glium::uniforms::MagnifySamplerFilter::Nearest);
+            let gstreamer_fb = frame.as_surface();
+            self.profiler.begin(Stage::Resample);
+            gstreamer_fb.fill(&int_surface, glium::uniforms::MagnifySamplerFilter::Nearest);
+        }
+        self.pixel_aspect = self
+            .gstreamer
+            .stream_video_info()
+            .map(|info| info.par())
+            .filter(|par| *par.numer() > 0 && *par.denom() > 0)
+            .map_or(1., |par| *par.numer() as f32 / *par.denom() as f32);
+        // Letterboxed or pillarboxed into the window as it is now, so resizes apply at once
+        let window = surface.get_dimensions();
+        let shown = (
+            (dimensions.0 as f32 * self.pixel_aspect).round() as u32,
+            dimensions.1,
+        );
+        let viewport = fit(shown, window);
+        self.content_fraction = (
+            viewport.width as f32 / window.0.max(1) as f32,
+            viewport.height as f32 / window.1.max(1) as f32,
+        );
+        if let WorkingSpace::Ycbcr(ycbcr) = &mut self.working_space {
+            if self.ycbcr_follow_stream {
+                if let Some(stream) = self.gstreamer.stream_ycbcr() {
+                    *ycbcr = stream;
+                }
+            }
+        }
 
-            fft_tex
-                .orig()
-                .as_surface()
-                .fill(surface, glium::uniforms::MagnifySamplerFilter::Nearest);
+        if self.adapt {
+            if let Err(err) = self.process(facade, &intermediate, self.precision) {
+                eprintln!("Adaptation turned off: {}", err);
+                self.adapt = false;
+            }
+        }
+        if self.adapt {
+            self.profiler.begin(Stage::Blit);
+            surface.clear_color(0., 0., 0., 1.);
+            self.output.draw_in(
+                surface,
+                self.processor.output().unwrap(),
+                &self.transfer,
+                viewport,
+            );
+            self.profiler.end(Stage::Blit);
         } else {
-            int_surface.fill(surface, glium::uniforms::MagnifySamplerFilter::Nearest);
+            self.profiler.end(Stage::Resample);
+
+            self.profiler.begin(Stage::Blit);
+            surface.clear_color(0., 0., 0., 1.);
+            self.output
+                .draw_in(surface, &intermediate, &self.transfer, viewport);
+            self.profiler.end(Stage::Blit);
+        }
+        if let Some(stream) = self.output_stream.as_mut() {
+            let frame = if self.adapt {
+                self.processor.output().unwrap()
+            } else {
+                &intermediate
+            };
+            let framerate = self.gstreamer.stream_video_info().map(|info| info.fps());
+            stream.push(facade, frame, &self.transfer, framerate);
         }
+        // After the frame so that the profiler does not count it
+        if self.measure_precision {
+            self.measure_precision = false;
+            match self.measure_precision_error(facade, &intermediate) {
+                Ok(report) => self.precision_report = Some(report),
+                Err(err) => eprintln!("Could not measure the 16-bit error: {}", err),
+            }
+        }
+        self.intermediate = Some(intermediate);
     }
 
-    fn pixels_per_vd(&self, pixels: f32, distance_mm: f32) -> f32 {
-        pixels / self.total_visual_angle(distance_mm).to_degrees()
+    fn adapt_params(&self, input: &Texture2d, precision: Precision) -> AdaptParams {
+        AdaptParams {
+            csf: self.csf.clone(),
+            pixels_per_visual_degree: self
+                .pixels_per_vd(input.width() as f32, self.viewing.screen_distance_mm),
+            target_pixels_per_visual_degree: self
+                .pixels_per_vd(input.width() as f32, self.viewing.target_distance_mm),
+            precision,
+            working_space: self.working_space,
+            transfer: self.transfer,
+            calibration: self.calibration,
+        }
     }
 
-    fn total_visual_angle(&self, distance: f32) -> f32 {
-        2. * (self.screen_dims_mm.x / distance).atan()
+    fn process(
+        &mut self,
+        facade: &dyn Facade,
+        input: &Texture2d,
+        precision: Precision,
+    ) -> Result<(), AllocationError> {
+        let params = self.adapt_params(input, precision);
+        self.processor
+            .process(facade, input, &params, &mut self.profiler)?;
+        Ok(())
+    }
+
+    // Compares the adapted input at half precision against full precision. Stalls on readback.
+    fn measure_precision_error(
+        &mut self,
+        facade: &dyn Facade,
+        input: &Texture2d,
+    ) -> Result<ErrorReport, AllocationError> {
+        let params = self.adapt_params(input, Precision::Full);
+        let mut profiler = GpuProfiler::disabled();
+        let [full, half] = self
+            .precision_processors
+            .get_or_insert_with(|| [Processor::new(facade), Processor::new(facade)]);
+        // The filters of the viewer are lent to each in turn
+        std::mem::swap(&mut full.filters, &mut self.processor.filters);
+        let full_result = full
+            .process(facade, input, &params, &mut profiler)
+            .map(|_| ());
+        std::mem::swap(&mut full.filters, &mut half.filters);
+        let params = AdaptParams {
+            precision: Precision::Half,
+            ..params
+        };
+        let half_result = half
+            .process(facade, input, &params, &mut profiler)
+            .map(|_| ());
+        std::mem::swap(&mut half.filters, &mut self.processor.filters);
+        full_result.and(half_result)?;
+        Ok(ErrorReport::compare(
+            &read_rgba_f32(full.output().unwrap()),
+            &read_rgba_f32(half.output().unwrap()),
+        ))
+    }
+
+    // Of content `pixels` of the source wide as it is shown in the window, counted in square
+    // pixels so that both directions agree
+    fn pixels_per_vd(&self, pixels: f32, distance_mm: f32) -> f32 {
+        self.viewing.content_pixels_per_vd(
+            pixels * self.pixel_aspect,
+            self.content_fraction.0,
+            distance_mm,
+        )
     }
 
     pub fn draw_ui(&mut self, egui_ctx: &egui::Context) {
         egui::SidePanel::left("my_side_panel").show(egui_ctx, |ui| {
+            if self.wizard_active {
+                ui.heading("Display calibration");
+                if self.wizard.draw_ui(ui) {
+                    self.wizard.apply(&mut self.calibration);
+                    self.save_calibration();
+                    self.apply_measured = true;
+                    self.wizard_active = false;
+                }
+                if ui.button("Cancel").clicked() {
+                    self.wizard_active = false;
+                }
+                return;
+            }
+            ui.collapsing("Source", |ui| self.draw_source_ui(ui));
+            ui.collapsing("Playback", |ui| {
+                self.gstreamer.draw_status_ui(ui);
+                self.gstreamer.draw_transport_ui(ui);
+                self.gstreamer.draw_audio_ui(ui);
+            });
+            ui.collapsing("Media information", |ui| self.draw_media_info_ui(ui));
+            ui.collapsing("Output stream", |ui| self.draw_output_stream_ui(ui));
             ui.horizontal(|ui| {
                 ui.label("# of cycles");
                 ui.add(
@@ -140,7 +638,7 @@ impl System {
             ui.horizontal(|ui| {
                 ui.label("width:");
                 ui.add(
-                    egui::DragValue::new(&mut self.screen_dims_mm.x)
+                    egui::DragValue::new(&mut self.viewing.screen_dims_mm.x)
                         .speed(0.1)
                         .clamp_range(0.0..=100000.),
                 );
@@ -149,7 +647,7 @@ impl System {
             ui.horizontal(|ui| {
                 ui.label("height:");
                 ui.add(
-                    egui::DragValue::new(&mut self.screen_dims_mm.y)
+                    egui::DragValue::new(&mut self.viewing.screen_dims_mm.y)
                         .speed(0.1)
                         .clamp_range(0.0..=100000.),
                 );
@@ -158,7 +656,7 @@ impl System {
             ui.horizontal(|ui| {
                 ui.label("Distance to screen:");
                 ui.add(
-                    egui::DragValue::new(&mut self.screen_distance_mm)
+                    egui::DragValue::new(&mut self.viewing.screen_distance_mm)
                         .speed(0.1)
                         .clamp_range(0.0..=100000.),
                 );
@@ -166,17 +664,24 @@ impl System {
             });
             ui.label(format!(
                 "Total visual angle: {}°",
-                self.total_visual_angle(self.screen_distance_mm)
+                self.viewing
+                    .total_visual_angle(self.viewing.screen_distance_mm)
                     .to_degrees()
             ));
             if let Some(intermediate) = self.intermediate.as_ref() {
                 ui.label(format!(
                     "Pixels per visual degree: {}",
-                    self.pixels_per_vd(intermediate.width() as f32, self.screen_distance_mm),
+                    self.pixels_per_vd(
+                        intermediate.width() as f32,
+                        self.viewing.screen_distance_mm
+                    ),
                 ));
                 ui.label(format!(
                     "Target pixels per visual degree: {}",
-                    self.pixels_per_vd(intermediate.width() as f32, self.target_distance_mm),
+                    self.pixels_per_vd(
+                        intermediate.width() as f32,
+                        self.viewing.target_distance_mm
+                    ),
                 ));
             }
 
@@ -197,17 +702,107 @@ impl System {
                 .show(ui, |plot_ui| {
                     plot_ui.line(self.plot_csf());
                 });
+            ui.heading("Transfer functions");
+            self.transfer.draw_ui(ui);
+            ui.label(format!("Framebuffer: {}", self.output.framebuffer().name()));
+            if self.output.framebuffer() == Framebuffer::Float {
+                ui.label("Float framebuffer: no tone mapping");
+            } else {
+                ui.checkbox(&mut self.output.dither, "Dither output");
+            }
+            ui.collapsing("Display calibration", |ui| {
+                let peak = self.calibration.peak_luminance;
+                self.calibration.draw_ui(ui);
+                // Pixel values are absolute luminance, with white at the calibrated peak
+                if self.calibration.peak_luminance != peak {
+                    self.transfer.sdr_white = self.calibration.peak_luminance;
+                }
+                if let Some([r, g, b]) = self.calibration.gamma {
+                    ui.label(format!(
+                        "Measured gamma: {:.2} {:.2} {:.2}, black offset {:.3}",
+                        r, g, b, self.calibration.black_offset
+                    ));
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("Measure curve").clicked() {
+                        self.wizard.restart();
+                        self.wizard_active = true;
+                    }
+                    if self.calibration.gamma.is_some() && ui.button("Use measured curve").clicked()
+                    {
+                        self.apply_measured = true;
+                    }
+                    if ui.button("Save").clicked() {
+                        self.save_calibration();
+                    }
+                });
+                if let Some(status) = self.calibration_status.as_ref() {
+                    ui.label(status);
+                }
+            });
+            ui.collapsing("Display profile", |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("ICC file:");
+                    ui.text_edit_singleline(&mut self.icc_path);
+                });
+                ui.horizontal(|ui| {
+                    if ui.button("Load").clicked() {
+                        self.load_icc = true;
+                    }
+                    if ui.button("Clear").clicked() {
+                        self.output.clear_profile();
+                        self.icc_status = None;
+                        if let Some([sdr_white, peak, black]) = self.before_icc.take() {
+                            self.transfer.sdr_white = sdr_white;
+                            self.calibration.peak_luminance = peak;
+                            self.calibration.black_level = black;
+                        }
+                        // Back to the measured curve, if there is one
+                        self.apply_measured = true;
+                    }
+                });
+                if let Some(status) = self.icc_status.as_ref() {
+                    ui.label(status);
+                }
+                if let Some(profile) = self.output.profile() {
+                    ui.label("The profile replaces the display transfer function and primaries");
+                    if let Some(luminance) = profile.white_luminance() {
+                        ui.label(format!("White luminance: {} cd/m²", luminance));
+                    }
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Working space:");
+                self.working_space.draw_ui(ui);
+            });
+            if let WorkingSpace::Ycbcr(_) = self.working_space {
+                ui.checkbox(&mut self.ycbcr_follow_stream, "Use the stream's matrix");
+            }
             ui.heading("Adjustment Algorithm");
             ui.checkbox(&mut self.adapt, "Activate");
             ui.horizontal(|ui| {
                 ui.label("Target distance to screen:");
                 ui.add(
-                    egui::DragValue::new(&mut self.target_distance_mm)
+                    egui::DragValue::new(&mut self.viewing.target_distance_mm)
                         .speed(10)
                         .clamp_range(0.0..=100000.),
                 );
                 ui.label("mm");
             });
+            ui.horizontal(|ui| {
+                ui.label("Precision:");
+                ui.radio_value(&mut self.precision, Precision::Full, "32-bit");
+                ui.radio_value(&mut self.precision, Precision::Half, "16-bit");
+            });
+            if ui.button("Measure 16-bit error").clicked() {
+                self.measure_precision = true;
+            }
+            if let Some(report) = self.precision_report.as_ref() {
+                report.draw_ui(ui);
+            }
+            ui.heading("Frequency filters");
+            self.processor.filters.draw_ui(ui);
+            ui.collapsing("GPU profiling", |ui| self.profiler.draw_ui(ui));
         });
     }
 
@@ -0,0 +1,451 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use glium::{
+    backend::Facade,
+    texture::{MipmapsOption, RawImage2d, UncompressedFloatFormat},
+    Rect, Texture2d,
+};
+
+use crate::{
+    calibration::DisplayCalibration,
+    color_space::WorkingSpace,
+    csf::Csf,
+    fft::AllocationError,
+    icc::IccProfile,
+    precision::{read_rgba_f32, Precision},
+    processor::{AdaptParams, Processor},
+    profiler::GpuProfiler,
+    transfer::TransferSettings,
+    viewing::Viewing,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TileConfig {
+    pub tile_size: u32,
+    pub overlap: u32,
+}
+
+impl Default for TileConfig {
+    fn default() -> Self {
+        Self {
+            tile_size: 1024,
+            overlap: 128,
+        }
+    }
+}
+
+impl TileConfig {
+    // Origins of the tiles along one axis. The first tile starts one overlap before the image so
+    // that every pixel lies where the window tapers sum to one.
+    fn origins(&self, size: u32) -> Vec<i64> {
+        let step = (self.tile_size - self.overlap) as i64;
+        let mut origins = vec![];
+        let mut origin = -(self.overlap as i64);
+        loop {
+            origins.push(origin);
+            if origin + step >= size as i64 {
+                break;
+            }
+            origin += step;
+        }
+        origins
+    }
+
+    // Raised-cosine taper over the overlap at both ends; neighbouring tapers sum to one.
+    fn window(&self, i: u32) -> f32 {
+        let taper = |k: u32| {
+            (std::f32::consts::FRAC_PI_2 * (k as f32 + 0.5) / self.overlap as f32)
+                .sin()
+                .powi(2)
+        };
+        if i < self.overlap {
+            taper(i)
+        } else if i >= self.tile_size - self.overlap {
+            taper(self.tile_size - 1 - i)
+        } else {
+            1.
+        }
+    }
+}
+
+/// A still image processed tile by tile with windowed overlap-add.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TileJob {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub viewing: Viewing,
+    pub csf: Csf,
+    pub precision: Precision,
+    pub transfer: TransferSettings,
+    pub working_space: WorkingSpace,
+    pub calibration: DisplayCalibration,
+    pub config: TileConfig,
+}
+
+#[derive(Debug)]
+pub enum TileError {
+    Io(io::Error),
+    Decoding(png::DecodingError),
+    Encoding(png::EncodingError),
+    Unsupported(String),
+}
+
+impl fmt::Display for TileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TileError::Io(err) => write!(f, "I/O error: {}", err),
+            TileError::Decoding(err) => write!(f, "Could not decode input: {}", err),
+            TileError::Encoding(err) => write!(f, "Could not encode output: {}", err),
+            TileError::Unsupported(what) => write!(f, "Unsupported: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for TileError {}
+
+impl From<io::Error> for TileError {
+    fn from(err: io::Error) -> Self {
+        TileError::Io(err)
+    }
+}
+
+impl From<png::DecodingError> for TileError {
+    fn from(err: png::DecodingError) -> Self {
+        TileError::Decoding(err)
+    }
+}
+
+impl From<png::EncodingError> for TileError {
+    fn from(err: png::EncodingError) -> Self {
+        TileError::Encoding(err)
+    }
+}
+
+/// Red, green, blue and alpha.
+pub type Pixel = [f32; 4];
+
+// An RGBA float image kept in an anonymous temporary file, so memory use does not grow with it.
+struct ScratchImage {
+    file: File,
+    width: u32,
+    height: u32,
+}
+
+impl ScratchImage {
+    fn create(width: u32, height: u32) -> io::Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "csf_contrast-{}-{}.scratch",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        std::fs::remove_file(&path)?;
+        // Sparse, reads back as zeroes
+        file.set_len(width as u64 * height as u64 * 16)?;
+        Ok(Self {
+            file,
+            width,
+            height,
+        })
+    }
+
+    fn offset(&self, x: u32, y: u32) -> u64 {
+        (y as u64 * self.width as u64 + x as u64) * 16
+    }
+
+    fn read_span(&self, x: u32, y: u32, len: u32) -> io::Result<Vec<Pixel>> {
+        let mut bytes = vec![0u8; len as usize * 16];
+        self.file.read_exact_at(&mut bytes, self.offset(x, y))?;
+        Ok(bytes
+            .chunks_exact(16)
+            .map(|p| {
+                let c = |i: usize| f32::from_ne_bytes(p[i * 4..i * 4 + 4].try_into().unwrap());
+                [c(0), c(1), c(2), c(3)]
+            })
+            .collect())
+    }
+
+    fn write_span(&self, x: u32, y: u32, pixels: &[Pixel]) -> io::Result<()> {
+        let bytes: Vec<u8> = pixels
+            .iter()
+            .flat_map(|p| p.iter().flat_map(|c| c.to_ne_bytes()))
+            .collect();
+        self.file.write_all_at(&bytes, self.offset(x, y))
+    }
+}
+
+fn decode_row(
+    data: &[u8],
+    color_type: png::ColorType,
+    bit_depth: png::BitDepth,
+    transfer: &TransferSettings,
+) -> Vec<Pixel> {
+    let samples: Vec<f32> = match bit_depth {
+        png::BitDepth::Sixteen => data
+            .chunks_exact(2)
+            .map(|s| u16::from_be_bytes([s[0], s[1]]) as f32 / 65535.)
+            .collect(),
+        _ => data.iter().map(|s| *s as f32 / 255.).collect(),
+    };
+    // Same conversion as the GStreamer upload
+    let lin = |rgb: [f32; 3], a: f32| {
+        let [r, g, b] = transfer.to_working(rgb);
+        [r, g, b, a]
+    };
+    match color_type {
+        png::ColorType::Grayscale => samples.iter().map(|g| lin([*g; 3], 1.)).collect(),
+        png::ColorType::GrayscaleAlpha => samples
+            .chunks_exact(2)
+            .map(|s| lin([s[0]; 3], s[1]))
+            .collect(),
+        png::ColorType::Rgb => samples
+            .chunks_exact(3)
+            .map(|s| lin([s[0], s[1], s[2]], 1.))
+            .collect(),
+        _ => samples
+            .chunks_exact(4)
+            .map(|s| lin([s[0], s[1], s[2]], s[3]))
+            .collect(),
+    }
+}
+
+fn load_png(path: &Path, transfer: &TransferSettings) -> Result<ScratchImage, TileError> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info()?;
+    if reader.info().interlaced {
+        return Err(TileError::Unsupported(
+            "interlaced PNGs cannot be streamed".to_string(),
+        ));
+    }
+    let (width, height) = (reader.info().width, reader.info().height);
+    let (color_type, bit_depth) = reader.output_color_type();
+
+    let image = ScratchImage::create(width, height)?;
+    let mut y = 0;
+    while let Some(row) = reader.next_row()? {
+        image.write_span(
+            0,
+            y,
+            &decode_row(row.data(), color_type, bit_depth, transfer),
+        )?;
+        y += 1;
+    }
+    Ok(image)
+}
+
+fn write_png(
+    path: &Path,
+    sums: &ScratchImage,
+    input: &ScratchImage,
+    transfer: &TransferSettings,
+    profile: Option<&IccProfile>,
+) -> Result<(), TileError> {
+    let mut encoder =
+        png::Encoder::new(BufWriter::new(File::create(path)?), sums.width, sums.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Sixteen);
+    let mut writer = encoder.write_header()?;
+    let mut stream = writer.stream_writer()?;
+
+    let encode = |v: f32| (v.clamp(0., 1.) * 65535.).round() as u16;
+    for y in 0..sums.height {
+        let alpha = input.read_span(0, y, input.width)?;
+        let row: Vec<u8> = sums
+            .read_span(0, y, sums.width)?
+            .iter()
+            .zip(alpha.iter())
+            .flat_map(|(sum, a)| {
+                let w = sum[3].max(f32::EPSILON);
+                let [r, g, b] = transfer.to_device([sum[0] / w, sum[1] / w, sum[2] / w], profile);
+                [encode(r), encode(g), encode(b), encode(a[3])]
+            })
+            .flat_map(|c| c.to_be_bytes())
+            .collect();
+        stream.write_all(&row)?;
+    }
+    stream.finish()?;
+    Ok(())
+}
+
+// Reads a tile, replicating the edge pixels where it extends past the image.
+fn read_tile(image: &ScratchImage, x0: i64, y0: i64, size: u32) -> io::Result<Vec<f32>> {
+    let clamp = |v: i64, max: u32| v.clamp(0, max as i64 - 1) as u32;
+    let (left, right) = (
+        clamp(x0, image.width),
+        clamp(x0 + size as i64 - 1, image.width),
+    );
+    let mut data = Vec::with_capacity(size as usize * size as usize * 4);
+    for ty in 0..size as i64 {
+        let span = image.read_span(left, clamp(y0 + ty, image.height), right - left + 1)?;
+        for tx in 0..size as i64 {
+            let x = clamp(x0 + tx, image.width);
+            data.extend_from_slice(&span[(x - left) as usize]);
+        }
+    }
+    Ok(data)
+}
+
+/// Processes a PNG of any size with tiles of bounded size, streaming from and to disk.
+pub fn run(facade: &dyn Facade, processor: &mut Processor, job: &TileJob) -> Result<(), TileError> {
+    let input = load_png(&job.input, &job.transfer)?;
+    // The whole image fills the screen, so the tiles share its pixels per degree
+    let params = AdaptParams {
+        csf: job.csf.clone(),
+        pixels_per_visual_degree: job
+            .viewing
+            .pixels_per_vd(input.width as f32, job.viewing.screen_distance_mm),
+        target_pixels_per_visual_degree: job
+            .viewing
+            .pixels_per_vd(input.width as f32, job.viewing.target_distance_mm),
+        precision: job.precision,
+        working_space: job.working_space,
+        transfer: job.transfer,
+        calibration: job.calibration,
+    };
+    let sums = adapt_tiles(facade, processor, &input, &params, job.config)?;
+    let profile = job.calibration.measured_profile();
+    write_png(&job.output, &sums, &input, &job.transfer, profile.as_ref())
+}
+
+/// Adapts an image held in memory tile by tile, for images larger than the GPU can transform
+/// whole. Rows are top first in both the working signal and the result; alpha is kept.
+pub fn adapt_pixels(
+    facade: &dyn Facade,
+    processor: &mut Processor,
+    (width, height): (u32, u32),
+    pixels: &[Pixel],
+    params: &AdaptParams,
+    config: TileConfig,
+) -> Result<Vec<Pixel>, TileError> {
+    let input = ScratchImage::create(width, height)?;
+    for (y, row) in pixels.chunks_exact(width as usize).enumerate() {
+        input.write_span(0, y as u32, row)?;
+    }
+    let sums = adapt_tiles(facade, processor, &input, params, config)?;
+    let mut result = Vec::with_capacity(pixels.len());
+    for (y, row) in pixels.chunks_exact(width as usize).enumerate() {
+        let span = sums.read_span(0, y as u32, width)?;
+        result.extend(span.iter().zip(row).map(|(sum, p)| {
+            let w = sum[3].max(f32::EPSILON);
+            [sum[0] / w, sum[1] / w, sum[2] / w, p[3]]
+        }));
+    }
+    Ok(result)
+}
+
+// Weighted sums of the adapted tiles that overlap each pixel, with the sum of the weights in
+// alpha
+fn adapt_tiles(
+    facade: &dyn Facade,
+    processor: &mut Processor,
+    input: &ScratchImage,
+    params: &AdaptParams,
+    config: TileConfig,
+) -> Result<ScratchImage, TileError> {
+    if config.tile_size <= 2 * config.overlap {
+        return Err(TileError::Unsupported(
+            "the tile size must be more than twice the overlap".to_string(),
+        ));
+    }
+    let sums = ScratchImage::create(input.width, input.height)?;
+
+    let size = config.tile_size;
+    let too_large = |err| TileError::Unsupported(format!("{}×{} tiles: {}", size, size, err));
+    let tile = Texture2d::empty_with_format(
+        facade,
+        UncompressedFloatFormat::F32F32F32F32,
+        MipmapsOption::NoMipmap,
+        size,
+        size,
+    )
+    .map_err(|err| too_large(AllocationError::from(err)))?;
+    let mut profiler = GpuProfiler::disabled();
+
+    let (xs, ys) = (config.origins(input.width), config.origins(input.height));
+    let total = xs.len() * ys.len();
+    for (i, (y0, x0)) in ys
+        .iter()
+        .flat_map(|y| xs.iter().map(move |x| (*y, *x)))
+        .enumerate()
+    {
+        eprintln!("Tile {}/{}", i + 1, total);
+        tile.write(
+            Rect {
+                left: 0,
+                bottom: 0,
+                width: size,
+                height: size,
+            },
+            RawImage2d::from_raw_rgba(read_tile(input, x0, y0, size)?, (size, size)),
+        );
+        let adapted = processor
+            .process(facade, &tile, params, &mut profiler)
+            .map_err(too_large)?;
+        let result = read_rgba_f32(adapted.orig());
+
+        // Rows were uploaded top first, so they come back in the same order
+        let left = x0.max(0) as u32;
+        let right = ((x0 + size as i64).min(input.width as i64)) as u32;
+        for (ty, row) in result.iter().enumerate() {
+            let y = y0 + ty as i64;
+            if y < 0 || y >= input.height as i64 {
+                continue;
+            }
+            let wy = config.window(ty as u32);
+            let mut span = sums.read_span(left, y as u32, right - left)?;
+            for (x, sum) in (left..right).zip(span.iter_mut()) {
+                let w = wy * config.window((x as i64 - x0) as u32);
+                let p = row[(x as i64 - x0) as usize];
+                sum[0] += w * p.0;
+                sum[1] += w * p.1;
+                sum[2] += w * p.2;
+                sum[3] += w;
+            }
+            sums.write_span(left, y as u32, &span)?;
+        }
+    }
+    Ok(sums)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_origins() {
+        let config = TileConfig {
+            tile_size: 8,
+            overlap: 2,
+        };
+        assert_eq!(config.origins(4), vec![-2]);
+        assert_eq!(config.origins(5), vec![-2, 4]);
+        assert_eq!(config.origins(10), vec![-2, 4]);
+        assert_eq!(config.origins(11), vec![-2, 4, 10]);
+    }
+
+    #[test]
+    fn test_window_partition_of_unity() {
+        let config = TileConfig {
+            tile_size: 16,
+            overlap: 4,
+        };
+        let step = config.tile_size - config.overlap;
+        for i in step..config.tile_size {
+            let sum = config.window(i) + config.window(i - step);
+            assert!((sum - 1.).abs() < 1e-6);
+        }
+    }
+}
@@ -0,0 +1,587 @@
+use std::{fmt, path::PathBuf, str::FromStr};
+
+use glium::{
+    backend::Facade,
+    texture::{MipmapsOption, RawImage2d, UncompressedFloatFormat},
+    uniform, Rect, Surface, Texture2d,
+};
+use gstreamer::prelude::*;
+
+use crate::{
+    calibration::DisplayCalibration,
+    color_space::WorkingSpace,
+    csf::Csf,
+    fft::AllocationError,
+    gstreamer::Gstreamer,
+    image_shader::ImageShader,
+    output::{Framebuffer, OutputStage},
+    precision::{read_rgba_f32, Precision},
+    processor::{AdaptParams, Processor},
+    profiler::GpuProfiler,
+    transfer::{
+        with_transfer_functions, Primaries, ProcessingDomain, TransferFunction, TransferSettings,
+    },
+    viewing::Viewing,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    H264,
+    H265,
+    Vp9,
+}
+
+impl FromStr for Codec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "h264" => Ok(Codec::H264),
+            "h265" => Ok(Codec::H265),
+            "vp9" => Ok(Codec::Vp9),
+            _ => Err(format!("Unknown codec: {}", s)),
+        }
+    }
+}
+
+impl Codec {
+    fn encoder(&self, bitrate_kbps: u32) -> String {
+        match self {
+            Codec::H264 => format!("x264enc bitrate={} ! h264parse", bitrate_kbps),
+            Codec::H265 => format!("x265enc bitrate={} ! h265parse", bitrate_kbps),
+            Codec::Vp9 => format!("vp9enc target-bitrate={}", bitrate_kbps * 1000),
+        }
+    }
+}
+
+// Muxer for the container named by the output's extension
+fn muxer(output: &std::path::Path, codec: Codec) -> Result<&'static str, TranscodeError> {
+    let extension = output.extension().and_then(|e| e.to_str()).unwrap_or("");
+    match (extension, codec) {
+        ("mp4", Codec::H264 | Codec::H265) => Ok("mp4mux"),
+        ("mov", Codec::H264 | Codec::H265) => Ok("qtmux"),
+        ("mkv", _) => Ok("matroskamux"),
+        ("webm", Codec::Vp9) => Ok("webmmux"),
+        _ => Err(TranscodeError::Unsupported(format!(
+            "{:?} in a .{} file",
+            codec, extension
+        ))),
+    }
+}
+
+/// A video file adapted frame by frame and encoded to a new file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TranscodeJob {
+    /// A path or a URI
+    pub input: String,
+    pub output: PathBuf,
+    pub viewing: Viewing,
+    pub csf: Csf,
+    pub precision: Precision,
+    pub transfer: TransferSettings,
+    pub working_space: WorkingSpace,
+    pub calibration: DisplayCalibration,
+    pub codec: Codec,
+    pub bitrate_kbps: u32,
+}
+
+impl TranscodeJob {
+    fn input_uri(&self) -> Result<String, TranscodeError> {
+        if self.input.contains("://") {
+            return Ok(self.input.clone());
+        }
+        let path = std::fs::canonicalize(&self.input)
+            .map_err(|err| TranscodeError::Pipeline(format!("{}: {}", self.input, err)))?;
+        glib::filename_to_uri(path, None)
+            .map(|uri| uri.to_string())
+            .map_err(|err| TranscodeError::Pipeline(err.to_string()))
+    }
+
+    // Compressed audio stops at the demuxer so it can be muxed again untouched
+    fn description(&self) -> Result<String, TranscodeError> {
+        Ok(format!(
+            "uridecodebin name=decode uri=\"{uri}\" caps=\"{caps}\" \
+             decode. ! videoconvert name=convert ! video/x-raw,format=RGBA64_LE ! \
+             appsink name=frames \
+             appsrc name=adapted format=time ! videoconvert ! {encoder} ! {muxer} name=mux ! \
+             filesink location=\"{output}\"",
+            uri = self.input_uri()?,
+            caps = PASSTHROUGH_CAPS,
+            encoder = self.codec.encoder(self.bitrate_kbps),
+            muxer = muxer(&self.output, self.codec)?,
+            output = self.output.display(),
+        ))
+    }
+}
+
+const PASSTHROUGH_CAPS: &str = "video/x-raw(ANY); audio/x-raw; audio/mpeg; audio/x-ac3; \
+                                audio/x-eac3; audio/x-opus; audio/x-vorbis; audio/x-flac";
+
+#[derive(Debug)]
+pub enum TranscodeError {
+    Pipeline(String),
+    Unsupported(String),
+}
+
+impl fmt::Display for TranscodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TranscodeError::Pipeline(msg) => write!(f, "Pipeline error: {}", msg),
+            TranscodeError::Unsupported(what) => write!(f, "Unsupported: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for TranscodeError {}
+
+impl From<glib::Error> for TranscodeError {
+    fn from(err: glib::Error) -> Self {
+        TranscodeError::Pipeline(err.to_string())
+    }
+}
+
+impl From<glib::BoolError> for TranscodeError {
+    fn from(err: glib::BoolError) -> Self {
+        TranscodeError::Pipeline(err.to_string())
+    }
+}
+
+impl From<gstreamer::StateChangeError> for TranscodeError {
+    fn from(err: gstreamer::StateChangeError) -> Self {
+        TranscodeError::Pipeline(err.to_string())
+    }
+}
+
+// Audio the muxer takes is passed through, anything else is dropped with a warning
+fn link_audio(pipeline: &gstreamer::Pipeline, mux: &gstreamer::Element, pad: &gstreamer::Pad) {
+    let is_audio = pad
+        .current_caps()
+        .and_then(|caps| caps.structure(0).map(|s| s.name().starts_with("audio/")))
+        .unwrap_or(false);
+    if !is_audio || pad.is_linked() {
+        return;
+    }
+    let queue = gstreamer::ElementFactory::make("queue", None).unwrap();
+    // The muxer waits for the video the encoder holds back for its lookahead, meanwhile the
+    // audio piles up here; a limit would stall the demuxer and with it the video
+    queue.set_property("max-size-time", 0u64);
+    queue.set_property("max-size-buffers", 0u32);
+    queue.set_property("max-size-bytes", 0u32);
+    pipeline.add(&queue).unwrap();
+    let queue_src = queue.static_pad("src").unwrap();
+    let target = match mux.compatible_pad(&queue_src, pad.current_caps().as_ref()) {
+        Some(mux_pad) => Some(mux_pad),
+        None => {
+            eprintln!("The container cannot hold this audio, it is dropped");
+            let sink = gstreamer::ElementFactory::make("fakesink", None).unwrap();
+            pipeline.add(&sink).unwrap();
+            sink.sync_state_with_parent().unwrap();
+            sink.static_pad("sink")
+        }
+    };
+    queue.sync_state_with_parent().unwrap();
+    pad.link(&queue.static_pad("sink").unwrap()).unwrap();
+    if let Some(target) = target {
+        queue_src.link(&target).unwrap();
+    }
+}
+
+// What the adapted frames are encoded for, so the encoder can signal it
+fn output_colorimetry(
+    transfer: &TransferSettings,
+    calibration: &DisplayCalibration,
+) -> gstreamer_video::VideoColorimetry {
+    use gstreamer_video::{
+        VideoColorMatrix, VideoColorPrimaries, VideoColorRange, VideoTransferFunction,
+    };
+
+    let function = match transfer.output {
+        // Device values for a measured display, whose curve has no name
+        _ if calibration.gamma.is_some() => VideoTransferFunction::Unknown,
+        TransferFunction::Srgb => VideoTransferFunction::Srgb,
+        TransferFunction::Bt1886 { .. } => VideoTransferFunction::Bt709,
+        TransferFunction::Gamma(gamma) if (gamma - 2.2).abs() < 0.01 => {
+            VideoTransferFunction::Gamma22
+        }
+        TransferFunction::Gamma(gamma) if (gamma - 2.8).abs() < 0.01 => {
+            VideoTransferFunction::Gamma28
+        }
+        TransferFunction::Gamma(_) => VideoTransferFunction::Unknown,
+        TransferFunction::Pq => VideoTransferFunction::Smpte2084,
+        TransferFunction::Hlg { .. } => VideoTransferFunction::AribStdB67,
+    };
+    let primaries = match transfer.output_primaries {
+        // The measured profile assumes BT.709 primaries
+        _ if calibration.gamma.is_some() => VideoColorPrimaries::Bt709,
+        Primaries::Bt709 => VideoColorPrimaries::Bt709,
+        Primaries::Bt2020 => VideoColorPrimaries::Bt2020,
+    };
+    gstreamer_video::VideoColorimetry::new(
+        VideoColorRange::Range0_255,
+        VideoColorMatrix::Rgb,
+        function,
+        primaries,
+    )
+}
+
+// How long a pull waits for a frame before the bus is checked again
+const PULL_TIMEOUT_MS: u64 = 100;
+
+/// Adapts every frame of the input without dropping any and encodes the result.
+pub fn run(
+    facade: &dyn Facade,
+    processor: &mut Processor,
+    job: &TranscodeJob,
+) -> Result<(), TranscodeError> {
+    gstreamer::init()?;
+    let pipeline = gstreamer::parse_launch(&job.description()?)?
+        .downcast::<gstreamer::Pipeline>()
+        .unwrap();
+    let by_name = |name: &str| pipeline.by_name(name).unwrap();
+    let frames = by_name("frames")
+        .dynamic_cast::<gstreamer_app::AppSink>()
+        .unwrap();
+    let adapted = by_name("adapted")
+        .dynamic_cast::<gstreamer_app::AppSrc>()
+        .unwrap();
+    // Back pressure instead of dropping, and as fast as the GPU goes instead of the clock
+    frames.set_max_buffers(4);
+    frames.set_drop(false);
+    frames.set_sync(false);
+    adapted.set_property("block", true);
+    adapted.set_max_bytes(64 << 20);
+
+    {
+        let pipeline = pipeline.clone();
+        let mux = by_name("mux");
+        by_name("decode").connect_pad_added(move |_, pad| link_audio(&pipeline, &mux, pad));
+    }
+    // Without a video stream the appsink would wait for ever
+    {
+        let convert = by_name("convert");
+        by_name("decode").connect_no_more_pads(move |decode| {
+            if !convert.static_pad("sink").unwrap().is_linked() {
+                gstreamer::element_error!(
+                    decode,
+                    gstreamer::StreamError::WrongType,
+                    ("The input has no video stream")
+                );
+            }
+        });
+    }
+
+    pipeline.set_state(gstreamer::State::Playing)?;
+    let bus = pipeline.bus().unwrap();
+    let ended = [gstreamer::MessageType::Eos, gstreamer::MessageType::Error];
+    let mut adapter = FrameAdapter::new(facade);
+    if let Some(profile) = job.calibration.measured_profile() {
+        adapter.output.set_profile(facade, profile);
+    }
+    let mut job = job.clone();
+    let mut count = 0;
+    // Errors upstream do not end the appsink, so the bus is checked between pulls
+    let message = loop {
+        if let Some(message) = bus.pop_filtered(&ended) {
+            break Some(message);
+        }
+        let sample =
+            match frames.try_pull_sample(gstreamer::ClockTime::from_mseconds(PULL_TIMEOUT_MS)) {
+                Some(sample) => sample,
+                None if frames.is_eos() => break None,
+                None => continue,
+            };
+        let info = sample
+            .caps()
+            .and_then(|caps| gstreamer_video::VideoInfo::from_caps(caps).ok())
+            .ok_or_else(|| TranscodeError::Pipeline("frame without video caps".to_string()))?;
+        if adapted.caps().is_none() {
+            if job.transfer.follow_stream {
+                Gstreamer::apply_colorimetry(&info.colorimetry(), &mut job.transfer);
+            }
+            let caps = gstreamer_video::VideoInfo::builder(
+                gstreamer_video::VideoFormat::Rgba64Le,
+                info.width(),
+                info.height(),
+            )
+            .fps(info.fps())
+            .par(info.par())
+            .colorimetry(&output_colorimetry(&job.transfer, &job.calibration))
+            .build()?
+            .to_caps()?;
+            adapted.set_caps(Some(&caps));
+        }
+
+        let input = sample.buffer().unwrap();
+        let map = input.map_readable()?;
+        let data = adapter.process(facade, processor, &job, &info, map.as_slice())?;
+        let mut output = gstreamer::Buffer::from_mut_slice(data);
+        {
+            let output = output.get_mut().unwrap();
+            output.set_pts(input.pts());
+            output.set_duration(input.duration());
+        }
+        if adapted.push_buffer(output).is_err() {
+            break None;
+        }
+        count += 1;
+        if count % 100 == 0 {
+            eprintln!("Frame {}", count);
+        }
+    };
+    let message = message.or_else(|| {
+        let _ = adapted.end_of_stream();
+        bus.timed_pop_filtered(gstreamer::ClockTime::NONE, &ended)
+    });
+
+    let result = match message.as_ref().map(|msg| msg.view()) {
+        Some(gstreamer::MessageView::Error(err)) => {
+            Err(TranscodeError::Pipeline(err.error().to_string()))
+        }
+        _ => Ok(()),
+    };
+    pipeline.set_state(gstreamer::State::Null)?;
+    eprintln!("{} frames", count);
+    result
+}
+
+// GPU side of the transcoder: decode, adapt and encode for the output, then read back
+struct FrameAdapter {
+    decode: ImageShader,
+    output: OutputStage,
+    profiler: GpuProfiler,
+    textures: Option<(Texture2d, Texture2d, Texture2d)>,
+}
+
+impl FrameAdapter {
+    fn new(facade: &dyn Facade) -> Self {
+        Self {
+            decode: ImageShader::new(
+                facade,
+                &with_transfer_functions(include_str!("gstreamer/copy_frag.glsl")),
+            ),
+            // Encoders take 8 bits, which is what the output is dithered for
+            output: OutputStage::new(facade, Framebuffer::Srgb),
+            profiler: GpuProfiler::disabled(),
+            textures: None,
+        }
+    }
+
+    // Input, linear and output textures of the frame size
+    fn textures(
+        &mut self,
+        facade: &dyn Facade,
+        width: u32,
+        height: u32,
+    ) -> Result<(), AllocationError> {
+        if let Some((input, _, _)) = self.textures.as_ref() {
+            if input.dimensions() == (width, height) {
+                return Ok(());
+            }
+        }
+        let texture = |format| {
+            Texture2d::empty_with_format(facade, format, MipmapsOption::NoMipmap, width, height)
+        };
+        self.textures = Some((
+            texture(UncompressedFloatFormat::U16U16U16U16)?,
+            texture(UncompressedFloatFormat::F32F32F32F32)?,
+            texture(UncompressedFloatFormat::F32F32F32F32)?,
+        ));
+        Ok(())
+    }
+
+    // Rows are uploaded and read back in the same order, so the frame is never flipped
+    fn process(
+        &mut self,
+        facade: &dyn Facade,
+        processor: &mut Processor,
+        job: &TranscodeJob,
+        info: &gstreamer_video::VideoInfo,
+        frame: &[u8],
+    ) -> Result<Vec<u8>, TranscodeError> {
+        let (width, height) = (info.width(), info.height());
+        let too_large =
+            |err| TranscodeError::Unsupported(format!("{}×{} frames: {}", width, height, err));
+        self.textures(facade, width, height).map_err(too_large)?;
+        let (input, linear, output) = self.textures.as_ref().unwrap();
+
+        let stride = info.stride()[0] as usize;
+        let pixels: Vec<u16> = (0..height as usize)
+            .flat_map(|y| {
+                frame[y * stride..y * stride + width as usize * 8]
+                    .chunks_exact(2)
+                    .map(|s| u16::from_le_bytes([s[0], s[1]]))
+            })
+            .collect();
+        input.write(
+            Rect {
+                left: 0,
+                bottom: 0,
+                width,
+                height,
+            },
+            RawImage2d::from_raw_rgba(pixels, (width, height)),
+        );
+
+        let transfer = &job.transfer;
+        self.decode.draw(
+            &mut linear.as_surface(),
+            &uniform! {
+                tex: input,
+                transfer: transfer.input.shader_id(),
+                transfer_param: transfer.input.shader_param(),
+                sdr_white: transfer.sdr_white,
+                linearize: transfer.domain == ProcessingDomain::Linear,
+            },
+        );
+
+        // The width in square pixels, as anamorphic video is shown
+        let par = info.par();
+        let shown_width = match (*par.numer(), *par.denom()) {
+            (numer, denom) if numer > 0 && denom > 0 => width as f32 * numer as f32 / denom as f32,
+            _ => width as f32,
+        };
+        let params = AdaptParams {
+            csf: job.csf.clone(),
+            pixels_per_visual_degree: job
+                .viewing
+                .pixels_per_vd(shown_width, job.viewing.screen_distance_mm),
+            target_pixels_per_visual_degree: job
+                .viewing
+                .pixels_per_vd(shown_width, job.viewing.target_distance_mm),
+            precision: job.precision,
+            working_space: job.working_space,
+            transfer: job.transfer,
+            calibration: job.calibration,
+        };
+        let adapted = processor
+            .process(facade, linear, &params, &mut self.profiler)
+            .map_err(too_large)?;
+        self.output
+            .draw(&mut output.as_surface(), adapted.orig(), transfer);
+
+        Ok(read_rgba_f32(output)
+            .into_iter()
+            .flatten()
+            .flat_map(|(r, g, b, a)| [r, g, b, a])
+            .flat_map(|v| ((v.clamp(0., 1.) * 65535.).round() as u16).to_le_bytes())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::HeadlessContext;
+    use std::path::Path;
+
+    #[test]
+    fn test_muxer() {
+        assert_eq!(muxer(Path::new("a.mp4"), Codec::H264).unwrap(), "mp4mux");
+        assert_eq!(
+            muxer(Path::new("a.mkv"), Codec::Vp9).unwrap(),
+            "matroskamux"
+        );
+        assert_eq!(muxer(Path::new("a.webm"), Codec::Vp9).unwrap(), "webmmux");
+        assert!(muxer(Path::new("a.webm"), Codec::H264).is_err());
+        assert!(muxer(Path::new("a"), Codec::H264).is_err());
+    }
+
+    #[test]
+    fn test_output_colorimetry() {
+        use gstreamer_video::{VideoColorPrimaries, VideoTransferFunction};
+
+        let calibration = DisplayCalibration::default();
+        let colorimetry = output_colorimetry(&TransferSettings::default(), &calibration);
+        assert_eq!(colorimetry.transfer(), VideoTransferFunction::Srgb);
+        assert_eq!(colorimetry.primaries(), VideoColorPrimaries::Bt709);
+        let hdr = TransferSettings {
+            output: TransferFunction::Pq,
+            output_primaries: Primaries::Bt2020,
+            ..TransferSettings::default()
+        };
+        let colorimetry = output_colorimetry(&hdr, &calibration);
+        assert_eq!(colorimetry.transfer(), VideoTransferFunction::Smpte2084);
+        assert_eq!(colorimetry.primaries(), VideoColorPrimaries::Bt2020);
+        let measured = DisplayCalibration {
+            gamma: Some([2.2, 2.3, 2.4]),
+            ..calibration
+        };
+        let colorimetry = output_colorimetry(&hdr, &measured);
+        assert_eq!(colorimetry.transfer(), VideoTransferFunction::Unknown);
+        assert_eq!(colorimetry.primaries(), VideoColorPrimaries::Bt709);
+    }
+
+    // Encodes a test pattern, transcodes it and counts the frames that come out
+    #[test]
+    #[ignore = "needs a GL driver"]
+    fn test_frame_count() {
+        const FRAMES: usize = 12;
+        gstreamer::init().unwrap();
+        let dir = std::env::temp_dir();
+        let id = std::process::id();
+        let input = dir.join(format!("csf_contrast-{}-in.mkv", id));
+        let output = dir.join(format!("csf_contrast-{}-out.mkv", id));
+
+        let encode = gstreamer::parse_launch(&format!(
+            "videotestsrc num-buffers={} ! video/x-raw,width=64,height=48,framerate=25/1 ! \
+             x264enc ! h264parse ! matroskamux ! filesink location=\"{}\"",
+            FRAMES,
+            input.display()
+        ))
+        .unwrap();
+        encode.set_state(gstreamer::State::Playing).unwrap();
+        let ended = [gstreamer::MessageType::Eos, gstreamer::MessageType::Error];
+        let message = encode
+            .bus()
+            .unwrap()
+            .timed_pop_filtered(gstreamer::ClockTime::from_seconds(30), &ended)
+            .unwrap();
+        assert!(matches!(message.view(), gstreamer::MessageView::Eos(_)));
+        encode.set_state(gstreamer::State::Null).unwrap();
+
+        let context = HeadlessContext::new();
+        let mut processor = Processor::new(&context.facade);
+        let job = TranscodeJob {
+            input: input.display().to_string(),
+            output: output.clone(),
+            viewing: Viewing::default(),
+            csf: Csf::default(),
+            precision: Precision::Full,
+            transfer: TransferSettings::default(),
+            working_space: WorkingSpace::default(),
+            calibration: DisplayCalibration::default(),
+            codec: Codec::H264,
+            bitrate_kbps: 1000,
+        };
+        run(&context.facade, &mut processor, &job).unwrap();
+
+        let decode = gstreamer::parse_launch(&format!(
+            "filesrc location=\"{}\" ! decodebin ! appsink name=frames sync=false",
+            output.display()
+        ))
+        .unwrap()
+        .downcast::<gstreamer::Pipeline>()
+        .unwrap();
+        let frames = decode
+            .by_name("frames")
+            .unwrap()
+            .dynamic_cast::<gstreamer_app::AppSink>()
+            .unwrap();
+        decode.set_state(gstreamer::State::Playing).unwrap();
+        let mut count = 0;
+        while frames.pull_sample().is_ok() {
+            count += 1;
+        }
+        decode.set_state(gstreamer::State::Null).unwrap();
+        let _ = std::fs::remove_file(&input);
+        let _ = std::fs::remove_file(&output);
+        assert_eq!(count, FRAMES);
+    }
+
+    #[test]
+    fn test_codec() {
+        assert_eq!("h265".parse(), Ok(Codec::H265));
+        assert!("av1".parse::<Codec>().is_err());
+        assert!(Codec::Vp9.encoder(2000).contains("target-bitrate=2000000"));
+    }
+}
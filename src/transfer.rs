@@ -0,0 +1,659 @@
+use std::{fmt, str::FromStr};
+
+use crate::{glsl::insert_after_version, icc::IccProfile};
+
+/// Relation between an encoded signal and linear light.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransferFunction {
+    /// Piecewise IEC 61966-2-1 curve
+    Srgb,
+    /// ITU-R BT.1886 reference display with a black level relative to white
+    Bt1886 {
+        black: f32,
+    },
+    Gamma(f32),
+    /// SMPTE ST 2084 perceptual quantizer, absolute up to 10000 cd/m²
+    Pq,
+    /// ARIB STD-B67 hybrid log-gamma shown on a display of the given peak luminance in cd/m²
+    Hlg {
+        peak: f32,
+    },
+}
+
+// ST 2084 constants
+const PQ_M1: f32 = 2610. / 16384.;
+const PQ_M2: f32 = 2523. / 4096. * 128.;
+const PQ_C1: f32 = 3424. / 4096.;
+const PQ_C2: f32 = 2413. / 4096. * 32.;
+const PQ_C3: f32 = 2392. / 4096. * 32.;
+const PQ_PEAK: f32 = 10000.;
+
+// STD-B67 constants
+const HLG_A: f32 = 0.17883277;
+const HLG_B: f32 = 0.28466892;
+const HLG_C: f32 = 0.55991073;
+
+impl TransferFunction {
+    pub const NAMES: [&'static str; 5] = ["sRGB", "BT.1886", "Gamma", "PQ", "HLG"];
+
+    pub fn name(&self) -> &'static str {
+        Self::NAMES[self.shader_id() as usize]
+    }
+
+    pub fn is_hdr(&self) -> bool {
+        matches!(self, TransferFunction::Pq | TransferFunction::Hlg { .. })
+    }
+
+    /// Luminance in cd/m² of a full signal. SDR functions reach the chosen SDR white.
+    pub fn peak_luminance(&self, sdr_white: f32) -> f32 {
+        match *self {
+            TransferFunction::Pq => PQ_PEAK,
+            TransferFunction::Hlg { peak } => peak,
+            _ => sdr_white,
+        }
+    }
+
+    // BT.1886 constants for a white luminance of 1
+    fn bt1886(black: f32) -> (f32, f32) {
+        let root = black.max(0.).powf(1. / 2.4);
+        ((1. - root).powf(2.4), root / (1. - root))
+    }
+
+    // BT.2100 system gamma of the HLG OOTF
+    fn hlg_gamma(peak: f32) -> f32 {
+        1.2 + 0.42 * (peak / 1000.).log10()
+    }
+
+    /// Encoded signal to linear light relative to the peak. For HLG this is scene light, the
+    /// display OOTF is only applied by [`TransferFunction::eotf_rgb`].
+    pub fn eotf(&self, v: f32) -> f32 {
+        let v = v.max(0.);
+        match *self {
+            TransferFunction::Srgb => {
+                if v <= 0.04045 {
+                    v / 12.92
+                } else {
+                    ((v + 0.055) / 1.055).powf(2.4)
+                }
+            }
+            TransferFunction::Bt1886 { black } => {
+                let (a, b) = Self::bt1886(black);
+                a * (v + b).powf(2.4)
+            }
+            TransferFunction::Gamma(gamma) => v.powf(gamma),
+            TransferFunction::Pq => {
+                let p = v.powf(1. / PQ_M2);
+                ((p - PQ_C1).max(0.) / (PQ_C2 - PQ_C3 * p)).powf(1. / PQ_M1)
+            }
+            TransferFunction::Hlg { .. } => {
+                if v <= 0.5 {
+                    v * v / 3.
+                } else {
+                    (((v - HLG_C) / HLG_A).exp() + HLG_B) / 12.
+                }
+            }
+        }
+    }
+
+    /// Linear light relative to the peak to encoded signal.
+    pub fn inverse_eotf(&self, l: f32) -> f32 {
+        let l = l.max(0.);
+        match *self {
+            TransferFunction::Srgb => {
+                if l <= 0.0031308 {
+                    l * 12.92
+                } else {
+                    1.055 * l.powf(1. / 2.4) - 0.055
+                }
+            }
+            TransferFunction::Bt1886 { black } => {
+                let (a, b) = Self::bt1886(black);
+                ((l / a).powf(1. / 2.4) - b).max(0.)
+            }
+            TransferFunction::Gamma(gamma) => l.powf(1. / gamma),
+            TransferFunction::Pq => {
+                let y = l.powf(PQ_M1);
+                ((PQ_C1 + PQ_C2 * y) / (1. + PQ_C3 * y)).powf(PQ_M2)
+            }
+            TransferFunction::Hlg { .. } => {
+                if l <= 1. / 12. {
+                    (3. * l).sqrt()
+                } else {
+                    HLG_A * (12. * l - HLG_B).ln() + HLG_C
+                }
+            }
+        }
+    }
+
+    /// Encoded RGB to absolute luminance in cd/m².
+    pub fn eotf_rgb(&self, rgb: [f32; 3], sdr_white: f32) -> [f32; 3] {
+        let linear = rgb.map(|c| self.eotf(c));
+        let scale = match *self {
+            TransferFunction::Hlg { peak } => {
+                let ys = luminance(linear, Primaries::Bt2020);
+                peak * ys.max(0.).powf(Self::hlg_gamma(peak) - 1.)
+            }
+            _ => self.peak_luminance(sdr_white),
+        };
+        linear.map(|c| c * scale)
+    }
+
+    /// Absolute luminance in cd/m² to encoded RGB.
+    pub fn inverse_eotf_rgb(&self, rgb: [f32; 3], sdr_white: f32) -> [f32; 3] {
+        let scale = match *self {
+            TransferFunction::Hlg { peak } => {
+                let yd = luminance(rgb, Primaries::Bt2020) / peak;
+                if yd <= 0. {
+                    return [0.; 3];
+                }
+                let gamma = Self::hlg_gamma(peak);
+                peak * yd.powf((gamma - 1.) / gamma)
+            }
+            _ => self.peak_luminance(sdr_white),
+        };
+        rgb.map(|c| self.inverse_eotf(c / scale))
+    }
+
+    // Matches the TF_* defines of transfer.glsl
+    pub fn shader_id(&self) -> i32 {
+        match self {
+            TransferFunction::Srgb => 0,
+            TransferFunction::Bt1886 { .. } => 1,
+            TransferFunction::Gamma(_) => 2,
+            TransferFunction::Pq => 3,
+            TransferFunction::Hlg { .. } => 4,
+        }
+    }
+
+    pub fn shader_param(&self) -> f32 {
+        match *self {
+            TransferFunction::Srgb | TransferFunction::Pq => 0.,
+            TransferFunction::Bt1886 { black } => black,
+            TransferFunction::Gamma(gamma) => gamma,
+            TransferFunction::Hlg { peak } => peak,
+        }
+    }
+
+    pub fn draw_ui(&mut self, ui: &mut egui::Ui, id_source: &str) {
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source(id_source)
+                .selected_text(self.name())
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(self, TransferFunction::Srgb, "sRGB");
+                    ui.selectable_value(self, TransferFunction::Bt1886 { black: 0. }, "BT.1886");
+                    ui.selectable_value(self, TransferFunction::Gamma(2.2), "Gamma");
+                    ui.selectable_value(self, TransferFunction::Pq, "PQ");
+                    ui.selectable_value(self, TransferFunction::Hlg { peak: 1000. }, "HLG");
+                });
+            match self {
+                TransferFunction::Srgb | TransferFunction::Pq => (),
+                TransferFunction::Bt1886 { black } => {
+                    ui.label("Black");
+                    ui.add(
+                        egui::DragValue::new(black)
+                            .speed(0.0001)
+                            .clamp_range(0.0..=0.5),
+                    );
+                }
+                TransferFunction::Gamma(gamma) => {
+                    ui.label("γ");
+                    ui.add(
+                        egui::DragValue::new(gamma)
+                            .speed(0.01)
+                            .clamp_range(0.1..=5.),
+                    );
+                }
+                TransferFunction::Hlg { peak } => {
+                    ui.label("Peak");
+                    ui.add(
+                        egui::DragValue::new(peak)
+                            .speed(10)
+                            .clamp_range(100.0..=PQ_PEAK),
+                    );
+                    ui.label("cd/m²");
+                }
+            }
+        });
+    }
+}
+
+impl fmt::Display for TransferFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransferFunction::Srgb => write!(f, "srgb"),
+            TransferFunction::Bt1886 { black } => write!(f, "bt1886:{}", black),
+            TransferFunction::Gamma(gamma) => write!(f, "gamma:{}", gamma),
+            TransferFunction::Pq => write!(f, "pq"),
+            TransferFunction::Hlg { peak } => write!(f, "hlg:{}", peak),
+        }
+    }
+}
+
+impl FromStr for TransferFunction {
+    type Err = String;
+
+    /// Parses `srgb`, `bt1886[:BLACK]`, `gamma:GAMMA`, `pq` or `hlg[:PEAK]`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, param) = match s.split_once(':') {
+            Some((name, param)) => (
+                name,
+                Some(
+                    param
+                        .parse::<f32>()
+                        .map_err(|_| format!("Invalid transfer function parameter: {}", param))?,
+                ),
+            ),
+            None => (s, None),
+        };
+        match (name, param) {
+            ("srgb", None) => Ok(TransferFunction::Srgb),
+            ("bt1886", black) => Ok(TransferFunction::Bt1886 {
+                black: black.unwrap_or(0.),
+            }),
+            ("gamma", Some(gamma)) => Ok(TransferFunction::Gamma(gamma)),
+            ("pq", None) => Ok(TransferFunction::Pq),
+            ("hlg", peak) => Ok(TransferFunction::Hlg {
+                peak: peak.unwrap_or(1000.),
+            }),
+            _ => Err(format!("Unknown transfer function: {}", s)),
+        }
+    }
+}
+
+/// RGB primaries and white point of a signal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Primaries {
+    Bt709,
+    Bt2020,
+}
+
+impl Primaries {
+    pub fn luma_coefficients(&self) -> [f32; 3] {
+        match self {
+            Primaries::Bt709 => [0.2126, 0.7152, 0.0722],
+            Primaries::Bt2020 => [0.2627, 0.6780, 0.0593],
+        }
+    }
+
+    /// Row-major matrix from linear RGB to CIE XYZ, with a D65 white of Y = 1.
+    pub fn to_xyz(&self) -> [[f32; 3]; 3] {
+        match self {
+            Primaries::Bt709 => [
+                [0.4124, 0.3576, 0.1805],
+                [0.2126, 0.7152, 0.0722],
+                [0.0193, 0.1192, 0.9505],
+            ],
+            Primaries::Bt2020 => [
+                [0.6370, 0.1446, 0.1689],
+                [0.2627, 0.6780, 0.0593],
+                [0.0, 0.0281, 1.0610],
+            ],
+        }
+    }
+
+    /// Row-major matrix from linear RGB in `self` to linear RGB in `to` (ITU-R BT.2087).
+    pub fn conversion(&self, to: Primaries) -> [[f32; 3]; 3] {
+        match (self, to) {
+            (Primaries::Bt709, Primaries::Bt2020) => [
+                [0.6274, 0.3293, 0.0433],
+                [0.0691, 0.9195, 0.0114],
+                [0.0164, 0.0880, 0.8956],
+            ],
+            (Primaries::Bt2020, Primaries::Bt709) => [
+                [1.6605, -0.5876, -0.0728],
+                [-0.1246, 1.1329, -0.0083],
+                [-0.0182, -0.1006, 1.1187],
+            ],
+            _ => [[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]],
+        }
+    }
+
+    pub fn draw_ui(&mut self, ui: &mut egui::Ui) {
+        ui.radio_value(self, Primaries::Bt709, "BT.709");
+        ui.radio_value(self, Primaries::Bt2020, "BT.2020");
+    }
+}
+
+impl FromStr for Primaries {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bt709" => Ok(Primaries::Bt709),
+            "bt2020" => Ok(Primaries::Bt2020),
+            _ => Err(format!("Unknown primaries: {}", s)),
+        }
+    }
+}
+
+fn luminance(rgb: [f32; 3], primaries: Primaries) -> f32 {
+    let k = primaries.luma_coefficients();
+    k[0] * rgb[0] + k[1] * rgb[1] + k[2] * rgb[2]
+}
+
+fn mul(m: &[[f32; 3]; 3], v: [f32; 3]) -> [f32; 3] {
+    m.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+/// How luminance above the peak of the output is brought into range.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ToneMapping {
+    Clip,
+    /// Extended Reinhard on luminance, mapping the input peak to the output peak
+    Reinhard,
+}
+
+impl ToneMapping {
+    // Matches the TONE_MAP_* defines of transfer.glsl
+    pub fn shader_id(&self) -> i32 {
+        match self {
+            ToneMapping::Clip => 0,
+            ToneMapping::Reinhard => 1,
+        }
+    }
+
+    /// Maps linear RGB relative to the output peak, with `peak` the input peak on the same scale.
+    pub fn apply(&self, rgb: [f32; 3], peak: f32, primaries: Primaries) -> [f32; 3] {
+        let l = luminance(rgb, primaries);
+        match self {
+            ToneMapping::Reinhard if peak > 1. && l > 0. => {
+                let mapped = l * (1. + l / (peak * peak)) / (1. + l);
+                rgb.map(|c| c * mapped / l)
+            }
+            _ => rgb,
+        }
+    }
+}
+
+impl FromStr for ToneMapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clip" => Ok(ToneMapping::Clip),
+            "reinhard" => Ok(ToneMapping::Reinhard),
+            _ => Err(format!("Unknown tone mapping: {}", s)),
+        }
+    }
+}
+
+/// Whether the adaptation works on linear luminance or on the encoded signal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProcessingDomain {
+    Linear,
+    Encoded,
+}
+
+impl FromStr for ProcessingDomain {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(ProcessingDomain::Linear),
+            "encoded" => Ok(ProcessingDomain::Encoded),
+            _ => Err(format!("Unknown processing domain: {}", s)),
+        }
+    }
+}
+
+/// Transfer functions of the source and of the display, and the domain the adaptation runs in.
+///
+/// The input stage turns the source signal into the working signal, the output stage turns the
+/// working signal into the display signal. In the linear domain the working signal is absolute
+/// luminance in cd/m², in the encoded domain it is the source signal itself.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TransferSettings {
+    pub input: TransferFunction,
+    pub output: TransferFunction,
+    pub domain: ProcessingDomain,
+    pub input_primaries: Primaries,
+    pub output_primaries: Primaries,
+    /// Luminance in cd/m² of SDR white, on both sides
+    pub sdr_white: f32,
+    pub tone_mapping: ToneMapping,
+    /// Replace the input settings with those signalled by the stream
+    pub follow_stream: bool,
+}
+
+impl Default for TransferSettings {
+    fn default() -> Self {
+        Self {
+            input: TransferFunction::Srgb,
+            output: TransferFunction::Srgb,
+            domain: ProcessingDomain::Linear,
+            input_primaries: Primaries::Bt709,
+            output_primaries: Primaries::Bt709,
+            sdr_white: 100.,
+            tone_mapping: ToneMapping::Reinhard,
+            follow_stream: true,
+        }
+    }
+}
+
+impl TransferSettings {
+    pub fn to_working(&self, rgb: [f32; 3]) -> [f32; 3] {
+        match self.domain {
+            ProcessingDomain::Linear => self.input.eotf_rgb(rgb, self.sdr_white),
+            ProcessingDomain::Encoded => rgb,
+        }
+    }
+
+    /// Working signal to output signal. Without `extended` the result is limited to the output
+    /// peak, tone mapped if the input can exceed it.
+    pub fn to_output(&self, rgb: [f32; 3], extended: bool) -> [f32; 3] {
+        let linear = match self.domain {
+            ProcessingDomain::Linear => rgb,
+            ProcessingDomain::Encoded => self.input.eotf_rgb(rgb, self.sdr_white),
+        };
+        let linear = mul(
+            &self.input_primaries.conversion(self.output_primaries),
+            linear,
+        );
+        let output_peak = self.output.peak_luminance(self.sdr_white);
+        let relative = linear.map(|c| c / output_peak);
+        let relative = if extended {
+            relative
+        } else {
+            self.tone_mapping
+                .apply(
+                    relative,
+                    self.content_peak() / output_peak,
+                    self.output_primaries,
+                )
+                .map(|c| c.clamp(0., 1.))
+        };
+        self.output
+            .inverse_eotf_rgb(relative.map(|c| c * output_peak), self.sdr_white)
+    }
+
+    /// Working signal to the device values of a display profile, as the output stage encodes
+    /// them. Without a profile this is the output signal.
+    pub fn to_device(&self, rgb: [f32; 3], profile: Option<&IccProfile>) -> [f32; 3] {
+        let profile = match profile {
+            Some(profile) => profile,
+            None => return self.to_output(rgb, false),
+        };
+        let linear = match self.domain {
+            ProcessingDomain::Linear => rgb,
+            ProcessingDomain::Encoded => self.input.eotf_rgb(rgb, self.sdr_white),
+        };
+        // The profile takes over from the output primaries, so tone mapping stays in the input's
+        let peak = profile.white_luminance().unwrap_or(self.sdr_white);
+        let relative = self
+            .tone_mapping
+            .apply(
+                linear.map(|c| c / peak),
+                self.content_peak() / peak,
+                self.input_primaries,
+            )
+            .map(|c| c.clamp(0., 1.));
+        let device = mul(&profile.output_matrix(self.input_primaries), relative);
+        [0, 1, 2].map(|i| profile.trc[i].inverse(device[i].clamp(0., 1.)))
+    }
+
+    /// Highest luminance in cd/m² the input can reach.
+    pub fn content_peak(&self) -> f32 {
+        self.input.peak_luminance(self.sdr_white)
+    }
+
+    pub fn draw_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Source:");
+            self.input.draw_ui(ui, "input_transfer");
+        });
+        ui.horizontal(|ui| {
+            ui.label("Source primaries:");
+            self.input_primaries.draw_ui(ui);
+        });
+        ui.checkbox(&mut self.follow_stream, "Use the stream's colorimetry");
+        ui.horizontal(|ui| {
+            ui.label("Display:");
+            self.output.draw_ui(ui, "output_transfer");
+        });
+        ui.horizontal(|ui| {
+            ui.label("Display primaries:");
+            self.output_primaries.draw_ui(ui);
+        });
+        ui.horizontal(|ui| {
+            ui.label("SDR white:");
+            ui.add(
+                egui::DragValue::new(&mut self.sdr_white)
+                    .speed(1)
+                    .clamp_range(1.0..=1000.),
+            );
+            ui.label("cd/m²");
+        });
+        if self.input.is_hdr() && !self.output.is_hdr() {
+            ui.horizontal(|ui| {
+                ui.label("Tone mapping:");
+                ui.radio_value(&mut self.tone_mapping, ToneMapping::Clip, "clip");
+                ui.radio_value(&mut self.tone_mapping, ToneMapping::Reinhard, "Reinhard");
+            });
+        }
+        ui.horizontal(|ui| {
+            ui.label("Adapt on:");
+            ui.radio_value(
+                &mut self.domain,
+                ProcessingDomain::Linear,
+                "absolute luminance",
+            );
+            ui.radio_value(
+                &mut self.domain,
+                ProcessingDomain::Encoded,
+                "encoded signal",
+            );
+        });
+    }
+}
+
+/// Prepends the transfer functions and tone mapping of transfer.glsl to a shader.
+pub fn with_transfer_functions(src: &str) -> String {
+    insert_after_version(src, include_str!("transfer/transfer.glsl"))
+}
+
+/// Column-major form of a row-major matrix, as GLSL `mat3` uniforms expect.
+pub fn glsl_mat3(m: [[f32; 3]; 3]) -> [[f32; 3]; 3] {
+    [
+        [m[0][0], m[1][0], m[2][0]],
+        [m[0][1], m[1][1], m[2][1]],
+        [m[0][2], m[1][2], m[2][2]],
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for tf in [
+            TransferFunction::Srgb,
+            TransferFunction::Bt1886 { black: 0.001 },
+            TransferFunction::Gamma(2.2),
+            TransferFunction::Pq,
+            TransferFunction::Hlg { peak: 1000. },
+        ] {
+            for i in 0..=100 {
+                let v = i as f32 / 100.;
+                assert!(
+                    (tf.inverse_eotf(tf.eotf(v)) - v).abs() < 1e-4,
+                    "{:?} {}",
+                    tf,
+                    v
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_bt1886_range() {
+        let tf = TransferFunction::Bt1886 { black: 0.01 };
+        assert!((tf.eotf(0.) - 0.01).abs() < 1e-6);
+        assert!((tf.eotf(1.) - 1.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_absolute_luminance() {
+        // PQ code value 0.508 is close to 100 cd/m²
+        let pq = TransferFunction::Pq.eotf_rgb([0.508; 3], 100.);
+        assert!((pq[0] - 100.).abs() < 1.);
+        // HLG white reaches the display peak
+        let hlg = TransferFunction::Hlg { peak: 1000. };
+        assert!((hlg.eotf_rgb([1.; 3], 100.)[1] - 1000.).abs() < 1e-1);
+        let rgb = [0.2, 0.5, 0.7];
+        let back = hlg.inverse_eotf_rgb(hlg.eotf_rgb(rgb, 100.), 100.);
+        for (a, b) in rgb.iter().zip(back.iter()) {
+            assert!((a - b).abs() < 1e-4);
+        }
+        assert!((TransferFunction::Srgb.eotf_rgb([1.; 3], 203.)[2] - 203.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_to_device() {
+        let settings = TransferSettings::default();
+        let grey = [18.; 3];
+        assert_eq!(
+            settings.to_device(grey, None),
+            settings.to_output(grey, false)
+        );
+        // A measured gamma of 2.2 at the calibrated peak of SDR white
+        let profile = crate::calibration::DisplayCalibration {
+            gamma: Some([2.2; 3]),
+            ..Default::default()
+        }
+        .measured_profile()
+        .unwrap();
+        let device = settings.to_device(grey, Some(&profile));
+        for c in device {
+            assert!((c - 0.18f32.powf(1. / 2.2)).abs() < 1e-2, "{:?}", device);
+        }
+    }
+
+    #[test]
+    fn test_tone_mapping() {
+        let settings = TransferSettings {
+            input: TransferFunction::Pq,
+            ..Default::default()
+        };
+        // The PQ peak lands on SDR white, and SDR white is compressed below it
+        assert!((settings.to_output([10000.; 3], false)[0] - 1.).abs() < 1e-4);
+        assert!(settings.to_output([100.; 3], false)[0] < 0.9);
+        assert!(settings.to_output([500.; 3], true)[0] > 1.);
+        let clipped = TransferSettings {
+            tone_mapping: ToneMapping::Clip,
+            ..settings
+        };
+        assert!((clipped.to_output([500.; 3], false)[0] - 1.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("srgb".parse(), Ok(TransferFunction::Srgb));
+        assert_eq!("bt1886".parse(), Ok(TransferFunction::Bt1886 { black: 0. }));
+        assert_eq!("gamma:2.4".parse(), Ok(TransferFunction::Gamma(2.4)));
+        assert_eq!("pq".parse(), Ok(TransferFunction::Pq));
+        assert_eq!("hlg".parse(), Ok(TransferFunction::Hlg { peak: 1000. }));
+        assert!("gamma".parse::<TransferFunction>().is_err());
+        assert!("log".parse::<TransferFunction>().is_err());
+    }
+}
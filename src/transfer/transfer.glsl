@@ -0,0 +1,112 @@
+// Transfer functions, selected by the ids of TransferFunction::shader_id
+// Inserted after #version, so it sets the default precision itself
+precision highp float;
+
+#define TF_SRGB 0
+#define TF_BT1886 1
+#define TF_GAMMA 2
+#define TF_PQ 3
+#define TF_HLG 4
+
+#define TONE_MAP_CLIP 0
+#define TONE_MAP_REINHARD 1
+
+const float PQ_M1 = 2610.0 / 16384.0;
+const float PQ_M2 = 2523.0 / 4096.0 * 128.0;
+const float PQ_C1 = 3424.0 / 4096.0;
+const float PQ_C2 = 2413.0 / 4096.0 * 32.0;
+const float PQ_C3 = 2392.0 / 4096.0 * 32.0;
+const float PQ_PEAK = 10000.0;
+
+const float HLG_A = 0.17883277;
+const float HLG_B = 0.28466892;
+const float HLG_C = 0.55991073;
+
+const vec3 BT2020_LUMA = vec3(0.2627, 0.6780, 0.0593);
+
+// Encoded signal to linear light relative to the peak
+float eotf(float v, int tf, float param) {
+  v = max(v, 0.0);
+  if (tf == TF_SRGB) {
+    return v <= 0.04045 ? v / 12.92 : pow((v + 0.055) / 1.055, 2.4);
+  } else if (tf == TF_BT1886) {
+    // param is the black level relative to white
+    float root = pow(max(param, 0.0), 1.0 / 2.4);
+    return pow(1.0 - root, 2.4) * pow(v + root / (1.0 - root), 2.4);
+  } else if (tf == TF_GAMMA) {
+    return pow(v, param);
+  } else if (tf == TF_PQ) {
+    float p = pow(v, 1.0 / PQ_M2);
+    return pow(max(p - PQ_C1, 0.0) / (PQ_C2 - PQ_C3 * p), 1.0 / PQ_M1);
+  } else {
+    // HLG scene light
+    return v <= 0.5 ? v * v / 3.0 : (exp((v - HLG_C) / HLG_A) + HLG_B) / 12.0;
+  }
+}
+
+float inverse_eotf(float l, int tf, float param) {
+  l = max(l, 0.0);
+  if (tf == TF_SRGB) {
+    return l <= 0.0031308 ? l * 12.92 : 1.055 * pow(l, 1.0 / 2.4) - 0.055;
+  } else if (tf == TF_BT1886) {
+    float root = pow(max(param, 0.0), 1.0 / 2.4);
+    return max(pow(l / pow(1.0 - root, 2.4), 1.0 / 2.4) - root / (1.0 - root), 0.0);
+  } else if (tf == TF_GAMMA) {
+    return pow(l, 1.0 / param);
+  } else if (tf == TF_PQ) {
+    float y = pow(l, PQ_M1);
+    return pow((PQ_C1 + PQ_C2 * y) / (1.0 + PQ_C3 * y), PQ_M2);
+  } else {
+    return l <= 1.0 / 12.0 ? sqrt(3.0 * l) : HLG_A * log(12.0 * l - HLG_B) + HLG_C;
+  }
+}
+
+float peak_luminance(int tf, float param, float sdr_white) {
+  if (tf == TF_PQ) {
+    return PQ_PEAK;
+  } else if (tf == TF_HLG) {
+    // param is the peak of the display
+    return param;
+  }
+  return sdr_white;
+}
+
+float hlg_gamma(float peak) {
+  return 1.2 + 0.42 * log(peak / 1000.0) / log(10.0);
+}
+
+// Encoded RGB to absolute luminance in cd/m²
+vec3 eotf_rgb(vec3 v, int tf, float param, float sdr_white) {
+  vec3 linear = vec3(eotf(v.r, tf, param), eotf(v.g, tf, param), eotf(v.b, tf, param));
+  float scale = peak_luminance(tf, param, sdr_white);
+  if (tf == TF_HLG) {
+    scale *= pow(max(dot(linear, BT2020_LUMA), 0.0), hlg_gamma(param) - 1.0);
+  }
+  return linear * scale;
+}
+
+// Absolute luminance in cd/m² to encoded RGB
+vec3 inverse_eotf_rgb(vec3 l, int tf, float param, float sdr_white) {
+  float scale = peak_luminance(tf, param, sdr_white);
+  if (tf == TF_HLG) {
+    float yd = dot(l, BT2020_LUMA) / param;
+    if (yd <= 0.0) {
+      return vec3(0.0);
+    }
+    float gamma = hlg_gamma(param);
+    scale *= pow(yd, (gamma - 1.0) / gamma);
+  }
+  l /= scale;
+  return vec3(inverse_eotf(l.r, tf, param), inverse_eotf(l.g, tf, param),
+              inverse_eotf(l.b, tf, param));
+}
+
+// Linear RGB relative to the output peak, with peak the input peak on the same scale
+vec3 tone_map(vec3 rgb, int mode, float peak, vec3 luma) {
+  float l = dot(rgb, luma);
+  if (mode == TONE_MAP_REINHARD && peak > 1.0 && l > 0.0) {
+    float mapped = l * (1.0 + l / (peak * peak)) / (1.0 + l);
+    return rgb * (mapped / l);
+  }
+  return rgb;
+}
@@ -0,0 +1,116 @@
+use cgmath::Vector2;
+
+/// Physical viewing geometry: the screen the content fills and the distances it is seen from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Viewing {
+    pub screen_dims_mm: Vector2<f32>,
+    pub screen_distance_mm: f32,
+    pub target_distance_mm: f32,
+}
+
+impl Default for Viewing {
+    fn default() -> Self {
+        Self {
+            screen_dims_mm: (600.0f32, 336.0f32).into(),
+            screen_distance_mm: 750.,
+            target_distance_mm: 900.,
+        }
+    }
+}
+
+impl Viewing {
+    pub const PRESETS: [&'static str; 7] = [
+        "desktop",
+        "laptop",
+        "phone",
+        "tv",
+        "signage",
+        "print-a4",
+        "poster-a1",
+    ];
+
+    /// Screen size and distance of a typical setup, adapted for that same distance.
+    pub fn preset(name: &str) -> Option<Self> {
+        let (width, height, distance) = match name {
+            "desktop" => (600., 336., 750.),
+            "laptop" => (345., 194., 500.),
+            "phone" => (70., 150., 300.),
+            "tv" => (1210., 680., 2500.),
+            "signage" => (1900., 1070., 4000.),
+            "print-a4" => (297., 210., 400.),
+            "poster-a1" => (841., 594., 1500.),
+            _ => return None,
+        };
+        Some(Self {
+            screen_dims_mm: (width, height).into(),
+            screen_distance_mm: distance,
+            target_distance_mm: distance,
+        })
+    }
+
+    pub fn pixels_per_vd(&self, pixels: f32, distance_mm: f32) -> f32 {
+        self.content_pixels_per_vd(pixels, 1., distance_mm)
+    }
+
+    /// Pixels per degree of content `pixels` wide that is shown across `fraction` of the
+    /// screen width, as when it is letterboxed or pillarboxed.
+    pub fn content_pixels_per_vd(&self, pixels: f32, fraction: f32, distance_mm: f32) -> f32 {
+        Self::pixels_per_vd_over(pixels, self.screen_dims_mm.x * fraction, distance_mm)
+    }
+
+    /// `content_pixels_per_vd` along the height of the screen, for content `pixels` high.
+    pub fn content_pixels_per_vd_vertical(
+        &self,
+        pixels: f32,
+        fraction: f32,
+        distance_mm: f32,
+    ) -> f32 {
+        Self::pixels_per_vd_over(pixels, self.screen_dims_mm.y * fraction, distance_mm)
+    }
+
+    fn pixels_per_vd_over(pixels: f32, length_mm: f32, distance_mm: f32) -> f32 {
+        pixels / (2. * (length_mm / distance_mm).atan()).to_degrees()
+    }
+
+    pub fn total_visual_angle(&self, distance: f32) -> f32 {
+        2. * (self.screen_dims_mm.x / distance).atan()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presets() {
+        for name in Viewing::PRESETS {
+            let viewing = Viewing::preset(name).unwrap();
+            assert_eq!(viewing.screen_distance_mm, viewing.target_distance_mm);
+        }
+        assert_eq!(
+            Viewing::preset("desktop").unwrap().screen_dims_mm,
+            Viewing::default().screen_dims_mm
+        );
+        assert_eq!(Viewing::preset("cinema"), None);
+    }
+
+    #[test]
+    fn test_content_pixels_per_vd() {
+        let viewing = Viewing::default();
+        let distance = viewing.screen_distance_mm;
+        assert_eq!(
+            viewing.content_pixels_per_vd(1920., 1., distance),
+            viewing.pixels_per_vd(1920., distance)
+        );
+        // Square pixels on a screen of the same aspect ratio, from far enough for the angles
+        // to be nearly proportional to the lengths
+        let vertical = viewing.content_pixels_per_vd_vertical(1080., 1., 3000.);
+        let horizontal = viewing.content_pixels_per_vd(1920., 1., 3000.);
+        assert!((vertical - horizontal).abs() / horizontal < 0.01);
+        // Pillarboxed content has fewer degrees for the same pixels
+        assert!(
+            viewing.content_pixels_per_vd(1080., 0.5, distance)
+                > viewing.pixels_per_vd(1080., distance)
+        );
+    }
+}